@@ -1,4 +1,4 @@
-use kvs::{kvs_engine::*, thread_pool::*, Command, KvsClient, KvsServer, Response};
+use kvs::{kvs_engine::*, thread_pool::*, Codec, Command, KvsClient, KvsServer, Response};
 
 use criterion::{criterion_group, criterion_main, BatchSize, Bencher, BenchmarkId, Criterion};
 use sloggers::{null::NullLoggerBuilder, Build};
@@ -44,6 +44,16 @@ pub fn criterion_write(c: &mut Criterion) {
             input,
             write_function::<SledKvsEngine, RayonThreadPool>,
         );
+        group.bench_with_input(
+            BenchmarkId::new("write_rayon_lsmkvsengine", input),
+            input,
+            write_function::<LsmKvsEngine, RayonThreadPool>,
+        );
+        group.bench_with_input(
+            BenchmarkId::new("write_rayon_shardedkvstore", input),
+            input,
+            write_function::<ShardedKvStore, RayonThreadPool>,
+        );
     }
 }
 
@@ -73,17 +83,36 @@ pub fn criterion_read(c: &mut Criterion) {
             input,
             read_function::<SledKvsEngine, RayonThreadPool>,
         );
+        group.bench_with_input(
+            BenchmarkId::new("read_rayon_lsmkvsengine", input),
+            input,
+            read_function::<LsmKvsEngine, RayonThreadPool>,
+        );
+        group.bench_with_input(
+            BenchmarkId::new("read_rayon_shardedkvstore", input),
+            input,
+            read_function::<ShardedKvStore, RayonThreadPool>,
+        );
     }
 }
 
-criterion_group!(benches, criterion_write, criterion_read);
+// benchmarks encoding and decoding NTASK commands and responses in the text and binary codecs
+pub fn criterion_codec(c: &mut Criterion) {
+    let mut group = c.benchmark_group("codec");
+    for (name, codec) in [("text", Codec::Text), ("binary", Codec::Binary)] {
+        group.bench_with_input(BenchmarkId::new("encode", name), &codec, encode_function);
+        group.bench_with_input(BenchmarkId::new("decode", name), &codec, decode_function);
+    }
+}
+
+criterion_group!(benches, criterion_write, criterion_read, criterion_codec);
 criterion_main!(benches);
 
 // the actual benchmark function for write-operations
 fn write_function<E, T>(b: &mut Bencher, &threads: &usize)
 where
     E: KvsEngine,
-    T: ThreadPool + Send + 'static,
+    T: ThreadPool + Send + Sync + 'static,
 {
     // key-values are [(00000000, value), ..., (00000999, value)]
     let keys: Vec<String> = (0..NTASK).map(|n| format!("{:0>8}", n)).collect();
@@ -153,7 +182,7 @@ where
 fn read_function<E, T>(b: &mut Bencher, &threads: &usize)
 where
     E: KvsEngine,
-    T: ThreadPool + Send + 'static,
+    T: ThreadPool + Send + Sync + 'static,
 {
     // key-values are [(00000000, value), ..., (00000999, value)]
     let keys: Vec<String> = (0..NTASK).map(|n| format!("{:0>8}", n)).collect();
@@ -227,3 +256,49 @@ where
         BatchSize::PerIteration,
     );
 }
+
+// commands and responses to set and get [(00000000, value), ..., (00000999, value)]
+fn construct_frames() -> (Vec<Command>, Vec<Response>) {
+    let keys = (0..NTASK).map(|n| format!("{:0>8}", n));
+    let commands = keys
+        .clone()
+        .map(|key| Command::Set {
+            key,
+            value: String::from("value"),
+        })
+        .chain(keys.map(|key| Command::Get { key }))
+        .collect();
+    let responses = (0..NTASK)
+        .map(|_| Response::SuccessSet())
+        .chain((0..NTASK).map(|_| Response::SuccessGet(Some(String::from("value")))))
+        .collect();
+    (commands, responses)
+}
+
+// the actual benchmark function for encoding
+fn encode_function(b: &mut Bencher, &codec: &Codec) {
+    let (commands, responses) = construct_frames();
+    b.iter(|| {
+        for command in &commands {
+            codec.encode(command).unwrap();
+        }
+        for response in &responses {
+            codec.encode(response).unwrap();
+        }
+    });
+}
+
+// the actual benchmark function for decoding
+fn decode_function(b: &mut Bencher, &codec: &Codec) {
+    let (commands, responses) = construct_frames();
+    let commands: Vec<_> = commands.iter().map(|c| codec.encode(c).unwrap()).collect();
+    let responses: Vec<_> = responses.iter().map(|r| codec.encode(r).unwrap()).collect();
+    b.iter(|| {
+        for command in &commands {
+            codec.decode::<Command>(command).unwrap();
+        }
+        for response in &responses {
+            codec.decode::<Response>(response).unwrap();
+        }
+    });
+}
@@ -0,0 +1,147 @@
+//! Authorizes the commands of users by the rules of an ACL file kept by the server.
+//!
+//! Each line of the file is `USER PERMISSIONS PATTERN`, where
+//!
+//! - `USER` is the name authenticated by the credentials, or `*` for any connections
+//!   including the unauthenticated ones,
+//! - `PERMISSIONS` are some of `read`, `write`, `delete` and `admin` separated by commas,
+//!   and `admin` grants all of them,
+//! - `PATTERN` is either a key, or a prefix of keys followed by `*`, and `*` alone matches all keys,
+//!   which may start with `NAMESPACE:` to match only the keys of the namespace, such as `ns:users/*`,
+//!   where the empty name means the default namespace.
+//!
+//! Empty lines and lines starting with `#` are skipped. Patterns without namespaces apply to keys in
+//! all namespaces, so keys containing `:` are matched by writing their namespaces before them.
+//! Commands not granted by any rules are denied.
+
+use crate::{Error, Result};
+
+use std::{fmt, fs, path::Path, str::FromStr};
+
+/// A type that represents the operations granted by rules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    /// Gets or watches keys
+    Read,
+    /// Sets keys
+    Write,
+    /// Removes keys
+    Delete,
+    /// Grants all the other permissions
+    Admin,
+}
+
+impl FromStr for Permission {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "read" => Ok(Permission::Read),
+            "write" => Ok(Permission::Write),
+            "delete" => Ok(Permission::Delete),
+            "admin" => Ok(Permission::Admin),
+            _ => Err(Error::InvalidAcl(format!("Unknown permission: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Delete => "delete",
+            Permission::Admin => "admin",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A type that represents the rules of access to keys.
+pub struct Acl {
+    rules: Vec<Rule>,
+}
+
+// grants the permissions on the keys matching the pattern to the user, or anyone if `None`,
+// in the namespace, or all namespaces if `None`
+struct Rule {
+    user: Option<String>,
+    permissions: Vec<Permission>,
+    namespace: Option<String>,
+    pattern: Pattern,
+}
+
+enum Pattern {
+    Key(String),
+    Prefix(String),
+}
+
+impl Acl {
+    /// Reads the rules from the file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        let mut rules = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = Rule::parse(line).map_err(|e| {
+                Error::InvalidAcl(format!("{}: line {}: {}", path.display(), i + 1, e))
+            })?;
+            rules.push(rule);
+        }
+        Ok(Acl { rules })
+    }
+
+    /// Checks whether the user, or the unauthenticated connection if `None`, is granted the permission
+    /// on the key in the namespace, or on all keys starting with it if `prefix` is true.
+    pub fn allows(
+        &self,
+        user: Option<&str>,
+        permission: Permission,
+        namespace: &str,
+        key: &str,
+        prefix: bool,
+    ) -> bool {
+        self.rules.iter().any(|rule| {
+            rule.user.as_deref().is_none_or(|name| Some(name) == user)
+                && rule.namespace.as_deref().is_none_or(|ns| ns == namespace)
+                && rule
+                    .permissions
+                    .iter()
+                    .any(|&p| p == permission || p == Permission::Admin)
+                && match &rule.pattern {
+                    Pattern::Key(k) => !prefix && k == key,
+                    Pattern::Prefix(p) => key.starts_with(p.as_str()),
+                }
+        })
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> std::result::Result<Self, String> {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let (user, permissions, pattern) = match fields[..] {
+            [user, permissions, pattern] => (user, permissions, pattern),
+            _ => return Err(String::from("Expected USER PERMISSIONS PATTERN")),
+        };
+        let (namespace, pattern) = match pattern.split_once(':') {
+            Some((namespace, pattern)) => (Some(namespace.to_owned()), pattern),
+            None => (None, pattern),
+        };
+        Ok(Rule {
+            user: Some(user).filter(|&user| user != "*").map(str::to_owned),
+            permissions: permissions
+                .split(',')
+                .map(|p| p.parse().map_err(|_| format!("Unknown permission: {}", p)))
+                .collect::<std::result::Result<_, _>>()?,
+            namespace,
+            pattern: match pattern.strip_suffix('*') {
+                Some(prefix) => Pattern::Prefix(prefix.to_owned()),
+                None => Pattern::Key(pattern.to_owned()),
+            },
+        })
+    }
+}
@@ -0,0 +1,212 @@
+//! Checks the credentials of clients against a credentials file kept by the server.
+//!
+//! Each line of the file is `NAME:HASH`, where the hash is either
+//!
+//! - `pbkdf2-sha256$ITERATIONS$SALT$KEY` for a password of the user `NAME`, or
+//! - `token-sha256$DIGEST` for a token, which authenticates clients as `NAME` by itself,
+//!
+//! with bytes written in hex. Empty lines and lines starting with `#` are skipped.
+//! Secrets are never stored, and the lines are built by [`hash_password`] and [`hash_token`].
+
+use crate::{Error, Result};
+
+use ring::{
+    digest, pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{collections::HashMap, fmt::Write, fs, num::NonZeroU32, path::Path};
+
+const PBKDF2_SCHEME: &str = "pbkdf2-sha256";
+const TOKEN_SCHEME: &str = "token-sha256";
+// the iterations of new password hashes, older hashes keep their own
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = digest::SHA256_OUTPUT_LEN;
+const TOKEN_LEN: usize = 32;
+
+/// A type that represents the users and tokens allowed to access the server.
+pub struct Credentials {
+    passwords: HashMap<String, Password>,
+    // maps the digests of tokens to their users
+    tokens: HashMap<Vec<u8>, String>,
+}
+
+// the salted hash of a password
+struct Password {
+    iterations: NonZeroU32,
+    salt: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl Credentials {
+    /// Reads the credentials from the file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        Credentials::parse(&content)
+            .map_err(|e| Error::InvalidCredentials(format!("{}: {}", path.display(), e)))
+    }
+
+    // parses the credentials in the format of the file
+    fn parse(content: &str) -> std::result::Result<Self, String> {
+        let mut credentials = Credentials {
+            passwords: HashMap::new(),
+            tokens: HashMap::new(),
+        };
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |msg: &str| format!("line {}: {}", i + 1, msg);
+
+            let (name, hash) = line
+                .split_once(':')
+                .filter(|(name, _)| !name.is_empty())
+                .ok_or_else(|| invalid("Expected NAME:HASH"))?;
+            let fields: Vec<_> = hash.split('$').collect();
+            match fields[..] {
+                [PBKDF2_SCHEME, iterations, salt, key] => {
+                    let password = Password {
+                        iterations: iterations
+                            .parse()
+                            .map_err(|_| invalid("Invalid iterations"))?,
+                        salt: from_hex(salt).ok_or_else(|| invalid("Invalid salt"))?,
+                        key: from_hex(key).ok_or_else(|| invalid("Invalid key"))?,
+                    };
+                    if credentials
+                        .passwords
+                        .insert(name.to_owned(), password)
+                        .is_some()
+                    {
+                        return Err(invalid("Duplicate user"));
+                    }
+                }
+                [TOKEN_SCHEME, digest] => {
+                    let digest = from_hex(digest).ok_or_else(|| invalid("Invalid digest"))?;
+                    credentials.tokens.insert(digest, name.to_owned());
+                }
+                _ => return Err(invalid("Unknown hash scheme")),
+            }
+        }
+        Ok(credentials)
+    }
+
+    /// Checks the password of the user, or the token if `user` is `None`,
+    /// and returns the name of the authenticated user.
+    pub fn verify(&self, user: Option<&str>, secret: &str) -> Result<String> {
+        let user = match user {
+            Some(user) => {
+                let password = self.passwords.get(user);
+                // unknown users cost as much as wrong passwords, so that they cannot be told apart
+                let verified = match password {
+                    Some(password) => pbkdf2::verify(
+                        pbkdf2::PBKDF2_HMAC_SHA256,
+                        password.iterations,
+                        &password.salt,
+                        secret.as_bytes(),
+                        &password.key,
+                    ),
+                    None => {
+                        let mut key = [0; KEY_LEN];
+                        pbkdf2::derive(
+                            pbkdf2::PBKDF2_HMAC_SHA256,
+                            NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+                            &[0; SALT_LEN],
+                            secret.as_bytes(),
+                            &mut key,
+                        );
+                        Err(ring::error::Unspecified)
+                    }
+                };
+                verified.ok().map(|()| user.to_owned())
+            }
+            None => {
+                let digest = digest::digest(&digest::SHA256, secret.as_bytes());
+                self.tokens.get(digest.as_ref()).cloned()
+            }
+        };
+        user.ok_or_else(|| Error::Unauthenticated(String::from("Invalid credentials")))
+    }
+}
+
+/// Hashes the password of the user into a line of the credentials file with a random salt.
+pub fn hash_password(user: &str, password: &str) -> Result<String> {
+    check_name(user)?;
+    let mut salt = [0; SALT_LEN];
+    random(&mut salt)?;
+    let mut key = [0; KEY_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        &salt,
+        password.as_bytes(),
+        &mut key,
+    );
+    Ok(format!(
+        "{}:{}${}${}${}",
+        user,
+        PBKDF2_SCHEME,
+        PBKDF2_ITERATIONS,
+        to_hex(&salt),
+        to_hex(&key)
+    ))
+}
+
+/// Gets the user of the line of the credentials file if the line is a password,
+/// so that the former password of a user can be replaced.
+pub fn password_user(line: &str) -> Option<&str> {
+    let (name, hash) = line.trim().split_once(':')?;
+    hash.strip_prefix(PBKDF2_SCHEME)?
+        .starts_with('$')
+        .then_some(name)
+}
+
+/// Generates a random token for the user, returns the token and its line of the credentials file.
+pub fn hash_token(user: &str) -> Result<(String, String)> {
+    check_name(user)?;
+    let mut token = [0; TOKEN_LEN];
+    random(&mut token)?;
+    let token = to_hex(&token);
+    let digest = digest::digest(&digest::SHA256, token.as_bytes());
+    let line = format!("{}:{}${}", user, TOKEN_SCHEME, to_hex(digest.as_ref()));
+    Ok((token, line))
+}
+
+// checks the name can be read back from its line
+fn check_name(user: &str) -> Result<()> {
+    if user.is_empty()
+        || user.contains(':')
+        || user.starts_with('#')
+        || user.contains(char::is_whitespace)
+    {
+        return Err(Error::InvalidCredentials(format!(
+            "Invalid user name: {:?}",
+            user
+        )));
+    }
+    Ok(())
+}
+
+fn random(buf: &mut [u8]) -> Result<()> {
+    SystemRandom::new()
+        .fill(buf)
+        .map_err(|_| Error::InvalidCredentials(String::from("Failed to generate random bytes")))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{:02x}", byte).unwrap();
+        hex
+    })
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,322 @@
+use kvs::{auth, dump, kvs_engine::*, migrate::migrate, Result};
+
+use clap::{ArgEnum, Parser};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+// `Config` is the type that represents the command-line arguments
+#[derive(Parser)]
+#[clap(name = "kvs-admin",
+    version = env!("CARGO_PKG_VERSION"),
+    author = env!("CARGO_PKG_AUTHORS"),
+    about = env!("CARGO_PKG_DESCRIPTION")
+)]
+enum Config {
+    /// Dumps all live keys of the store directory to a file or stdout,
+    /// or only the ones of the namespace given by `--ns`
+    Dump {
+        #[clap(value_name = "DB-PATH")]
+        path: PathBuf,
+        #[clap(arg_enum, long = "engine", value_name = "ENGINE-NAME")]
+        engine: Option<EngineKind>,
+        #[clap(long = "ns", value_name = "NAMESPACE")]
+        ns: Option<String>,
+        #[clap(
+            arg_enum,
+            long = "format",
+            value_name = "FORMAT",
+            default_value = "json"
+        )]
+        format: FormatKind,
+        #[clap(short = 'o', long = "output", value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Loads a dump from a file or stdin into the empty store directory,
+    /// or only the keys of the namespace given by `--ns`
+    Load {
+        #[clap(value_name = "DB-PATH")]
+        path: PathBuf,
+        #[clap(arg_enum, long = "engine", value_name = "ENGINE-NAME")]
+        engine: Option<EngineKind>,
+        #[clap(long = "ns", value_name = "NAMESPACE")]
+        ns: Option<String>,
+        #[clap(short = 'i', long = "input", value_name = "FILE")]
+        input: Option<PathBuf>,
+    },
+    /// Copies all live keys of the store directory into a new one of the selected engine,
+    /// then keeps the old one with the `.bak` suffix and moves the new one in place
+    Migrate {
+        #[clap(value_name = "DB-PATH")]
+        path: PathBuf,
+        #[clap(arg_enum, long = "engine", value_name = "ENGINE-NAME")]
+        engine: Option<EngineKind>,
+        #[clap(arg_enum, long = "to", value_name = "ENGINE-NAME")]
+        to: EngineKind,
+    },
+    /// Adds the user with the password read from the first line of stdin to the credentials file,
+    /// or replaces the password of the user if it is already there
+    AddUser {
+        #[clap(value_name = "USER")]
+        user: String,
+        #[clap(short = 'c', long = "credentials", value_name = "CREDENTIALS-FILE")]
+        credentials: PathBuf,
+    },
+    /// Appends a new random token of the user to the credentials file, and prints the token
+    AddToken {
+        #[clap(value_name = "USER")]
+        user: String,
+        #[clap(short = 'c', long = "credentials", value_name = "CREDENTIALS-FILE")]
+        credentials: PathBuf,
+    },
+}
+
+// `EngineKind` is for the argument <ENGINE-NAME>
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+enum EngineKind {
+    Kvs,
+    Sled,
+    Lsm,
+}
+
+// `FormatKind` is for the argument <FORMAT>
+#[derive(ArgEnum, Clone, Copy)]
+enum FormatKind {
+    Json,
+    Binary,
+}
+
+impl EngineKind {
+    // translates the EngineKind to the corresponding str
+    fn as_str(&self) -> &str {
+        match self {
+            EngineKind::Kvs => "kvs",
+            EngineKind::Sled => "sled",
+            EngineKind::Lsm => "lsm",
+        }
+    }
+}
+
+impl From<FormatKind> for dump::Format {
+    fn from(format: FormatKind) -> Self {
+        match format {
+            FormatKind::Json => dump::Format::Json,
+            FormatKind::Binary => dump::Format::Binary,
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    match Config::parse() {
+        Config::Dump {
+            path,
+            engine,
+            ns,
+            format,
+            output,
+        } => {
+            // never creates the store directory when dumping
+            if !path.is_dir() {
+                eprintln!("{} is not a store directory", path.display());
+                exit(1);
+            }
+
+            let writer: Box<dyn Write> = match output {
+                Some(output) => Box::new(File::create(output)?),
+                None => Box::new(io::stdout()),
+            };
+            let writer = BufWriter::new(writer);
+
+            let ns = ns.as_deref();
+            let count = match check_engine(&path, engine) {
+                EngineKind::Kvs => {
+                    // neither locks out the writer nor creates files
+                    let options = KvStoreOptions {
+                        read_only: true,
+                        ..KvStoreOptions::default()
+                    };
+                    let engine = KvStore::open_with(path, options)?;
+                    dump::dump(&engine, ns, format.into(), writer)?
+                }
+                EngineKind::Sled => {
+                    dump::dump(&SledKvsEngine::open(path)?, ns, format.into(), writer)?
+                }
+                EngineKind::Lsm => {
+                    dump::dump(&LsmKvsEngine::open(path)?, ns, format.into(), writer)?
+                }
+            };
+            eprintln!("dumped {} keys", count);
+        }
+        Config::Load {
+            path,
+            engine,
+            ns,
+            input,
+        } => {
+            let reader: Box<dyn Read> = match input {
+                Some(input) => Box::new(File::open(input)?),
+                None => Box::new(io::stdin()),
+            };
+
+            let ns = ns.as_deref();
+            let count = match check_engine(&path, engine) {
+                EngineKind::Kvs => dump::load(&KvStore::open(path)?, ns, reader)?,
+                EngineKind::Sled => dump::load(&SledKvsEngine::open(path)?, ns, reader)?,
+                EngineKind::Lsm => dump::load(&LsmKvsEngine::open(path)?, ns, reader)?,
+            };
+            eprintln!("loaded {} keys", count);
+        }
+        Config::Migrate { path, engine, to } => {
+            let engine = check_engine(&path, engine);
+            if engine == to {
+                eprintln!("the store is already in the selected engine");
+                exit(1);
+            }
+
+            // the new store is written beside the old one, whose name tells the engine
+            let new_path = path.with_extension(to.as_str());
+            let temp_path = path.with_extension(format!("{}.migrating", to.as_str()));
+            let backup_path = path.with_extension(format!("{}.bak", engine.as_str()));
+            for path in [&new_path, &temp_path, &backup_path] {
+                if path.exists() {
+                    eprintln!("{} already exists", path.display());
+                    exit(1);
+                }
+            }
+            if !path.is_dir() {
+                eprintln!("{} is not a store directory", path.display());
+                exit(1);
+            }
+
+            // both stores are dropped before the directories are swapped or cleaned up
+            let result = match engine {
+                EngineKind::Kvs => migrate_into(&KvStore::open(&path)?, to, &temp_path),
+                EngineKind::Sled => migrate_into(&SledKvsEngine::open(&path)?, to, &temp_path),
+                EngineKind::Lsm => migrate_into(&LsmKvsEngine::open(&path)?, to, &temp_path),
+            };
+            let (namespaces, keys) = match result {
+                Ok(counts) => counts,
+                Err(e) => {
+                    fs::remove_dir_all(&temp_path)?;
+                    return Err(e);
+                }
+            };
+            fs::rename(&path, &backup_path)?;
+            fs::rename(&temp_path, &new_path)?;
+            eprintln!(
+                "migrated {} keys in {} namespaces to {}, the old store is kept in {}",
+                keys,
+                namespaces,
+                new_path.display(),
+                backup_path.display()
+            );
+        }
+        Config::AddUser { user, credentials } => {
+            let mut password = String::new();
+            io::stdin().lock().read_line(&mut password)?;
+            let password = password.trim_end_matches(['\r', '\n']);
+            if password.is_empty() {
+                eprintln!("the password is empty");
+                exit(1);
+            }
+            let line = auth::hash_password(&user, password)?;
+            let content = match fs::read_to_string(&credentials) {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e.into()),
+            };
+            // the tokens of the user are kept
+            let mut lines: Vec<&str> = content
+                .lines()
+                .filter(|line| auth::password_user(line) != Some(user.as_str()))
+                .collect();
+            let replaced = lines.len() < content.lines().count();
+            lines.push(&line);
+            replace_lines(&credentials, &lines)?;
+            if replaced {
+                eprintln!(
+                    "replaced the password of {} in {}",
+                    user,
+                    credentials.display()
+                );
+            } else {
+                eprintln!("added user {} to {}", user, credentials.display());
+            }
+        }
+        Config::AddToken { user, credentials } => {
+            let (token, line) = auth::hash_token(&user)?;
+            append_line(&credentials, &line)?;
+            println!("{}", token);
+            eprintln!("added a token of {} to {}", user, credentials.display());
+        }
+    }
+
+    Ok(())
+}
+
+// appends the line to the credentials file, which is created if missing
+fn append_line(path: &Path, line: &str) -> Result<()> {
+    let mut file = private_options().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)?;
+    Ok(())
+}
+
+// replaces the content of the credentials file with the lines atomically
+fn replace_lines(path: &Path, lines: &[&str]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = private_options()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&tmp_path)?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    file.sync_all()?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+// gets the options that create files readable and writable only by their owners,
+// since the credentials file holds the hashes of secrets
+fn private_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+// migrates all keys of the source into a new store of the given engine at the path
+fn migrate_into(source: &impl KvsEngine, to: EngineKind, path: &Path) -> Result<(usize, usize)> {
+    match to {
+        EngineKind::Kvs => migrate(source, &KvStore::open(path)?),
+        EngineKind::Sled => migrate(source, &SledKvsEngine::open(path)?),
+        EngineKind::Lsm => migrate(source, &LsmKvsEngine::open(path)?),
+    }
+}
+
+// checks the input engine with the one implied by the extension of the store directory
+fn check_engine(path: &Path, engine: Option<EngineKind>) -> EngineKind {
+    let implied_engine = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("kvs") => Some(EngineKind::Kvs),
+        Some("sled") => Some(EngineKind::Sled),
+        Some("lsm") => Some(EngineKind::Lsm),
+        _ => None,
+    };
+
+    match (engine, implied_engine) {
+        (Some(en1), Some(en2)) if en1 != en2 => {
+            eprintln!("selected engine is different from the one implied by the path");
+            exit(1);
+        }
+        (None, None) => {
+            eprintln!("unable to infer the engine from the path, please select one");
+            exit(1);
+        }
+        (en1, en2) => en1.or(en2).unwrap(),
+    }
+}
@@ -0,0 +1,62 @@
+use kvs::{KvStore, Result};
+
+use clap::Parser;
+use std::{path::PathBuf, process::exit};
+
+// `Config` is the type that represents the command-line arguments
+#[derive(Parser)]
+#[clap(name = "kvs-check",
+    version = env!("CARGO_PKG_VERSION"),
+    author = env!("CARGO_PKG_AUTHORS"),
+    about = env!("CARGO_PKG_DESCRIPTION")
+)]
+struct Config {
+    #[clap(value_name = "DB-PATH", default_value = "db.kvs")]
+    path: PathBuf,
+    /// Salvages readable records into a fresh directory
+    #[clap(long = "repair", value_name = "NEW-DB-PATH")]
+    repair: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    let Config { path, repair } = Config::parse();
+
+    // never creates the store directory when checking
+    if !path.is_dir() {
+        eprintln!("{} is not a store directory", path.display());
+        exit(1);
+    }
+
+    let report = match &repair {
+        Some(new_path) => {
+            if new_path.exists() {
+                eprintln!("{} already exists", new_path.display());
+                exit(1);
+            }
+            KvStore::repair(&path, new_path)?
+        }
+        None => KvStore::check(&path)?,
+    };
+
+    println!("files: {}", report.files);
+    println!("live records: {}", report.live);
+    println!("overwritten records: {}", report.overwritten);
+    println!("tombstone records: {}", report.tombstones);
+    println!("corrupt records: {}", report.corrupt.len());
+    for (n, pos) in &report.corrupt {
+        println!("  kvs.data.{} at offset {}", n, pos);
+    }
+
+    match repair {
+        Some(new_path) => println!(
+            "salvaged {} live records into {}",
+            report.live,
+            new_path.display()
+        ),
+        // exits with failure to tell scripts that the directory is broken
+        None if !report.corrupt.is_empty() => exit(1),
+        None => (),
+    }
+
+    Ok(())
+}
@@ -1,7 +1,7 @@
-use kvs::{Command, KvsClient, Response, Result};
+use kvs::{kvs_engine::Event, tls, Command, KvsClient, Response, Result};
 
-use clap::Parser;
-use std::{net::SocketAddr, process::exit};
+use clap::{Args, Parser};
+use std::{net::SocketAddr, path::PathBuf, process::exit};
 
 // `Config` is the type that represents the command-line arguments
 #[derive(Parser)]
@@ -20,6 +20,12 @@ pub enum Config {
             default_value = "127.0.0.1:4000"
         )]
         addr: SocketAddr,
+        #[clap(long = "ns", value_name = "NAMESPACE")]
+        ns: Option<String>,
+        #[clap(flatten)]
+        tls: TlsOptions,
+        #[clap(flatten)]
+        auth: AuthOptions,
     },
     Get {
         key: String,
@@ -29,6 +35,12 @@ pub enum Config {
             default_value = "127.0.0.1:4000"
         )]
         addr: SocketAddr,
+        #[clap(long = "ns", value_name = "NAMESPACE")]
+        ns: Option<String>,
+        #[clap(flatten)]
+        tls: TlsOptions,
+        #[clap(flatten)]
+        auth: AuthOptions,
     },
     Rm {
         key: String,
@@ -38,7 +50,116 @@ pub enum Config {
             default_value = "127.0.0.1:4000"
         )]
         addr: SocketAddr,
+        #[clap(long = "ns", value_name = "NAMESPACE")]
+        ns: Option<String>,
+        #[clap(flatten)]
+        tls: TlsOptions,
+        #[clap(flatten)]
+        auth: AuthOptions,
     },
+    /// Prints the changes of the key, or the keys starting with it with `--prefix`, until killed
+    Watch {
+        key: String,
+        #[clap(long = "prefix")]
+        prefix: bool,
+        #[clap(
+            long = "addr",
+            value_name = "IP-PORT",
+            default_value = "127.0.0.1:4000"
+        )]
+        addr: SocketAddr,
+        #[clap(long = "ns", value_name = "NAMESPACE")]
+        ns: Option<String>,
+        #[clap(flatten)]
+        tls: TlsOptions,
+        #[clap(flatten)]
+        auth: AuthOptions,
+    },
+}
+
+// `TlsOptions` is the type that represents the arguments of TLS shared by the subcommands
+#[derive(Args)]
+pub struct TlsOptions {
+    /// Encrypts the connection with TLS, trusting the roots of Mozilla unless `--ca` is given
+    #[clap(long = "tls")]
+    tls: bool,
+    /// Encrypts the connection with TLS, trusting the CAs in the PEM file
+    #[clap(long = "ca", value_name = "CA-FILE")]
+    ca: Option<PathBuf>,
+    /// Presents the certificate chain in the PEM file to servers requiring client certificates
+    #[clap(long = "cert", value_name = "CERT-FILE", requires = "key-file")]
+    cert_file: Option<PathBuf>,
+    /// The private key of the client certificate in the PEM file
+    #[clap(long = "key", value_name = "KEY-FILE", requires = "cert-file")]
+    key_file: Option<PathBuf>,
+    /// The name the certificate of the server must be valid for, which is the IP of `--addr` by default
+    #[clap(long = "server-name", value_name = "NAME")]
+    server_name: Option<String>,
+}
+
+impl TlsOptions {
+    // checks whether TLS is enabled by any of the arguments
+    fn enabled(&self) -> bool {
+        self.tls || self.ca.is_some() || self.cert_file.is_some() || self.server_name.is_some()
+    }
+
+    // connects the server with TLS if enabled
+    fn connect(&self, addr: SocketAddr) -> Result<KvsClient> {
+        if !self.enabled() {
+            return KvsClient::connect(addr);
+        }
+
+        let identity = self.cert_file.as_deref().zip(self.key_file.as_deref());
+        let config = tls::client_config(self.ca.as_deref(), identity)?;
+        let server_name = match &self.server_name {
+            Some(name) => name.clone(),
+            None => addr.ip().to_string(),
+        };
+        KvsClient::connect_tls(addr, &server_name, config)
+    }
+}
+
+// `AuthOptions` is the type that represents the credentials shared by the subcommands
+#[derive(Args)]
+pub struct AuthOptions {
+    /// Authenticates as the user, with the password of `--password`
+    #[clap(
+        long = "user",
+        value_name = "USER",
+        env = "KVS_USER",
+        requires = "password"
+    )]
+    user: Option<String>,
+    /// The password of the user
+    #[clap(
+        long = "password",
+        value_name = "PASSWORD",
+        env = "KVS_PASSWORD",
+        hide_env_values = true,
+        requires = "user"
+    )]
+    password: Option<String>,
+    /// Authenticates with the token instead of a user and a password
+    #[clap(
+        long = "token",
+        value_name = "TOKEN",
+        env = "KVS_TOKEN",
+        hide_env_values = true,
+        conflicts_with = "user"
+    )]
+    token: Option<String>,
+}
+
+impl AuthOptions {
+    // authenticates the connection if any credentials are given
+    fn authenticate(&self, client: &mut KvsClient) -> Result<()> {
+        if let Some(token) = &self.token {
+            client.authenticate(None, token)?;
+        } else if let (Some(user), Some(password)) = (&self.user, &self.password) {
+            client.authenticate(Some(user), password)?;
+        }
+        Ok(())
+    }
 }
 
 impl Config {
@@ -48,19 +169,47 @@ impl Config {
             Config::Set { key, value, .. } => Command::Set { key, value },
             Config::Get { key, .. } => Command::Get { key },
             Config::Rm { key, .. } => Command::Rm { key },
+            Config::Watch { key, prefix, .. } => Command::Watch { key, prefix },
         }
     }
 
     // gets the server address from the Config
     fn addr(&self) -> &SocketAddr {
         match self {
-            Config::Set {
-                key: _,
-                value: _,
-                addr,
-            } => addr,
-            Config::Get { key: _, addr } => addr,
-            Config::Rm { key: _, addr } => addr,
+            Config::Set { addr, .. } => addr,
+            Config::Get { addr, .. } => addr,
+            Config::Rm { addr, .. } => addr,
+            Config::Watch { addr, .. } => addr,
+        }
+    }
+
+    // gets the namespace from the Config
+    fn ns(&self) -> Option<&String> {
+        match self {
+            Config::Set { ns, .. } => ns.as_ref(),
+            Config::Get { ns, .. } => ns.as_ref(),
+            Config::Rm { ns, .. } => ns.as_ref(),
+            Config::Watch { ns, .. } => ns.as_ref(),
+        }
+    }
+
+    // gets the arguments of TLS from the Config
+    fn tls(&self) -> &TlsOptions {
+        match self {
+            Config::Set { tls, .. } => tls,
+            Config::Get { tls, .. } => tls,
+            Config::Rm { tls, .. } => tls,
+            Config::Watch { tls, .. } => tls,
+        }
+    }
+
+    // gets the credentials from the Config
+    fn auth(&self) -> &AuthOptions {
+        match self {
+            Config::Set { auth, .. } => auth,
+            Config::Get { auth, .. } => auth,
+            Config::Rm { auth, .. } => auth,
+            Config::Watch { auth, .. } => auth,
         }
     }
 }
@@ -69,11 +218,30 @@ fn main() -> Result<()> {
     // parses the command-line arguments
     let config = Config::parse();
 
-    // creates a kvs client with input address
-    let mut client = KvsClient::connect(*config.addr())?;
-    // sends the command to the kvs serevr
-    match client.send(config.into_command())? {
-        Response::Fail(msg) => {
+    // creates a kvs client with input address, TLS arguments, credentials and namespace
+    let mut client = config.tls().connect(*config.addr())?;
+    if let Err(e) = config.auth().authenticate(&mut client) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+    if let Some(ns) = config.ns() {
+        client.set_namespace(ns);
+    }
+    // prints the events line by line if watching, or sends the command to the kvs serevr
+    let command = config.into_command();
+    if let Command::Watch { key, prefix } = command {
+        for event in client.watch(key, prefix)? {
+            match event? {
+                Event::Set { key, value } => println!("set {} {}", key, value),
+                Event::Rm { key } => println!("rm {}", key),
+            }
+        }
+        return Ok(());
+    }
+    let response = client.send(command)?;
+    client.close()?;
+    match response {
+        Response::Fail(msg) | Response::Unauthenticated(msg) | Response::PermissionDenied(msg) => {
             eprintln!("{}", msg);
             exit(1);
         }
@@ -1,12 +1,22 @@
-use kvs::{kvs_engine::*, thread_pool::*, KvsServer, Result};
+use kvs::{
+    acl::Acl, auth::Credentials, kvs_engine::*, thread_pool::*, tls, HttpServer, KvsServer,
+    RespServer, Result,
+};
 
 use clap::{ArgEnum, Parser};
-use slog::info;
+use slog::{error, info, Logger};
 use sloggers::{
     terminal::{Destination, TerminalLoggerBuilder},
     Build,
 };
-use std::{net::SocketAddr, path::Path, process::exit};
+use std::{
+    fs::File,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::Arc,
+    thread,
+};
 
 // `Config` is the type that represents the command-line arguments
 #[derive(Parser)]
@@ -24,6 +34,50 @@ struct Config {
     addr: SocketAddr,
     #[clap(arg_enum, long = "engine", value_name = "ENGINE-NAME")]
     engine: Option<EngineKind>,
+    /// Seeds the memory engine with a dump file in either format
+    #[clap(long = "seed", value_name = "DUMP-FILE")]
+    seed: Option<PathBuf>,
+    /// Limits the bytes per second rewritten by compaction of the kvs engine
+    #[clap(long = "compaction-rate", value_name = "BYTES-PER-SEC")]
+    compaction_rate: Option<u64>,
+    /// Rejects requests longer than the given bytes
+    #[clap(long = "max-request-size", value_name = "BYTES")]
+    max_request_size: Option<usize>,
+    /// Also listens for Redis clients (RESP2/RESP3) on the given address
+    #[clap(long = "resp-addr", value_name = "IP-PORT")]
+    resp_addr: Option<SocketAddr>,
+    /// Also serves the HTTP/JSON gateway on the given address
+    #[clap(long = "http-addr", value_name = "IP-PORT")]
+    http_addr: Option<SocketAddr>,
+    /// Encrypts connections of kvs clients with TLS using the certificate chain in the PEM file,
+    /// which the RESP and HTTP listeners do not support
+    #[clap(
+        long = "tls-cert",
+        value_name = "CERT-FILE",
+        requires = "tls-key",
+        conflicts_with_all = &["resp-addr", "http-addr"]
+    )]
+    tls_cert: Option<PathBuf>,
+    /// The private key of the certificate in the PEM file
+    #[clap(long = "tls-key", value_name = "KEY-FILE", requires = "tls-cert")]
+    tls_key: Option<PathBuf>,
+    /// Requires kvs clients to present certificates signed by the CAs in the PEM file
+    #[clap(long = "tls-client-ca", value_name = "CA-FILE", requires = "tls-cert")]
+    tls_client_ca: Option<PathBuf>,
+    /// Requires kvs clients to authenticate with the users or the tokens in the credentials file
+    #[clap(
+        long = "credentials",
+        value_name = "CREDENTIALS-FILE",
+        conflicts_with_all = &["resp-addr", "http-addr"]
+    )]
+    credentials: Option<PathBuf>,
+    /// Grants kvs clients only the operations on keys allowed by the rules in the ACL file
+    #[clap(
+        long = "acl",
+        value_name = "ACL-FILE",
+        conflicts_with_all = &["resp-addr", "http-addr"]
+    )]
+    acl: Option<PathBuf>,
 }
 
 // `EngineKind` is for the argument <ENGINE-NAME>
@@ -31,6 +85,8 @@ struct Config {
 enum EngineKind {
     Kvs,
     Sled,
+    Lsm,
+    Memory,
 }
 
 impl EngineKind {
@@ -39,6 +95,8 @@ impl EngineKind {
         match self {
             EngineKind::Kvs => "kvs",
             EngineKind::Sled => "sled",
+            EngineKind::Lsm => "lsm",
+            EngineKind::Memory => "memory",
         }
     }
 }
@@ -50,28 +108,165 @@ fn main() -> Result<()> {
     let logger = builder.build()?;
 
     // parses the command-line arguments and checks the engine
-    let Config { addr, engine } = Config::parse();
+    let Config {
+        addr,
+        engine,
+        seed,
+        compaction_rate,
+        max_request_size,
+        resp_addr,
+        http_addr,
+        tls_cert,
+        tls_key,
+        tls_client_ca,
+        credentials,
+        acl,
+    } = Config::parse();
     let engine = check_engine(engine);
+    if seed.is_some() && engine != EngineKind::Memory {
+        eprintln!("only the memory engine can be seeded");
+        exit(1);
+    }
+    if compaction_rate.is_some() && engine != EngineKind::Kvs {
+        eprintln!("only the kvs engine can limit the compaction rate");
+        exit(1);
+    }
 
     info!(logger, "kvs-server version: {}", env!("CARGO_PKG_VERSION"));
     info!(logger, "IP-PORT: {}, ENGINE: {}", addr, engine.as_str());
+    if let Some(resp_addr) = resp_addr {
+        info!(logger, "RESP IP-PORT: {}", resp_addr);
+    }
+    if let Some(http_addr) = http_addr {
+        info!(logger, "HTTP IP-PORT: {}", http_addr);
+    }
+    let tls = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => {
+            info!(
+                logger,
+                "TLS enabled, client certificates required: {}",
+                tls_client_ca.is_some()
+            );
+            Some(tls::server_config(cert, key, tls_client_ca.as_deref())?)
+        }
+        _ => None,
+    };
+    let credentials = match credentials {
+        Some(path) => {
+            info!(logger, "Authentication required by: {}", path.display());
+            Some(Arc::new(Credentials::open(path)?))
+        }
+        None => None,
+    };
+    let acl = match acl {
+        Some(path) => {
+            info!(logger, "Access controlled by: {}", path.display());
+            Some(Arc::new(Acl::open(path)?))
+        }
+        None => None,
+    };
 
     // creates the thread_pool, engine and server and then runs the server
     let thread_pool = SharedQueueThreadPool::new(num_cpus::get()).unwrap();
+    let serve_options = ServeOptions {
+        max_request_size,
+        resp_addr,
+        http_addr,
+        tls,
+        credentials,
+        acl,
+    };
     match engine {
         EngineKind::Kvs => {
-            let engine = KvStore::open("db.".to_owned() + engine.as_str())?;
-            KvsServer::new(logger, addr, engine, thread_pool)?.run(None)?;
+            let options = KvStoreOptions {
+                compaction_rate,
+                ..KvStoreOptions::default()
+            };
+            let engine = KvStore::open_with("db.".to_owned() + engine.as_str(), options)?;
+            serve(logger, addr, engine, thread_pool, &serve_options)?;
         }
         EngineKind::Sled => {
             let engine = SledKvsEngine::open("db.".to_owned() + engine.as_str())?;
-            KvsServer::new(logger, addr, engine, thread_pool)?.run(None)?;
+            serve(logger, addr, engine, thread_pool, &serve_options)?;
+        }
+        EngineKind::Lsm => {
+            let engine = LsmKvsEngine::open("db.".to_owned() + engine.as_str())?;
+            serve(logger, addr, engine, thread_pool, &serve_options)?;
+        }
+        EngineKind::Memory => {
+            let engine = match seed {
+                Some(seed) => MemKvsEngine::seed(File::open(seed)?)?,
+                None => MemKvsEngine::new(),
+            };
+            serve(logger, addr, engine, thread_pool, &serve_options)?;
         }
     };
 
     Ok(())
 }
 
+// `ServeOptions` is the type that represents the options of servers
+struct ServeOptions {
+    max_request_size: Option<usize>,
+    resp_addr: Option<SocketAddr>,
+    http_addr: Option<SocketAddr>,
+    tls: Option<Arc<tls::ServerConfig>>,
+    credentials: Option<Arc<Credentials>>,
+    acl: Option<Arc<Acl>>,
+}
+
+// creates the server with the options and then runs it,
+// along with the RESP server and the HTTP gateway in other threads if their addresses are given
+fn serve(
+    logger: Logger,
+    addr: SocketAddr,
+    engine: impl KvsEngine,
+    thread_pool: impl ThreadPool + Send + Sync + 'static,
+    options: &ServeOptions,
+) -> Result<()> {
+    if let Some(resp_addr) = options.resp_addr {
+        let thread_pool = SharedQueueThreadPool::new(num_cpus::get())?;
+        let mut server = RespServer::new(logger.clone(), resp_addr, engine.clone(), thread_pool)?;
+        if let Some(size) = options.max_request_size {
+            server.set_max_request_size(size);
+        }
+        let logger = logger.clone();
+        thread::spawn(move || {
+            if let Err(e) = server.run(None) {
+                error!(logger, "RESP server stopped: {}", e);
+            }
+        });
+    }
+    if let Some(http_addr) = options.http_addr {
+        let thread_pool = SharedQueueThreadPool::new(num_cpus::get())?;
+        let mut server = HttpServer::new(logger.clone(), http_addr, engine.clone(), thread_pool)?;
+        if let Some(size) = options.max_request_size {
+            server.set_max_request_size(size);
+        }
+        let logger = logger.clone();
+        thread::spawn(move || {
+            if let Err(e) = server.run(None) {
+                error!(logger, "HTTP server stopped: {}", e);
+            }
+        });
+    }
+
+    let mut server = KvsServer::new(logger, addr, engine, thread_pool)?;
+    if let Some(size) = options.max_request_size {
+        server.set_max_request_size(size);
+    }
+    if let Some(config) = &options.tls {
+        server.set_tls(Arc::clone(config));
+    }
+    if let Some(credentials) = &options.credentials {
+        server.set_credentials(Arc::clone(credentials));
+    }
+    if let Some(acl) = &options.acl {
+        server.set_acl(Arc::clone(acl));
+    }
+    server.run(None)
+}
+
 // checks the input engine with selected engine if there has been a selected engine
 fn check_engine(engine: Option<EngineKind>) -> EngineKind {
     // gets the existed engine
@@ -79,6 +274,8 @@ fn check_engine(engine: Option<EngineKind>) -> EngineKind {
         Some(EngineKind::Kvs)
     } else if Path::new("db.sled").exists() {
         Some(EngineKind::Sled)
+    } else if Path::new("db.lsm").exists() {
+        Some(EngineKind::Lsm)
     } else {
         None
     };
@@ -86,6 +283,8 @@ fn check_engine(engine: Option<EngineKind>) -> EngineKind {
     // checks and returns the final valid engine
     match (engine, exist_engine) {
         (None, None) => EngineKind::Kvs,
+        // the memory engine never reads the persisted data
+        (Some(EngineKind::Memory), _) => EngineKind::Memory,
         (Some(en1), Some(en2)) if en1 == en2 => en1,
         // prints to stderr and exits if the input engine is different from the selected engine
         (Some(_), Some(_)) => {
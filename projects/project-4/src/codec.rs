@@ -0,0 +1,57 @@
+mod binary_de;
+mod binary_ser;
+
+use crate::{Error, Handshake, Result};
+
+use serde::{Deserialize, Serialize};
+
+// the feature of the handshake that switches the following frames to the binary codec
+pub(crate) const BINARY: &str = "binary";
+
+// the longest length prefix of frames, which is the digits of the largest `u64` and `#`
+pub(crate) const MAX_PREFIX_LEN: u64 = 21;
+
+/// A type that represents the encoding of frames after handshakes, which is either
+/// the readable text format ([`Text`]) or the compact binary format ([`Binary`]).
+///
+/// Handshakes are always in the text format, so that any versions can read them.
+///
+/// [`Text`]: Codec::Text
+/// [`Binary`]: Codec::Binary
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// Writes numbers as decimal strings and prefixes strings with their lengths, kept for debugging
+    Text,
+    /// Writes numbers as varints and strings as raw bytes
+    Binary,
+}
+
+impl Codec {
+    /// Encodes the given value into a payload.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Codec::Text => crate::ser::to_string(value).map(String::into_bytes),
+            Codec::Binary => binary_ser::to_vec(value),
+        }
+    }
+
+    /// Decodes a value from the given payload.
+    pub fn decode<'a, T: Deserialize<'a>>(self, payload: &'a [u8]) -> Result<T> {
+        match self {
+            Codec::Text => crate::de::from_str(
+                std::str::from_utf8(payload)
+                    .map_err(|_| Error::SerdeError(String::from("Expected utf-8")))?,
+            ),
+            Codec::Binary => binary_de::from_slice(payload),
+        }
+    }
+
+    // gets the codec agreed by the handshake
+    pub(crate) fn of(handshake: &Handshake) -> Self {
+        if handshake.supports(BINARY) {
+            Codec::Binary
+        } else {
+            Codec::Text
+        }
+    }
+}
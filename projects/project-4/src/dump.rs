@@ -0,0 +1,229 @@
+//! A module for dumping live data of store engines into portable files and loading them back.
+
+use crate::{Error, KvsEngine, Result};
+
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+
+// the header of binary dumps, which also tells them from json-lines dumps
+const BINARY_MAGIC: &[u8] = b"KVSDUMP\x02";
+// the header of binary dumps written before namespaces, whose records are in the default namespace
+const BINARY_MAGIC_V1: &[u8] = b"KVSDUMP\x01";
+
+/// A type that represents the format of dump files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One json object like `{"ns":"n","key":"k","value":"v"}` per line,
+    /// where `ns` is left out for the default namespace
+    Json,
+    /// The magic header followed by length-prefixed namespaces, keys and values
+    Binary,
+}
+
+// one key-value pair in json-lines dumps
+#[derive(Serialize, Deserialize)]
+struct Record {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    ns: String,
+    key: String,
+    value: String,
+}
+
+/// Writes the live key-value pairs of the namespace `ns` of the given engine,
+/// or of all its namespaces if `ns` is None, to `writer` in the given format,
+/// returns the number of written pairs.
+pub fn dump(
+    engine: &impl KvsEngine,
+    ns: Option<&str>,
+    format: Format,
+    mut writer: impl Write,
+) -> Result<usize> {
+    if format == Format::Binary {
+        writer.write_all(BINARY_MAGIC)?;
+    }
+
+    let names = match ns {
+        Some(ns) => vec![ns.to_owned()],
+        None => engine.namespaces()?,
+    };
+    let mut count = 0;
+    for ns in names {
+        engine.open_namespace(&ns)?.for_each(|key, value| {
+            match format {
+                Format::Json => {
+                    let record = Record {
+                        ns: ns.clone(),
+                        key,
+                        value,
+                    };
+                    serde_json::to_writer(&mut writer, &record)?;
+                    writer.write_all(b"\n")?;
+                }
+                Format::Binary => {
+                    write_bytes(&mut writer, ns.as_bytes())?;
+                    write_bytes(&mut writer, key.as_bytes())?;
+                    write_bytes(&mut writer, value.as_bytes())?;
+                }
+            }
+            count += 1;
+            Ok(())
+        })?;
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Loads the records of the namespace `ns` in a dump of either format,
+/// or all its records if `ns` is None, into the same namespaces of the given engine,
+/// which must be empty, returns the number of loaded pairs.
+pub fn load(engine: &impl KvsEngine, ns: Option<&str>, reader: impl Read) -> Result<usize> {
+    let names = match ns {
+        Some(ns) => vec![ns.to_owned()],
+        None => engine.namespaces()?,
+    };
+    for ns in names {
+        // stops at the first live key, if any
+        engine
+            .open_namespace(&ns)?
+            .for_each(|_, _| Err(Error::StoreNotEmpty))?;
+    }
+
+    let mut records = Records::new(reader)?
+        .filter(|record| match (record, ns) {
+            (Ok((record_ns, _, _)), Some(ns)) => record_ns == ns,
+            _ => true,
+        })
+        .peekable();
+    let mut count = 0;
+    // loads each run of records in the same namespace at once
+    while let Some(record) = records.peek() {
+        let ns = match record {
+            Ok((ns, _, _)) => ns.clone(),
+            Err(_) => return Err(records.next().unwrap().unwrap_err()),
+        };
+        let pairs = std::iter::from_fn(|| {
+            records
+                .next_if(|record| !matches!(record, Ok((record_ns, _, _)) if *record_ns != ns))
+                .map(|record| record.map(|(_, key, value)| (key, value)))
+        });
+        count += engine.open_namespace(&ns)?.load(pairs)?;
+    }
+    Ok(count)
+}
+
+/// An iterator over the namespaces, keys and values of a dump, whose format is detected by the header.
+pub struct Records<R: Read> {
+    reader: BufReader<R>,
+    format: Format,
+    // whether binary records have namespaces, which the ones of older dumps do not
+    namespaces: bool,
+}
+
+impl<R: Read> Records<R> {
+    /// Creates the iterator and detects the format from the given reader.
+    pub fn new(reader: R) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let header = reader.fill_buf()?;
+        let (format, namespaces) = if header.starts_with(BINARY_MAGIC) {
+            (Format::Binary, true)
+        } else if header.starts_with(BINARY_MAGIC_V1) {
+            (Format::Binary, false)
+        } else {
+            (Format::Json, true)
+        };
+        if format == Format::Binary {
+            reader.consume(BINARY_MAGIC.len());
+        }
+        Ok(Records {
+            reader,
+            format,
+            namespaces,
+        })
+    }
+
+    /// Gets the detected format.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    // reads the next record from json lines, skipping empty lines
+    fn next_json(&mut self) -> Result<Option<(String, String, String)>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                let Record { ns, key, value } = serde_json::from_str(&line)?;
+                return Ok(Some((ns, key, value)));
+            }
+        }
+    }
+
+    // reads the next record from binary records
+    fn next_binary(&mut self) -> Result<Option<(String, String, String)>> {
+        let ns = if self.namespaces {
+            match read_bytes(&mut self.reader)? {
+                None => return Ok(None),
+                Some(ns) => Some(into_string(ns)?),
+            }
+        } else {
+            None
+        };
+        let key = match read_bytes(&mut self.reader)? {
+            None if ns.is_none() => return Ok(None),
+            None => return Err(Error::InvalidDump(String::from("Missing key"))),
+            Some(key) => into_string(key)?,
+        };
+        match read_bytes(&mut self.reader)? {
+            None => Err(Error::InvalidDump(String::from("Missing value"))),
+            Some(value) => Ok(Some((ns.unwrap_or_default(), key, into_string(value)?))),
+        }
+    }
+}
+
+impl<R: Read> Iterator for Records<R> {
+    type Item = Result<(String, String, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.format {
+            Format::Json => self.next_json(),
+            Format::Binary => self.next_binary(),
+        }
+        .transpose()
+    }
+}
+
+// writes the length as u32 in little endian and then the bytes
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    let len = u32::try_from(bytes.len())
+        .map_err(|_| Error::InvalidDump(String::from("Record too large")))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+// reads the bytes written by `write_bytes`, returns None at the end of the dump
+fn read_bytes(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    // the buffer only grows with the bytes read, whatever the length claims
+    let len = u64::from(u32::from_le_bytes(len));
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(Error::InvalidDump(String::from("Truncated record")));
+    }
+    Ok(Some(bytes))
+}
+
+fn into_string(bytes: Vec<u8>) -> Result<String> {
+    String::from_utf8(bytes).map_err(|e| Error::InvalidDump(e.to_string()))
+}
@@ -27,6 +27,100 @@ pub enum Error {
 
     #[error("Key not found")]
     KeyNotFound,
+
+    #[error("InvalidManifest: {0}")]
+    InvalidManifest(String),
+
+    #[error("Store locked by another process")]
+    StoreLocked,
+
+    #[error("Store not empty")]
+    StoreNotEmpty,
+
+    #[error("InvalidDump: {0}")]
+    InvalidDump(String),
+
+    #[error("ServerError: {0}")]
+    ServerError(String),
+
+    #[error("InvalidTable: {0}")]
+    InvalidTable(String),
+
+    #[error("MigrationError: {0}")]
+    MigrationError(String),
+
+    #[error("Store opened in read-only mode")]
+    ReadOnly,
+
+    #[error("Request too large: {0} bytes")]
+    RequestTooLarge(usize),
+
+    #[error("InvalidCommand: {0}")]
+    InvalidCommand(String),
+
+    #[error("IncompatibleProtocol: {0}")]
+    IncompatibleProtocol(String),
+
+    #[error("RespError: {0}")]
+    RespError(String),
+
+    #[error("TlsError: {0}")]
+    TlsError(String),
+
+    #[error("InvalidCredentials: {0}")]
+    InvalidCredentials(String),
+
+    #[error("Unauthenticated: {0}")]
+    Unauthenticated(String),
+
+    #[error("InvalidAcl: {0}")]
+    InvalidAcl(String),
+
+    #[error("PermissionDenied: {0}")]
+    PermissionDenied(String),
+
+    #[error("InvalidWal: {0}")]
+    InvalidWal(String),
+
+    #[cfg(feature = "async")]
+    #[error("JoinError: {0:?}")]
+    JoinError(#[from] tokio::task::JoinError),
+}
+
+impl Error {
+    /// Gets the name of the variant, which tells the kind of errors to clients of other protocols.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::IOError(_) => "IOError",
+            Error::SerdeJSONError(_) => "SerdeJSONError",
+            Error::SerdeError(_) => "SerdeError",
+            Error::WalkdirError(_) => "WalkdirError",
+            Error::SloggersError(_) => "SloggersError",
+            Error::SledError(_) => "SledError",
+            Error::ErrorLogMeet => "ErrorLogMeet",
+            Error::KeyNotFound => "KeyNotFound",
+            Error::InvalidManifest(_) => "InvalidManifest",
+            Error::StoreLocked => "StoreLocked",
+            Error::StoreNotEmpty => "StoreNotEmpty",
+            Error::InvalidDump(_) => "InvalidDump",
+            Error::ServerError(_) => "ServerError",
+            Error::InvalidTable(_) => "InvalidTable",
+            Error::MigrationError(_) => "MigrationError",
+            Error::ReadOnly => "ReadOnly",
+            Error::RequestTooLarge(_) => "RequestTooLarge",
+            Error::InvalidCommand(_) => "InvalidCommand",
+            Error::IncompatibleProtocol(_) => "IncompatibleProtocol",
+            Error::RespError(_) => "RespError",
+            Error::TlsError(_) => "TlsError",
+            Error::InvalidCredentials(_) => "InvalidCredentials",
+            Error::Unauthenticated(_) => "Unauthenticated",
+            Error::InvalidAcl(_) => "InvalidAcl",
+            Error::PermissionDenied(_) => "PermissionDenied",
+            Error::InvalidWal(_) => "InvalidWal",
+            #[cfg(feature = "async")]
+            Error::JoinError(_) => "JoinError",
+        }
+    }
 }
 
 impl serde::ser::Error for Error {
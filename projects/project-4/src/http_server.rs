@@ -0,0 +1,328 @@
+use crate::{kvs_engine::KvsEngine, thread_pool::*, Error, Result};
+
+use crossbeam_channel::SendError;
+use percent_encoding::percent_decode_str;
+use serde_json::json;
+use slog::{info, Logger};
+use std::{
+    io::{self, Read},
+    net::SocketAddr,
+    thread,
+    time::Duration,
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+// the default limit of the length of one value
+const MAX_REQUEST_SIZE: usize = 16 * 1024 * 1024;
+
+// the default limit of the time to receive the body of one request
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// the default and the largest number of pairs in one page of scans
+const SCAN_LIMIT: usize = 100;
+const MAX_SCAN_LIMIT: usize = 1000;
+
+/// A type that abstracts the HTTP/JSON gateway, which serves the routes
+///
+/// - `GET /keys/{key}` to get `{"key": .., "value": ..}`,
+/// - `PUT /keys/{key}` to set the value in the body,
+/// - `DELETE /keys/{key}` to remove the key,
+/// - `GET /keys?prefix=..&after=..&limit=..` to scan a page of `{"items": [..], "next": ..}`,
+///   where `next` is passed as `after` to get the following page,
+///
+/// in the namespace given by the query `ns`. Errors are sent as `{"error": .., "message": ..}`,
+/// where `error` is the name of the variant of [`Error`].
+pub struct HttpServer<E: KvsEngine, T: ThreadPool> {
+    logger: Logger,
+    server: Server,
+    engine: E,
+    thread_pool: T,
+    max_request_size: usize,
+    read_timeout: Duration,
+}
+
+impl<E: KvsEngine, T: ThreadPool> HttpServer<E, T> {
+    /// Creates a server with a logger, a listening address, a store engine and a thread pool.
+    pub fn new(logger: Logger, addr: SocketAddr, engine: E, thread_pool: T) -> Result<Self> {
+        let server = Server::http(addr).map_err(|e| Error::ServerError(e.to_string()))?;
+        Ok(HttpServer {
+            logger,
+            server,
+            engine,
+            thread_pool,
+            max_request_size: MAX_REQUEST_SIZE,
+            read_timeout: READ_TIMEOUT,
+        })
+    }
+
+    /// Sets the limit of the length of one value in bytes, which is 16 MiB by default,
+    /// larger values are answered with `413 Payload Too Large`.
+    pub fn set_max_request_size(&mut self, size: usize) {
+        self.max_request_size = size;
+    }
+
+    /// Sets the limit of the time a thread of the pool waits for the body of one request,
+    /// which is 30 seconds by default, slower bodies are answered with `408 Request Timeout`.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    /// Starts receiving requests and replying responses.
+    ///
+    /// Each request is handled by one thread of the pool,
+    /// and quits after receiving `N` requests with `Some(N)` as `tasks`,
+    /// or keeps running if `tasks` is `None`.
+    pub fn run(&mut self, tasks: Option<usize>) -> Result<()> {
+        let mut tasks_cnt = 0;
+        for request in self.server.incoming_requests() {
+            info!(
+                self.logger,
+                "Received HTTP request: {} {}",
+                request.method(),
+                request.url()
+            );
+
+            let logger = self.logger.clone();
+            let engine = self.engine.clone();
+            let max_request_size = self.max_request_size;
+            let read_timeout = self.read_timeout;
+            self.thread_pool.spawn(move || {
+                if let Err(e) = handle(&logger, engine, request, max_request_size, read_timeout) {
+                    info!(logger, "HTTP response failed: {}", e);
+                }
+            });
+
+            tasks_cnt += 1;
+            if let Some(tasks) = tasks {
+                if tasks_cnt >= tasks {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Gets the local address of the server
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.server_addr().to_ip().unwrap()
+    }
+}
+
+// A type that represents the responses before they are written
+enum Reply {
+    Json(u16, serde_json::Value),
+    Empty(u16),
+    // 405 with the allowed methods
+    NotAllowed(&'static str, serde_json::Value),
+}
+
+impl Reply {
+    fn error(status: u16, e: &Error) -> Self {
+        Reply::Json(status, json!({"error": e.name(), "message": e.to_string()}))
+    }
+}
+
+// receives the body, routes the request and responds it
+fn handle(
+    logger: &Logger,
+    engine: impl KvsEngine,
+    request: Request,
+    max_request_size: usize,
+    read_timeout: Duration,
+) -> Result<()> {
+    let (request, body) = if *request.method() == Method::Put {
+        match receive_body(logger, request, max_request_size, read_timeout) {
+            Some((request, body)) => (request, Some(body)),
+            None => {
+                info!(
+                    logger,
+                    "HTTP request body not received in {:?}", read_timeout
+                );
+                return Ok(());
+            }
+        }
+    } else {
+        (request, None)
+    };
+
+    let reply = route(engine, &request, body).unwrap_or_else(|e| Reply::error(status_of(&e), &e));
+    respond(logger, request, reply)
+}
+
+// reads the body in its own thread and waits for it at most the read timeout,
+// so that a slow client does not hold the thread of the pool for the whole upload,
+// where the reading thread itself answers the request once the body is read after the timeout
+fn receive_body(
+    logger: &Logger,
+    mut request: Request,
+    max_request_size: usize,
+    read_timeout: Duration,
+) -> Option<(Request, Result<String>)> {
+    // a rendezvous channel, whose send fails once the receiver stops waiting
+    let (sender, receiver) = crossbeam_channel::bounded(0);
+    let logger = logger.clone();
+    thread::spawn(move || {
+        let body = read_body(&mut request, max_request_size);
+        if let Err(SendError((request, _))) = sender.send((request, body)) {
+            let e = Error::IOError(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Request body not received in time",
+            ));
+            if let Err(e) = respond(&logger, request, Reply::error(408, &e)) {
+                info!(logger, "HTTP response failed: {}", e);
+            }
+        }
+    });
+    receiver.recv_timeout(read_timeout).ok()
+}
+
+// writes the reply as the response of the request
+fn respond(logger: &Logger, request: Request, reply: Reply) -> Result<()> {
+    let content_type = || Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = match reply {
+        Reply::Json(status, body) => {
+            info!(logger, "HTTP response: {} {}", status, body);
+            Response::from_string(body.to_string())
+                .with_status_code(status)
+                .with_header(content_type())
+                .boxed()
+        }
+        Reply::NotAllowed(allow, body) => {
+            info!(logger, "HTTP response: 405 {}", body);
+            Response::from_string(body.to_string())
+                .with_status_code(405)
+                .with_header(content_type())
+                .with_header(Header::from_bytes("Allow", allow).unwrap())
+                .boxed()
+        }
+        Reply::Empty(status) => {
+            info!(logger, "HTTP response: {}", status);
+            Response::empty(status).boxed()
+        }
+    };
+    request.respond(response)?;
+    Ok(())
+}
+
+// dispatches the request by its method and path, where the body is only received for PUT
+fn route(engine: impl KvsEngine, request: &Request, body: Option<Result<String>>) -> Result<Reply> {
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_owned(), parse_query(query)?),
+        None => (request.url().to_owned(), Vec::new()),
+    };
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let engine = engine.open_namespace(param("ns").unwrap_or_default())?;
+
+    if path == "/keys" {
+        return match request.method() {
+            Method::Get => {
+                let limit = match param("limit") {
+                    Some(limit) => limit
+                        .parse::<usize>()
+                        .map_err(|_| Error::InvalidCommand(format!("Invalid limit: {}", limit)))?
+                        .min(MAX_SCAN_LIMIT),
+                    None => SCAN_LIMIT,
+                };
+                let pairs =
+                    engine.scan(param("prefix").unwrap_or_default(), param("after"), limit)?;
+                // a full page may be followed by more pairs
+                let next = match pairs.last() {
+                    Some((key, _)) if pairs.len() == limit => Some(key.clone()),
+                    _ => None,
+                };
+                let items: Vec<_> = pairs
+                    .into_iter()
+                    .map(|(key, value)| json!({"key": key, "value": value}))
+                    .collect();
+                Ok(Reply::Json(200, json!({"items": items, "next": next})))
+            }
+            method => Ok(not_allowed(method, &path, "GET")),
+        };
+    }
+
+    let key = match path.strip_prefix("/keys/") {
+        Some(key) if !key.is_empty() => decode(key)?,
+        _ => {
+            let e = Error::InvalidCommand(format!("No route for {} {}", request.method(), path));
+            return Ok(Reply::error(404, &e));
+        }
+    };
+    match request.method() {
+        Method::Get => match engine.get(key.clone())? {
+            Some(value) => Ok(Reply::Json(200, json!({"key": key, "value": value}))),
+            None => Err(Error::KeyNotFound),
+        },
+        Method::Put => {
+            let value = body.unwrap_or_else(|| Ok(String::new()))?;
+            engine.set(key, value)?;
+            Ok(Reply::Empty(204))
+        }
+        Method::Delete => {
+            engine.remove(key)?;
+            Ok(Reply::Empty(204))
+        }
+        method => Ok(not_allowed(method, &path, "GET, PUT, DELETE")),
+    }
+}
+
+// reads the body as the value from the stream of the request, without buffering the raw request,
+// and fails as soon as it is longer than the given limit
+fn read_body(request: &mut Request, max_request_size: usize) -> Result<String> {
+    if let Some(len) = request.body_length().filter(|&len| len > max_request_size) {
+        return Err(Error::RequestTooLarge(len));
+    }
+
+    let mut value = Vec::with_capacity(request.body_length().unwrap_or_default());
+    let len = request
+        .as_reader()
+        .take(max_request_size as u64 + 1)
+        .read_to_end(&mut value)?;
+    if len > max_request_size {
+        return Err(Error::RequestTooLarge(len));
+    }
+    String::from_utf8(value).map_err(|_| Error::SerdeError(String::from("Expected utf-8")))
+}
+
+// parses the pairs of the query, where `+` means a space
+fn parse_query(query: &str) -> Result<Vec<(String, String)>> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((
+                decode(&key.replace('+', " "))?,
+                decode(&value.replace('+', " "))?,
+            ))
+        })
+        .collect()
+}
+
+fn decode(s: &str) -> Result<String> {
+    percent_decode_str(s)
+        .decode_utf8()
+        .map(|s| s.into_owned())
+        .map_err(|_| Error::SerdeError(String::from("Expected utf-8")))
+}
+
+fn not_allowed(method: &Method, path: &str, allow: &'static str) -> Reply {
+    let e = Error::InvalidCommand(format!("Method {} not allowed for {}", method, path));
+    Reply::NotAllowed(allow, json!({"error": e.name(), "message": e.to_string()}))
+}
+
+// maps the error to the status code of HTTP
+fn status_of(e: &Error) -> u16 {
+    match e {
+        Error::KeyNotFound => 404,
+        Error::InvalidCommand(_) | Error::SerdeError(_) => 400,
+        Error::RequestTooLarge(_) => 413,
+        Error::ReadOnly | Error::PermissionDenied(_) => 403,
+        _ => 500,
+    }
+}
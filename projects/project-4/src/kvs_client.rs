@@ -1,35 +1,316 @@
-use crate::{Command, Response, Result};
+use crate::{
+    codec::{BINARY, MAX_PREFIX_LEN},
+    kvs_engine::Event,
+    stream::Stream,
+    tls::ClientConfig,
+    Codec, Command, Error, Handshake, Request, Response, Result, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
+};
 
+use serde::Serialize;
 use std::{
-    io::{Read, Write},
-    net::{SocketAddr, TcpStream},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::Arc,
+    thread,
 };
 
-/// A type that abstracts the kvs client.
+/// A type that abstracts the kvs client, which sends any number of commands on one connection.
 pub struct KvsClient {
-    stream: TcpStream,
+    stream: Stream,
+    // reads the responses from the same connection
+    reader: BufReader<Stream>,
+    namespace: String,
+    handshake: Handshake,
+    codec: Codec,
 }
 
 impl KvsClient {
-    /// Creates a client that connects the server with the given `addr`.
+    /// Creates a client that connects the server with the given `addr`,
+    /// and agrees on the version and the features of the protocol with the server.
+    ///
+    /// The binary codec is used if the server supports it.
+    /// Returns `Error::IncompatibleProtocol` if the server cannot speak any version this client speaks.
     pub fn connect(addr: SocketAddr) -> Result<Self> {
-        let stream = TcpStream::connect(addr)?;
-        Ok(KvsClient { stream })
+        KvsClient::connect_with(addr, Codec::Binary)
+    }
+
+    /// Creates a client like `connect`, which never uses the binary codec if `codec` is `Codec::Text`.
+    pub fn connect_with(addr: SocketAddr, codec: Codec) -> Result<Self> {
+        KvsClient::start(Stream::Tcp(TcpStream::connect(addr)?), codec)
+    }
+
+    /// Creates a client like `connect` on a connection encrypted by TLS in the given configuration,
+    /// which can be built by [`tls::client_config`](crate::tls::client_config).
+    ///
+    /// The certificate of the server must be valid for `server_name`, which is either a DNS name or an IP.
+    pub fn connect_tls(
+        addr: SocketAddr,
+        server_name: &str,
+        config: Arc<ClientConfig>,
+    ) -> Result<Self> {
+        let stream = Stream::connect(TcpStream::connect(addr)?, server_name, config)?;
+        KvsClient::start(stream, Codec::Binary)
+    }
+
+    // starts the protocol on the connection with the handshake
+    fn start(mut stream: Stream, codec: Codec) -> Result<Self> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        let mut handshake = Handshake::new();
+        if codec == Codec::Text {
+            handshake.features.retain(|feature| feature != BINARY);
+        }
+        write_frame(&mut stream, Codec::Text, &handshake)?;
+        let handshake = match read_frame(&mut reader, Codec::Text) {
+            Ok(Some(Response::Handshake(handshake))) => handshake,
+            Ok(Some(Response::Fail(msg))) => return Err(Error::IncompatibleProtocol(msg)),
+            // other failures of the connection, such as TLS rejecting the certificate of the client
+            Err(Error::IOError(e)) if e.kind() != io::ErrorKind::UnexpectedEof => {
+                return Err(Error::IOError(e))
+            }
+            // servers older than the handshake close the connection for the unknown request,
+            // or reply something that is not a frame
+            Ok(None) | Err(Error::IOError(_) | Error::SerdeError(_)) => {
+                return Err(Error::IncompatibleProtocol(String::from(
+                    "The server does not support handshakes",
+                )))
+            }
+            Err(e) => return Err(e),
+            Ok(Some(_)) => return Err(Error::SerdeError(String::from("Unexpected response"))),
+        };
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&handshake.version) {
+            return Err(Error::IncompatibleProtocol(format!(
+                "Unsupported protocol version {}",
+                handshake.version
+            )));
+        }
+
+        Ok(KvsClient {
+            stream,
+            reader,
+            namespace: String::new(),
+            codec: Codec::of(&handshake),
+            handshake,
+        })
+    }
+
+    /// Returns the version and the features of the protocol agreed with the server.
+    pub fn handshake(&self) -> &Handshake {
+        &self.handshake
+    }
+
+    /// Authenticates the connection with the password of the user, or the token if `user` is `None`,
+    /// returns the name of the authenticated user.
+    ///
+    /// Returns `Error::Unauthenticated` if the server rejects the credentials.
+    pub fn authenticate(&mut self, user: Option<&str>, secret: &str) -> Result<String> {
+        let command = Command::Auth {
+            user: user.map(str::to_owned),
+            secret: secret.to_owned(),
+        };
+        match self.send(command)? {
+            Response::SuccessAuth(user) => Ok(user),
+            Response::Unauthenticated(msg) => Err(Error::Unauthenticated(msg)),
+            Response::Fail(msg) => Err(Error::ServerError(msg)),
+            _ => Err(Error::SerdeError(String::from("Unexpected response"))),
+        }
+    }
+
+    /// Sets the namespace where the following commands run, the empty string means the default namespace.
+    pub fn set_namespace(&mut self, namespace: impl Into<String>) {
+        self.namespace = namespace.into();
     }
 
-    /// Sends the given `command` to the server
+    /// Sends the given `command` to the server and waits for its response,
+    /// the connection is kept for the following commands.
     pub fn send(&mut self, command: Command) -> Result<Response> {
-        let buffer = crate::ser::to_string(&command)?;
-        self.stream
-            .write_all(format!("{}#{}", buffer.len(), buffer).as_bytes())?;
+        self.write_command(command)?;
+        read_frame(&mut self.reader, self.codec)?.ok_or_else(connection_closed)
+    }
+
+    /// Closes the connection, after which the server stops serving it.
+    pub fn close(self) -> Result<()> {
+        self.stream.shutdown(Shutdown::Both)?;
+        Ok(())
+    }
+
+    /// Watches the given `key`, or the keys starting with it if `prefix` is true,
+    /// returns the stream of change events sent by the server.
+    pub fn watch(mut self, key: String, prefix: bool) -> Result<EventStream> {
+        self.write_command(Command::Watch { key, prefix })?;
+
+        let mut events = EventStream {
+            reader: self.reader,
+            codec: self.codec,
+        };
+        match read_frame(&mut events.reader, events.codec)? {
+            Some(Response::SuccessWatch()) => Ok(events),
+            Some(Response::Fail(msg)) => Err(Error::ServerError(msg)),
+            Some(Response::Unauthenticated(msg)) => Err(Error::Unauthenticated(msg)),
+            Some(Response::PermissionDenied(msg)) => Err(Error::PermissionDenied(msg)),
+            _ => Err(Error::SerdeError(String::from("Unexpected response"))),
+        }
+    }
+
+    /// Starts a pipeline, whose commands are sent without waiting for each response.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use kvs::{Command, KvsClient};
+    ///
+    /// # fn main() -> kvs::Result<()> {
+    /// let mut client = KvsClient::connect("127.0.0.1:4000".parse().unwrap())?;
+    /// let responses = client
+    ///     .pipeline()
+    ///     .command(Command::Set { key: "k".to_owned(), value: "v".to_owned() })
+    ///     .command(Command::Get { key: "k".to_owned() })
+    ///     .send()?;
+    /// assert_eq!(responses.len(), 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pipeline(&mut self) -> Pipeline<'_> {
+        Pipeline {
+            client: self,
+            commands: Vec::new(),
+        }
+    }
+
+    fn write_command(&mut self, command: Command) -> Result<()> {
+        write_command(&mut self.stream, self.codec, &self.namespace, command)
+    }
+}
+
+/// A builder that collects commands and sends them on the connection of the client at once.
+pub struct Pipeline<'a> {
+    client: &'a mut KvsClient,
+    commands: Vec<Command>,
+}
+
+impl Pipeline<'_> {
+    /// Appends the given `command`, which cannot be `Command::Watch`.
+    pub fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    /// Sends all commands in the namespace of the client, returns their responses in the same order.
+    pub fn send(self) -> Result<Vec<Response>> {
+        if self
+            .commands
+            .iter()
+            .any(|command| matches!(command, Command::Watch { .. }))
+        {
+            return Err(Error::InvalidCommand(String::from(
+                "Watches cannot be pipelined",
+            )));
+        }
+
+        let Pipeline { client, commands } = self;
+        let count = commands.len();
+        let mut writer = BufWriter::new(client.stream.try_clone()?);
+        let namespace = &client.namespace;
+        let codec = client.codec;
+        let reader = &mut client.reader;
+        // writes in another thread while reading responses,
+        // so that neither side blocks on a full socket buffer while the other is writing
+        thread::scope(|scope| {
+            let sender = scope.spawn(move || -> Result<()> {
+                for command in commands {
+                    write_command(&mut writer, codec, namespace, command)?;
+                }
+                writer.flush()?;
+                Ok(())
+            });
+
+            let mut responses = Vec::with_capacity(count);
+            while responses.len() < count {
+                match read_frame(reader, codec)? {
+                    Some(response) => responses.push(response),
+                    None => return Err(connection_closed()),
+                }
+            }
+            sender.join().unwrap()?;
+            Ok(responses)
+        })
+    }
+}
+
+// writes the command in the namespace,
+// which is sent alone for the default namespace in the text codec
+fn write_command(
+    writer: &mut impl Write,
+    codec: Codec,
+    namespace: &str,
+    command: Command,
+) -> Result<()> {
+    if codec == Codec::Text && namespace.is_empty() {
+        write_frame(writer, codec, &command)
+    } else {
+        let request = Request {
+            namespace: namespace.to_owned(),
+            command,
+        };
+        write_frame(writer, codec, &request)
+    }
+}
+
+// writes the value in the codec with its length
+fn write_frame(writer: &mut impl Write, codec: Codec, value: &impl Serialize) -> Result<()> {
+    let payload = codec.encode(value)?;
+    let mut frame = format!("{}#", payload.len()).into_bytes();
+    frame.extend_from_slice(&payload);
+    writer.write_all(&frame)?;
+    Ok(())
+}
+
+fn connection_closed() -> Error {
+    Error::IOError(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Connection closed by the server",
+    ))
+}
 
-        // Error with shutdown, may cause more unexpected RST
-        // see https://stackoverflow.com/questions/70796728/why-does-shutdown-write-in-the-client-cause-the-connection-to-be-closed
-        // self.stream.shutdown(std::net::Shutdown::Write)?;
+/// An iterator over the change events of a watch, which blocks until the next event
+/// and ends when the server closes the connection.
+pub struct EventStream {
+    reader: BufReader<Stream>,
+    codec: Codec,
+}
+
+impl Iterator for EventStream {
+    type Item = Result<Event>;
 
-        let mut buffer = Vec::new();
-        self.stream.read_to_end(&mut buffer)?;
+    fn next(&mut self) -> Option<Self::Item> {
+        match read_frame(&mut self.reader, self.codec) {
+            Ok(Some(Response::Event(event))) => Some(Ok(event)),
+            Ok(Some(_)) => Some(Err(Error::SerdeError(String::from("Unexpected response")))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
 
-        crate::de::from_str(std::str::from_utf8(&buffer).unwrap())
+// reads one response written in the codec with its length, returns None if the connection is closed
+fn read_frame(reader: &mut BufReader<Stream>, codec: Codec) -> Result<Option<Response>> {
+    let mut buffer = Vec::new();
+    let len = reader
+        .by_ref()
+        .take(MAX_PREFIX_LEN)
+        .read_until(b'#', &mut buffer)?;
+    if len == 0 {
+        return Ok(None);
     }
+
+    let len: usize = buffer
+        .strip_suffix(b"#")
+        .and_then(|len| std::str::from_utf8(len).ok())
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| Error::SerdeError(String::from("Expected length")))?;
+    let mut buffer = vec![0; len];
+    reader.read_exact(&mut buffer)?;
+
+    codec.decode(&buffer).map(Some)
 }
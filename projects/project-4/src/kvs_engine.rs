@@ -1,17 +1,266 @@
 //! A module for store engines.
 
+#[cfg(feature = "async")]
+mod async_kvs_engine;
 mod kv_store;
+mod lsm_kvs_engine;
+mod mem_kvs_engine;
+mod sharded_kv_store;
 mod sled_kvs_engine;
 
-pub use kv_store::KvStore;
+#[cfg(feature = "async")]
+pub use async_kvs_engine::AsyncKvsEngine;
+pub use kv_store::{CheckReport, CompactionStats, KvStore, KvStoreOptions, Retention, Version};
+pub use lsm_kvs_engine::LsmKvsEngine;
+pub use mem_kvs_engine::MemKvsEngine;
+pub use sharded_kv_store::ShardedKvStore;
 pub use sled_kvs_engine::SledKvsEngine;
 
 use crate::Result;
 
-use std::path::PathBuf;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Select, Sender};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
 
-/// A trait for persistent store engines,
-/// which provides methods `open`, `set`, `get` and `remove`.
+// the number of events a watcher may fall behind, after which it is disconnected
+const WATCH_CAPACITY: usize = 1024;
+
+/// A type that represents a change of a key, which is either set ([`Set`]) or rm ([`Rm`]).
+///
+/// [`Set`]: Event::Set
+/// [`Rm`]: Event::Rm
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Contains the key and its new value
+    Set { key: String, value: String },
+    /// Contains the removed key
+    Rm { key: String },
+}
+
+impl Event {
+    /// Gets the key changed by the event.
+    pub fn key(&self) -> &str {
+        match self {
+            Event::Set { key, .. } => key,
+            Event::Rm { key } => key,
+        }
+    }
+}
+
+/// An iterator that blocks until the next change of the watched keys,
+/// and ends if the engine is dropped or the watcher falls more than 1024 events behind.
+pub struct Watcher(Events);
+
+// the sources of events, which are either the channels sent by engines, or the subscriber of sled
+enum Events {
+    // the channels with whether one of them is disconnected,
+    // after which only the events already sent by the others are received
+    Channels(Vec<Receiver<Event>>, bool),
+    Sled(sled::Subscriber),
+}
+
+impl Watcher {
+    pub(crate) fn new(receiver: Receiver<Event>) -> Self {
+        Watcher(Events::Channels(vec![receiver], false))
+    }
+
+    // merges the watchers of the shards of an engine, whose events are all sent by channels
+    pub(crate) fn merge(watchers: impl IntoIterator<Item = Watcher>) -> Self {
+        let receivers = watchers
+            .into_iter()
+            .flat_map(|watcher| match watcher.0 {
+                Events::Channels(receivers, _) => receivers,
+                Events::Sled(_) => unreachable!("sled is never sharded"),
+            })
+            .collect();
+        Watcher(Events::Channels(receivers, false))
+    }
+
+    pub(crate) fn sled(subscriber: sled::Subscriber) -> Self {
+        Watcher(Events::Sled(subscriber))
+    }
+
+    // waits for the next change for at most the timeout, or until the engine is dropped
+    pub(crate) fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> std::result::Result<Event, RecvTimeoutError> {
+        self.recv(Some(timeout))
+    }
+
+    fn recv(&mut self, timeout: Option<Duration>) -> std::result::Result<Event, RecvTimeoutError> {
+        match &mut self.0 {
+            Events::Channels(receivers, disconnected) => loop {
+                // a merged watcher ends with any of its channels, so that no shard is missed silently
+                if *disconnected {
+                    while let Some(receiver) = receivers.last() {
+                        match receiver.try_recv() {
+                            Ok(event) => return Ok(event),
+                            Err(_) => {
+                                receivers.pop();
+                            }
+                        }
+                    }
+                }
+                if receivers.is_empty() {
+                    return Err(RecvTimeoutError::Disconnected);
+                }
+                let mut select = Select::new();
+                for receiver in receivers.iter() {
+                    select.recv(receiver);
+                }
+                let operation = match timeout {
+                    Some(timeout) => select
+                        .select_timeout(timeout)
+                        .map_err(|_| RecvTimeoutError::Timeout)?,
+                    None => select.select(),
+                };
+                let index = operation.index();
+                match operation.recv(&receivers[index]) {
+                    Ok(event) => return Ok(event),
+                    // forgets the channels whose engines are dropped or which fall behind
+                    Err(_) => {
+                        receivers.swap_remove(index);
+                        *disconnected = true;
+                    }
+                }
+            },
+            Events::Sled(subscriber) => {
+                let event = match timeout {
+                    Some(timeout) => subscriber.next_timeout(timeout).map_err(|e| match e {
+                        mpsc::RecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+                        mpsc::RecvTimeoutError::Disconnected => RecvTimeoutError::Disconnected,
+                    })?,
+                    None => subscriber.next().ok_or(RecvTimeoutError::Disconnected)?,
+                };
+                Ok(sled_kvs_engine::event_of(event))
+            }
+        }
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        self.recv(None).ok()
+    }
+}
+
+// the watchers of engines that send the events by themselves,
+// which are the namespaces and prefixes of watched keys with the senders of their events
+#[derive(Default)]
+struct Watchers(Vec<(String, String, Sender<Event>)>);
+
+impl Watchers {
+    // adds a watcher of the keys starting with `prefix` in the namespace
+    fn watch(&mut self, namespace: &str, prefix: &str) -> Watcher {
+        let (sender, receiver) = crossbeam_channel::bounded(WATCH_CAPACITY);
+        self.0
+            .push((namespace.to_owned(), prefix.to_owned(), sender));
+        Watcher::new(receiver)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // sends the event to the matched watchers without blocking, and forgets the ones
+    // whose receivers are dropped or full, which disconnects the watchers that fall behind
+    fn notify(&mut self, namespace: &str, event: Event) {
+        self.0.retain(|(ns, prefix, sender)| {
+            ns != namespace
+                || !event.key().starts_with(prefix.as_str())
+                || sender.try_send(event.clone()).is_ok()
+        });
+    }
+}
+
+// the thread that runs the compactions of an engine in the background when they are requested,
+// which finishes the requested compaction before it is dropped
+struct Compactor {
+    state: Arc<(Mutex<CompactorState>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct CompactorState {
+    // requests made during a compaction are merged into the next one
+    requested: bool,
+    running: bool,
+    closed: bool,
+}
+
+impl Compactor {
+    fn spawn(mut compact: impl FnMut() + Send + 'static) -> Self {
+        let state = Arc::new((Mutex::new(CompactorState::default()), Condvar::new()));
+        let shared = Arc::clone(&state);
+        let handle = thread::spawn(move || {
+            let (lock, cvar) = &*shared;
+            loop {
+                let mut state = cvar
+                    .wait_while(lock.lock().unwrap(), |state| {
+                        !state.requested && !state.closed
+                    })
+                    .unwrap();
+                if !state.requested {
+                    return;
+                }
+                state.requested = false;
+                state.running = true;
+                drop(state);
+
+                compact();
+                lock.lock().unwrap().running = false;
+                cvar.notify_all();
+            }
+        });
+        Compactor {
+            state,
+            handle: Some(handle),
+        }
+    }
+
+    fn request(&self) {
+        let (lock, cvar) = &*self.state;
+        lock.lock().unwrap().requested = true;
+        cvar.notify_all();
+    }
+
+    // waits until the requested compactions are done
+    fn wait(&self) {
+        let (lock, cvar) = &*self.state;
+        let _state = cvar
+            .wait_while(lock.lock().unwrap(), |state| {
+                state.requested || state.running
+            })
+            .unwrap();
+    }
+}
+
+impl Drop for Compactor {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.state;
+        lock.lock().unwrap().closed = true;
+        cvar.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// A trait for store engines,
+/// which provides methods `open`, `set`, `get`, `remove`, `for_each`, `load`,
+/// `open_namespace`, `namespaces` and `watch`.
+///
+/// Each handle works on the keys of one namespace,
+/// where the handle returned by `open` works on the default namespace named by the empty string.
 pub trait KvsEngine: Clone + Send + 'static {
     /// Opens a store engine from the given path
     fn open(path: impl Into<PathBuf>) -> Result<Self>;
@@ -24,4 +273,45 @@ pub trait KvsEngine: Clone + Send + 'static {
 
     /// Removes a given string key.
     fn remove(&self, key: String) -> Result<()>;
+
+    /// Calls `f` with each live key and its value in no particular order,
+    /// stops at the first error returned by `f`.
+    fn for_each(&self, f: impl FnMut(String, String) -> Result<()>) -> Result<()>;
+
+    /// Gets at most `limit` live keys starting with `prefix` and their values in order of keys,
+    /// which are after the key `after` if given, so that pages are scanned by passing the last key.
+    ///
+    /// The default implementation visits every key with `for_each` and keeps only `limit` pairs.
+    fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let mut pairs = BTreeMap::new();
+        self.for_each(|key, value| {
+            if key.starts_with(prefix) && after.is_none_or(|after| key.as_str() > after) {
+                pairs.insert(key, value);
+                if pairs.len() > limit {
+                    pairs.pop_last();
+                }
+            }
+            Ok(())
+        })?;
+        Ok(pairs.into_iter().collect())
+    }
+
+    /// Sets all the given key-value pairs but only flushes once at the end,
+    /// returns the number of pairs loaded.
+    fn load(&self, pairs: impl IntoIterator<Item = Result<(String, String)>>) -> Result<usize>;
+
+    /// Gets a handle of the same engine that works on the keys of the namespace `name`.
+    fn open_namespace(&self, name: &str) -> Result<Self>;
+
+    /// Lists the names of namespaces in the store in order, which may contain the empty ones.
+    fn namespaces(&self) -> Result<Vec<String>>;
+
+    /// Watches the changes of the keys starting with `prefix`,
+    /// where a single key is watched by using it as the prefix and comparing the keys of events.
+    fn watch(&self, prefix: &str) -> Result<Watcher>;
 }
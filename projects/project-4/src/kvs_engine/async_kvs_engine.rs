@@ -0,0 +1,56 @@
+use super::KvsEngine;
+use crate::Result;
+
+use std::{future::Future, panic, path::PathBuf};
+use tokio::task;
+
+/// A trait for store engines used in async tasks,
+/// which provides methods `open_async`, `set_async`, `get_async` and `remove_async`.
+///
+/// It is implemented for every [`KvsEngine`] that is `Sync`, whose blocking calls run on the
+/// blocking pool of the tokio runtime, so the methods must be called within a runtime.
+/// The names differ from the ones of [`KvsEngine`], so that both traits can be used together.
+pub trait AsyncKvsEngine: Clone + Send + Sync + 'static {
+    /// Opens the store at the given path.
+    fn open_async(path: impl Into<PathBuf>) -> impl Future<Output = Result<Self>> + Send;
+
+    /// Sets the given value with the given key.
+    fn set_async(&self, key: String, value: String) -> impl Future<Output = Result<()>> + Send;
+
+    /// Gets the corresponding value of the given key, return None if the key not exists.
+    fn get_async(&self, key: String) -> impl Future<Output = Result<Option<String>>> + Send;
+
+    /// Removes the given key and the corresponding value.
+    fn remove_async(&self, key: String) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl<E: KvsEngine + Sync> AsyncKvsEngine for E {
+    fn open_async(path: impl Into<PathBuf>) -> impl Future<Output = Result<Self>> + Send {
+        let path = path.into();
+        blocking(move || E::open(path))
+    }
+
+    fn set_async(&self, key: String, value: String) -> impl Future<Output = Result<()>> + Send {
+        let engine = self.clone();
+        blocking(move || engine.set(key, value))
+    }
+
+    fn get_async(&self, key: String) -> impl Future<Output = Result<Option<String>>> + Send {
+        let engine = self.clone();
+        blocking(move || engine.get(key))
+    }
+
+    fn remove_async(&self, key: String) -> impl Future<Output = Result<()>> + Send {
+        let engine = self.clone();
+        blocking(move || engine.remove(key))
+    }
+}
+
+// runs the given function on the blocking pool, and resumes its panic in the calling task
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    match task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,40 +1,580 @@
-use super::KvsEngine;
-use crate::{Command, Error, Result};
+mod manifest;
 
+use super::{Compactor, Event, KvsEngine, Watcher, Watchers};
+use crate::{Error, Result};
+use manifest::Manifest;
+
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    fs::{self, copy, File, OpenOptions},
-    io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
-    path::PathBuf,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicPtr, Ordering},
-        Arc, Mutex,
+        Arc, Mutex, MutexGuard,
     },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use walkdir::WalkDir;
 
 const SINGLE_FILE_SIZE: u64 = 1024 * 1024;
-const UNUSED_LIMIT: usize = 1024;
+// the file locked by the process that opens the store
+const LOCK_FILE: &str = "LOCK";
+// the file shared by read-only stores, which the writer locks to remove the files replaced by compaction
+const READERS_FILE: &str = "READERS";
+
+// the record in kvs.data.* files, where `seq` and `ts` are missing in records written before versioning,
+// so that they are read as zero
+#[derive(Serialize, Deserialize, Debug)]
+enum Record {
+    Set {
+        key: String,
+        value: String,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        ns: String,
+        #[serde(default)]
+        seq: u64,
+        #[serde(default)]
+        ts: u64,
+    },
+    Rm {
+        key: String,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        ns: String,
+        #[serde(default)]
+        seq: u64,
+        #[serde(default)]
+        ts: u64,
+    },
+}
+
+impl Record {
+    // gets the namespace and the key of the record
+    fn ns_key(&self) -> (&String, &String) {
+        match self {
+            Record::Set { ns, key, .. } | Record::Rm { ns, key, .. } => (ns, key),
+        }
+    }
+
+    fn seq(&self) -> u64 {
+        match self {
+            Record::Set { seq, .. } | Record::Rm { seq, .. } => *seq,
+        }
+    }
+
+    // gets the milliseconds since the Unix epoch when the record was written
+    fn ts(&self) -> u64 {
+        match self {
+            Record::Set { ts, .. } | Record::Rm { ts, .. } => *ts,
+        }
+    }
+
+    // converts the record into the namespace and the event of the change
+    fn into_event(self) -> (String, Event) {
+        match self {
+            Record::Set { key, value, ns, .. } => (ns, Event::Set { key, value }),
+            Record::Rm { key, ns, .. } => (ns, Event::Rm { key }),
+        }
+    }
+
+    fn into_version(self) -> Version {
+        match self {
+            Record::Set { value, seq, ts, .. } => Version {
+                seq,
+                timestamp: UNIX_EPOCH + Duration::from_millis(ts),
+                value: Some(value),
+            },
+            Record::Rm { seq, ts, .. } => Version {
+                seq,
+                timestamp: UNIX_EPOCH + Duration::from_millis(ts),
+                value: None,
+            },
+        }
+    }
+}
 
-// the lock-free reader that only contains the index map
-struct KvStoreReader(HashMap<String, (u64, u64)>);
+// the index maps each namespace to the file numbers and offsets of the records of its keys
+type Index = HashMap<String, HashMap<String, (u64, u64)>>;
+
+// the history maps each namespace to the positions of the retained old records of its keys,
+// from the oldest to the newest, which includes rm-records and keys that have been removed
+type History = HashMap<String, HashMap<String, VecDeque<(u64, u64)>>>;
+
+// the lock-free reader that only contains the index map and the history
+#[derive(Clone, Default)]
+struct KvStoreReader {
+    index: Index,
+    history: History,
+}
 
 impl KvStoreReader {
-    // gets a raw pointer of Arc<KvStoreReader> from the given map
-    fn raw_arc(index: HashMap<String, (u64, u64)>) -> *mut Arc<KvStoreReader> {
-        Box::into_raw(Box::new(Arc::new(KvStoreReader(index))))
+    // gets a raw pointer of Arc<KvStoreReader> from the given reader
+    fn raw_arc(self) -> *mut Arc<KvStoreReader> {
+        Box::into_raw(Box::new(Arc::new(self)))
+    }
+
+    // applies the record at the given position, keeps at most `versions` old records for the key,
+    // returns the positions of records that are no longer used
+    fn apply(&mut self, n: u64, pos: u64, record: &Record, versions: usize) -> Vec<(u64, u64)> {
+        let (ns, key) = record.ns_key();
+        let mut replaced = Vec::new();
+        match record {
+            Record::Set { .. } => {
+                let keys = self.index.entry(ns.clone()).or_default();
+                replaced.extend(keys.insert(key.clone(), (n, pos)));
+            }
+            Record::Rm { .. } => {
+                if let Some(keys) = self.index.get_mut(ns) {
+                    replaced.extend(keys.remove(key));
+                    if keys.is_empty() {
+                        self.index.remove(ns);
+                    }
+                }
+                replaced.push((n, pos));
+            }
+        }
+
+        if versions == 0 {
+            return replaced;
+        }
+        let old = self
+            .history
+            .entry(ns.clone())
+            .or_default()
+            .entry(key.clone())
+            .or_default();
+        old.extend(replaced);
+        // the rm-record of a removed key is its current version rather than an old one
+        let current = usize::from(matches!(record, Record::Rm { .. }));
+        let unused = old.len().saturating_sub(versions + current);
+        old.drain(..unused).collect()
+    }
+
+    // checks whether the record at the given position is still used by the index or the history
+    fn is_used(&self, n: u64, pos: u64, record: &Record) -> (bool, bool) {
+        let (ns, key) = record.ns_key();
+        let live = self.index.get(ns).and_then(|keys| keys.get(key)) == Some(&(n, pos));
+        let retained = self
+            .history
+            .get(ns)
+            .and_then(|keys| keys.get(key))
+            .is_some_and(|old| old.contains(&(n, pos)));
+        (live, retained)
+    }
+
+    // checks whether the key of the record has a live value
+    fn is_live(&self, record: &Record) -> bool {
+        let (ns, key) = record.ns_key();
+        self.index
+            .get(ns)
+            .is_some_and(|keys| keys.contains_key(key))
+    }
+
+    // replaces the position of the record of the key in the index and the history with the new one,
+    // or forgets the record in the history if the new one is None
+    fn relocate(&mut self, from: (u64, u64), to: Option<(u64, u64)>, ns: &str, key: &str) {
+        if let Some(latest) = self.index.get_mut(ns).and_then(|keys| keys.get_mut(key)) {
+            if let (true, Some(to)) = (*latest == from, to) {
+                *latest = to;
+            }
+        }
+        if let Some(keys) = self.history.get_mut(ns) {
+            if let Some(old) = keys.get_mut(key) {
+                match to {
+                    Some(to) => old
+                        .iter_mut()
+                        .filter(|pos| **pos == from)
+                        .for_each(|pos| *pos = to),
+                    None => old.retain(|pos| *pos != from),
+                }
+                if old.is_empty() {
+                    keys.remove(key);
+                }
+            }
+            if keys.is_empty() {
+                self.history.remove(ns);
+            }
+        }
+    }
+
+    // gets the positions of all retained records of the key from the newest to the oldest
+    fn versions<'a>(&'a self, ns: &str, key: &str) -> impl Iterator<Item = (u64, u64)> + 'a {
+        let latest = self.index.get(ns).and_then(|keys| keys.get(key));
+        let old = self.history.get(ns).and_then(|keys| keys.get(key));
+        latest
+            .into_iter()
+            .chain(old.into_iter().flat_map(|old| old.iter().rev()))
+            .copied()
     }
 }
 
 struct KvStoreWriter {
-    // base_file is current base file number after the last compaction
-    base_file: u64,
-    // active_file is the file number that new logs will be written in
-    active_file: u64,
-    // active_writer is the writer for active file
-    active_writer: BufWriter<File>,
-    // unused represents current unused logs
-    unused: usize,
+    // segments are the file numbers in the MANIFEST, where the last one is the active file
+    segments: Vec<u64>,
+    // the number of the next new file, which is larger than the numbers of all files
+    next_file: u64,
+    // the files no longer in the MANIFEST, which are kept while read-only stores may read them
+    obsolete: Vec<u64>,
+    // active_writer is the writer for active file, which read-only stores do not have
+    active_writer: Option<RecordWriter>,
+    // counts the records of each segment and the ones no longer used
+    counts: HashMap<u64, SegmentCounts>,
+    // watchers are sent the events after the index is published
+    watchers: Watchers,
+    // the sequence number of the last written record
+    last_seq: u64,
+    // how many old records are kept in the history
+    retention: Retention,
+    // the maximum bytes per second rewritten by compaction
+    compaction_rate: Option<u64>,
+    // the dead ratio over which a segment is rewritten by compaction
+    compaction_threshold: f64,
+    compaction_stats: CompactionStats,
+    // the failure of the last compaction, which is returned by the next write
+    compaction_error: Option<Error>,
+}
+
+impl KvStoreWriter {
+    // gets the file number that new logs will be written in
+    fn active_file(&self) -> u64 {
+        *self.segments.last().unwrap()
+    }
+
+    fn active_writer(&mut self) -> Result<&mut RecordWriter> {
+        self.active_writer.as_mut().ok_or(Error::ReadOnly)
+    }
+
+    // takes the number for a new file, which is larger than the numbers of all segments
+    // and the files being written by compaction
+    fn new_file(&mut self) -> u64 {
+        self.next_file += 1;
+        self.next_file - 1
+    }
+
+    // counts the kept rm-records as dead if the segments before them no longer have the records they hide
+    fn recount_tombstones(&mut self) {
+        let mut first_dead: Option<u64> = None;
+        for n in &self.segments {
+            let counts = self.counts.entry(*n).or_default();
+            if counts.tombstones > 0 && first_dead.is_none_or(|seq| seq > counts.masks) {
+                for _ in 0..counts.tombstones {
+                    counts.kill(self.last_seq);
+                }
+                counts.tombstones = 0;
+            }
+            first_dead = first_dead.into_iter().chain(counts.first_dead).min();
+        }
+    }
+
+    // counts the record written in the given segment and the records no longer used
+    fn count(&mut self, n: u64, unused: Vec<(u64, u64)>) {
+        self.counts.entry(n).or_default().records += 1;
+        for (n, _) in unused {
+            self.counts.entry(n).or_default().kill(self.last_seq);
+        }
+    }
+
+    // builds the record of setting the key with the next sequence number
+    fn set_record(&mut self, ns: &str, key: String, value: String) -> Record {
+        self.last_seq += 1;
+        Record::Set {
+            key,
+            value,
+            ns: ns.to_owned(),
+            seq: self.last_seq,
+            ts: now(),
+        }
+    }
+
+    // builds the record of removing the key with the next sequence number
+    fn rm_record(&mut self, ns: &str, key: String) -> Record {
+        self.last_seq += 1;
+        Record::Rm {
+            key,
+            ns: ns.to_owned(),
+            seq: self.last_seq,
+            ts: now(),
+        }
+    }
+}
+
+// the number of records in a segment and the ones that are no longer used
+#[derive(Clone, Copy, Default)]
+struct SegmentCounts {
+    records: usize,
+    dead: usize,
+    // the rm-records kept by compaction only to hide the older records of their keys,
+    // which are counted as dead once the segments before them have no records unused since the newest one
+    tombstones: usize,
+    // the newest sequence number of the kept rm-records
+    masks: u64,
+    // the sequence number of the first write that left an unused record in the segment,
+    // so that only the rm-records since then may hide its records
+    first_dead: Option<u64>,
+}
+
+impl SegmentCounts {
+    // counts a record that is no longer used since the write of the given sequence number
+    fn kill(&mut self, seq: u64) {
+        self.dead += 1;
+        self.first_dead.get_or_insert(seq);
+    }
+
+    fn dead_ratio(&self) -> f64 {
+        if self.records == 0 {
+            0.0
+        } else {
+            self.dead as f64 / self.records as f64
+        }
+    }
+}
+
+// the reader of a read-only store that reads the records appended by the writer process
+#[derive(Default)]
+struct Follower {
+    // the segments that have been read, where the last one may be still appended
+    segments: Vec<u64>,
+    // the offset of the next record in the last segment
+    pos: u64,
+    reader: KvStoreReader,
+}
+
+impl Follower {
+    // reads the records appended since the last call and collects their events,
+    // or rereads all segments without events if they have been replaced by compaction,
+    // returns whether the reader has been changed
+    fn catch_up(
+        &mut self,
+        path: &Path,
+        versions: usize,
+        events: &mut Vec<(String, Event)>,
+    ) -> Result<bool> {
+        let segments = KvStore::segments(path)?;
+        let rebuilt = !segments.starts_with(&self.segments);
+        if rebuilt {
+            *self = Follower::default();
+        }
+
+        let mut changed = rebuilt;
+        for &n in &segments[self.segments.len().saturating_sub(1)..] {
+            if self.segments.last() != Some(&n) {
+                self.segments.push(n);
+                self.pos = 0;
+            }
+
+            let mut file = File::open(path.join(KvStore::file_name(n)))?;
+            file.seek(SeekFrom::Start(self.pos))?;
+            let mut appended = Vec::new();
+            file.read_to_end(&mut appended)?;
+
+            // the last record may be still being written, which is read at the next call
+            let mut rest = appended.as_slice();
+            while let Some(len) = rest.iter().position(|&b| b == b'#') {
+                let record: Record = serde_json::from_slice(&rest[..len])?;
+                self.reader.apply(n, self.pos, &record, versions);
+                if !rebuilt {
+                    events.push(record.into_event());
+                }
+                self.pos += len as u64 + 1;
+                rest = &rest[len + 1..];
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    // polls the directory at the given interval in a thread until all handles of the store are dropped,
+    // a failed poll is retried at the next one since files may be removed by compaction meanwhile
+    fn spawn(mut self, store: &KvStore, interval: Duration, versions: usize) {
+        let reader = Arc::downgrade(&store.reader);
+        let writer = Arc::downgrade(&store.writer);
+        let path = store.path.clone();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let (reader, writer) = match (reader.upgrade(), writer.upgrade()) {
+                (Some(reader), Some(writer)) => (reader, writer),
+                _ => return,
+            };
+            let store = KvStore {
+                reader,
+                writer,
+                path: path.clone(),
+                compactor: None,
+                lock: None,
+                namespace: String::new(),
+            };
+
+            let mut events = Vec::new();
+            if let Ok(true) = self.catch_up(&path, versions, &mut events) {
+                store.swap_reader(self.reader.clone());
+                let mut writer = store.writer.lock().unwrap();
+                for (ns, event) in events {
+                    writer.watchers.notify(&ns, event);
+                }
+            }
+        });
+    }
+}
+
+// the budget of bytes per second, which sleeps when the bytes are written faster than the rate
+struct Throttle {
+    rate: Option<u64>,
+    start: Instant,
+    // the total time slept
+    throttled: Duration,
+}
+
+impl Throttle {
+    fn new(rate: Option<u64>) -> Self {
+        Throttle {
+            rate,
+            start: Instant::now(),
+            throttled: Duration::ZERO,
+        }
+    }
+
+    // waits until writing the given total bytes since the start fits in the rate
+    fn wait(&mut self, bytes: u64) {
+        if let Some(rate) = self.rate.filter(|&rate| rate > 0) {
+            let expected = Duration::from_secs_f64(bytes as f64 / rate as f64);
+            if let Some(ahead) = expected.checked_sub(self.start.elapsed()) {
+                thread::sleep(ahead);
+                self.throttled += ahead;
+            }
+        }
+    }
+}
+
+// the buffered writer of a data file that keeps the offset of its end,
+// so that the positions of records are known without flushing the buffer
+struct RecordWriter {
+    writer: BufWriter<File>,
+    pos: u64,
+}
+
+impl RecordWriter {
+    // appends to the file after its current content
+    fn new(file: File) -> Result<Self> {
+        let pos = file.metadata()?.len();
+        Ok(RecordWriter {
+            writer: BufWriter::new(file),
+            pos,
+        })
+    }
+
+    fn get_ref(&self) -> &File {
+        self.writer.get_ref()
+    }
+}
+
+impl Write for RecordWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.writer.write(buf)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// gets the milliseconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// A type that represents the options of opening a `KvStore`.
+#[derive(Clone, Copy, Debug)]
+pub struct KvStoreOptions {
+    /// The policy of keeping old versions of keys
+    pub retention: Retention,
+    /// Opens the store without creating any file, where `set`, `remove` and `load` return `Error::ReadOnly`.
+    ///
+    /// Read-only stores share the lock of the READERS file, which the writer creates,
+    /// and the writer keeps the files replaced by compaction until all read-only stores are dropped.
+    /// A read-only store opened before the file is created is not protected, and may fail to read
+    /// once the writer compacts the files.
+    pub read_only: bool,
+    /// Polls the directory of a read-only store at the given interval for the records appended by the writer,
+    /// whose events are sent to watchers unless the writer has compacted the files since the last poll
+    pub follow: Option<Duration>,
+    /// The maximum bytes per second rewritten by compaction, which is unlimited by default,
+    /// where compaction runs in a background thread, so neither writes nor reads wait for its throttling
+    pub compaction_rate: Option<u64>,
+    /// The ratio of records no longer used in a segment, over which compaction rewrites the segment,
+    /// which is 0.5 by default
+    pub compaction_threshold: f64,
+}
+
+impl Default for KvStoreOptions {
+    fn default() -> Self {
+        KvStoreOptions {
+            retention: Retention::default(),
+            read_only: false,
+            follow: None,
+            compaction_rate: None,
+            compaction_threshold: 0.5,
+        }
+    }
+}
+
+/// A type that represents the statistics of compactions since the store was opened.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// The number of compactions
+    pub compactions: u64,
+    /// The number of segments rewritten by compactions
+    pub segments: u64,
+    /// The number of bytes rewritten by compactions
+    pub bytes: u64,
+    /// The total time spent in compactions
+    pub elapsed: Duration,
+    /// The total time compactions slept to keep within `KvStoreOptions::compaction_rate`
+    pub throttled: Duration,
+}
+
+/// A type that represents how many old versions of each key are kept by `KvStore`.
+///
+/// At most `versions` old versions are kept for each key besides its current value or removal,
+/// and compaction drops the ones older than `age` if it is set. The default keeps no old version.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Retention {
+    /// The maximum number of old versions of each key
+    pub versions: usize,
+    /// The maximum age of old versions kept by compaction
+    pub age: Option<Duration>,
+}
+
+/// A type that represents one version of a key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    /// The sequence number of the write, which increases monotonically within the store
+    pub seq: u64,
+    /// The time of the write
+    pub timestamp: SystemTime,
+    /// The value set by the write, or None if the key was removed
+    pub value: Option<String>,
+}
+
+/// A type that represents the result of checking a `KvStore` directory offline.
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    /// The number of `kvs.data.*` files
+    pub files: usize,
+    /// The number of set-records that hold current values
+    pub live: usize,
+    /// The number of set-records that have been overwritten or removed
+    pub overwritten: usize,
+    /// The number of rm-records
+    pub tombstones: usize,
+    /// The file numbers and offsets of records that cannot be parsed
+    pub corrupt: Vec<(u64, u64)>,
 }
 
 /// A store engine that allows lock-free readers to read.
@@ -46,112 +586,467 @@ pub struct KvStore {
     writer: Arc<Mutex<KvStoreWriter>>,
     // the path to the directory of the store, whose shared by readers and writers
     path: PathBuf,
+    // compacts the segments in the background, which read-only stores do not have,
+    // and is dropped before the lock, so that no compaction runs after the store is unlocked
+    compactor: Option<Arc<Compactor>>,
+    // the locked LOCK file, or the shared READERS file of read-only stores,
+    // which is unlocked after all clones are dropped
+    lock: Option<Arc<File>>,
+    // the namespace of keys in this handle, where the empty string is the default namespace
+    namespace: String,
 }
 
 impl KvStore {
     pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        KvStore::open_with(path, KvStoreOptions::default())
+    }
+
+    /// Opens the store with the given options.
+    pub fn open_with(path: impl Into<PathBuf>, options: KvStoreOptions) -> Result<Self> {
         let path: PathBuf = path.into();
-        let path_at = |n: u64| path.join("kvs.data.".to_owned() + &n.to_string());
+        if options.read_only {
+            return KvStore::open_read_only(path, options);
+        }
+        let path_at = |n: u64| path.join(KvStore::file_name(n));
 
         if !path.exists() {
             fs::create_dir(&path)?;
         }
 
-        // rebuild the in-memory index
-        let mut index = HashMap::new();
-        let mut unused = 0;
-
-        // scan how many kvs.data.* files in the given dir
-        let mut nfile: u64 = 0;
-        for entry in WalkDir::new(&path).min_depth(1).max_depth(1) {
-            if entry?
-                .file_name()
-                .to_string_lossy()
-                .starts_with("kvs.data.")
-            {
-                nfile += 1;
-            }
-        }
-
-        // if no file exists, set active_file 0
-        let (active_file, base_file) = if nfile == 0 {
-            (0, 0)
-        } else {
-            // finds the base file number
-            let mut base = 0;
-            base = loop {
-                if path_at(base).is_file() {
-                    break base;
-                } else {
-                    base += 1;
-                }
-            };
+        // locks the directory before reading any file
+        let lock = KvStore::lock(&path, LOCK_FILE, true)?.unwrap();
 
-            // read each kvs.data.* file
-            for i in base..base + nfile {
-                let file = File::open(path_at(i))?;
-                let reader = BufReader::new(&file);
-
-                // replay each command
-                let mut pos: u64 = 0;
-                for command in reader.split(b'#') {
-                    let command = command?;
-                    let next_pos = pos + command.len() as u64 + 1;
-
-                    let command = serde_json::from_slice(&command)?;
-                    match command {
-                        Command::Set { key, .. } => {
-                            if index.insert(key.clone(), (i, pos)).is_some() {
-                                unused += 1;
-                            }
-                        }
-                        Command::Rm { key } => {
-                            index.remove(&key);
-                            unused += 1;
-                        }
-                        _ => (),
-                    }
-                    pos = next_pos;
+        // reads the layout from the MANIFEST,
+        // or builds it from kvs.data.* files in order for stores created without it
+        let data_files = KvStore::data_files(&path)?;
+        let manifest = match Manifest::load(&path)? {
+            Some(manifest) => manifest,
+            None => {
+                let mut segments = data_files.clone();
+                if segments.is_empty() {
+                    segments.push(0);
                 }
+                let manifest = Manifest::new(segments);
+                manifest.store(&path)?;
+                manifest
             }
-
-            (base + nfile - 1, base)
         };
 
-        let active_writer = BufWriter::new(
+        // removes files left by interrupted rotations or compactions,
+        // which also creates the READERS file for read-only stores
+        let next_file = data_files
+            .iter()
+            .chain(&manifest.segments)
+            .max()
+            .map_or(0, |n| n + 1);
+        let mut obsolete: Vec<u64> = data_files
+            .into_iter()
+            .filter(|n| !manifest.segments.contains(n))
+            .collect();
+        KvStore::remove_obsolete(&path, &mut obsolete)?;
+
+        let active_file = *manifest.segments.last().unwrap();
+        let active_writer = Some(RecordWriter::new(
             OpenOptions::new()
                 .create(true)
                 .append(true)
                 .open(path_at(active_file))?,
-        );
+        )?);
+
+        // rebuild the in-memory index and history
+        let mut reader = KvStoreReader::default();
+        let mut counts: HashMap<u64, SegmentCounts> = HashMap::new();
+        let mut last_seq = 0;
 
-        let reader = Arc::new(AtomicPtr::new(KvStoreReader::raw_arc(index)));
+        // read each kvs.data.* file and replay each command
+        for &i in &manifest.segments {
+            if !path_at(i).is_file() {
+                return Err(Error::InvalidManifest(format!(
+                    "Missing {}",
+                    KvStore::file_name(i)
+                )));
+            }
+
+            KvStore::replay_file(path_at(i), |pos, record| {
+                let record = record?;
+                last_seq = last_seq.max(record.seq());
+                counts.entry(i).or_default().records += 1;
+                for (n, _) in reader.apply(i, pos, &record, options.retention.versions) {
+                    counts.entry(n).or_default().kill(record.seq());
+                }
+                Ok(())
+            })?;
+        }
+
+        let reader = Arc::new(AtomicPtr::new(reader.raw_arc()));
 
         let writer = KvStoreWriter {
-            base_file,
-            active_file,
+            next_file,
+            obsolete,
+            segments: manifest.segments,
             active_writer,
-            unused,
+            counts,
+            watchers: Watchers::default(),
+            last_seq,
+            retention: options.retention,
+            compaction_rate: options.compaction_rate,
+            compaction_threshold: options.compaction_threshold,
+            compaction_stats: CompactionStats::default(),
+            compaction_error: None,
         };
         let writer = Arc::new(Mutex::new(writer));
 
+        // the thread holds a handle without the compactor, which would never be dropped otherwise
+        let store = KvStore {
+            reader: Arc::clone(&reader),
+            writer: Arc::clone(&writer),
+            path: path.clone(),
+            compactor: None,
+            lock: None,
+            namespace: String::new(),
+        };
+        let compactor = Compactor::spawn(move || {
+            if let Err(e) = store.compact() {
+                store.writer.lock().unwrap().compaction_error = Some(e);
+            }
+        });
+
         Ok(KvStore {
             reader,
             writer,
             path,
+            compactor: Some(Arc::new(compactor)),
+            lock: Some(Arc::new(lock)),
+            namespace: String::new(),
         })
     }
 
+    /// Gets the statistics of compactions since the store was opened.
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.writer.lock().unwrap().compaction_stats
+    }
+
+    /// Waits until the compactions requested by the writes so far are done.
+    ///
+    /// Compactions run in a background thread, whose failure is returned by the next write.
+    /// Dropping the last handle of the store also waits for them.
+    pub fn wait_for_compaction(&self) {
+        if let Some(compactor) = &self.compactor {
+            compactor.wait();
+        }
+    }
+
+    // opens the store without locking the directory or creating any file,
+    // and follows the appends of the writer process if it is enabled
+    fn open_read_only(path: PathBuf, options: KvStoreOptions) -> Result<Self> {
+        if !path.is_dir() {
+            return Err(Error::IOError(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a store directory", path.display()),
+            )));
+        }
+        // shares the lock before reading any file, so that the writer keeps the files until the store is dropped
+        let lock = match File::open(path.join(READERS_FILE)) {
+            Ok(file) => {
+                FileExt::lock_shared(&file)?;
+                Some(Arc::new(file))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        for n in KvStore::segments(&path)? {
+            if !path.join(KvStore::file_name(n)).is_file() {
+                return Err(Error::InvalidManifest(format!(
+                    "Missing {}",
+                    KvStore::file_name(n)
+                )));
+            }
+        }
+
+        let mut follower = Follower::default();
+        follower.catch_up(&path, options.retention.versions, &mut Vec::new())?;
+
+        let writer = KvStoreWriter {
+            segments: Vec::new(),
+            next_file: 0,
+            obsolete: Vec::new(),
+            active_writer: None,
+            counts: HashMap::new(),
+            watchers: Watchers::default(),
+            last_seq: 0,
+            retention: options.retention,
+            compaction_rate: None,
+            compaction_threshold: options.compaction_threshold,
+            compaction_stats: CompactionStats::default(),
+            compaction_error: None,
+        };
+        let store = KvStore {
+            reader: Arc::new(AtomicPtr::new(follower.reader.clone().raw_arc())),
+            writer: Arc::new(Mutex::new(writer)),
+            path,
+            compactor: None,
+            lock,
+            namespace: String::new(),
+        };
+        if let Some(interval) = options.follow {
+            follower.spawn(&store, interval, options.retention.versions);
+        }
+        Ok(store)
+    }
+
+    /// Gets the value of the given key right after the write with the given sequence number,
+    /// returns None if the key did not exist then, or its version at that time is no longer retained.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tempfile::TempDir;
+    /// use kvs::{kvs_engine::{KvStoreOptions, Retention}, KvStore, KvsEngine};
+    ///
+    /// # fn main() -> kvs::Result<()> {
+    /// let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    /// let retention = Retention { versions: 10, age: None };
+    /// let options = KvStoreOptions { retention, ..KvStoreOptions::default() };
+    /// let store = KvStore::open_with(temp_dir.path(), options)?;
+    ///
+    /// store.set("k".to_owned(), "v1".to_owned())?;
+    /// let seq = store.history("k", 1)?[0].seq;
+    /// store.set("k".to_owned(), "v2".to_owned())?;
+    /// assert_eq!(store.get_at("k", seq)?, Some("v1".to_owned()));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_at(&self, key: &str, seq: u64) -> Result<Option<String>> {
+        let reader = self.get_reader();
+        for (n, pos) in reader.versions(&self.namespace, key) {
+            let record = KvStore::read_record_from(self.path_at(n), pos)?;
+            if record.seq() <= seq {
+                return Ok(record.into_version().value);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Gets at most `limit` retained versions of the given key from the newest to the oldest,
+    /// where the newest one is the current value or the removal of the key.
+    pub fn history(&self, key: &str, limit: usize) -> Result<Vec<Version>> {
+        let reader = self.get_reader();
+        reader
+            .versions(&self.namespace, key)
+            .take(limit)
+            .map(|(n, pos)| Ok(KvStore::read_record_from(self.path_at(n), pos)?.into_version()))
+            .collect()
+    }
+
+    /// Checks every `kvs.data.*` file in the given directory without opening the store,
+    /// reports the counts of records and the offsets of corrupt records.
+    pub fn check(path: impl Into<PathBuf>) -> Result<CheckReport> {
+        let path: PathBuf = path.into();
+        let _lock = KvStore::lock(&path, LOCK_FILE, false)?;
+        KvStore::check_files(&path, &KvStore::segments(&path)?)
+    }
+
+    /// Salvages all readable records in the given directory into a fresh store at `new_path`,
+    /// returns the report of checking the old directory.
+    ///
+    /// All `kvs.data.*` files are salvaged in the order of their numbers
+    /// if the MANIFEST cannot be parsed or lists a missing file.
+    pub fn repair(path: impl Into<PathBuf>, new_path: impl Into<PathBuf>) -> Result<CheckReport> {
+        let path: PathBuf = path.into();
+        let _lock = KvStore::lock(&path, LOCK_FILE, false)?;
+        let segments = match Manifest::load(&path) {
+            Ok(Some(manifest))
+                if manifest
+                    .segments
+                    .iter()
+                    .all(|&n| path.join(KvStore::file_name(n)).is_file()) =>
+            {
+                manifest.segments
+            }
+            Ok(_) | Err(Error::SerdeJSONError(_)) | Err(Error::InvalidManifest(_)) => {
+                KvStore::data_files(&path)?
+            }
+            Err(e) => return Err(e),
+        };
+        let report = KvStore::check_files(&path, &segments)?;
+
+        // rebuilds the index from readable records only
+        let mut index = HashMap::new();
+        for &n in &segments {
+            KvStore::replay_file(path.join(KvStore::file_name(n)), |pos, record| {
+                match record {
+                    Ok(Record::Set { key, ns, .. }) => {
+                        index.insert((ns, key), (n, pos));
+                    }
+                    Ok(Record::Rm { key, ns, .. }) => {
+                        index.remove(&(ns, key));
+                    }
+                    Err(_) => (),
+                }
+                Ok(())
+            })?;
+        }
+
+        // groups live records by namespaces and loads them
+        let mut namespaces: HashMap<_, Vec<_>> = HashMap::new();
+        for ((ns, _), pos) in index {
+            namespaces.entry(ns).or_default().push(pos);
+        }
+
+        let store = KvStore::open(new_path)?;
+        for (ns, positions) in namespaces {
+            let store = store.open_namespace(&ns)?;
+            store.for_each(|_, _| Err(Error::StoreNotEmpty))?;
+            store.load(positions.into_iter().map(|(n, pos)| {
+                match KvStore::read_record_from(path.join(KvStore::file_name(n)), pos)? {
+                    Record::Set { key, value, .. } => Ok((key, value)),
+                    _ => Err(Error::ErrorLogMeet),
+                }
+            }))?;
+        }
+
+        Ok(report)
+    }
+
+    // checks the given data files without locking the directory
+    fn check_files(path: &Path, segments: &[u64]) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+
+        // replays all readable records to find out which ones are still live
+        let mut index = HashSet::new();
+        let mut sets = 0;
+        for &n in segments {
+            report.files += 1;
+            KvStore::replay_file(path.join(KvStore::file_name(n)), |pos, record| {
+                match record {
+                    Ok(Record::Set { key, ns, .. }) => {
+                        index.insert((ns, key));
+                        sets += 1;
+                    }
+                    Ok(Record::Rm { key, ns, .. }) => {
+                        index.remove(&(ns, key));
+                        report.tombstones += 1;
+                    }
+                    Err(_) => report.corrupt.push((n, pos)),
+                }
+                Ok(())
+            })?;
+        }
+        report.live = index.len();
+        report.overwritten = sets - report.live;
+
+        Ok(report)
+    }
+
+    // gets the numbers of live kvs.data.* files in the given directory in replay order
+    fn segments(path: &Path) -> Result<Vec<u64>> {
+        match Manifest::load(path)? {
+            Some(manifest) => Ok(manifest.segments),
+            None => KvStore::data_files(path),
+        }
+    }
+
+    // lists the numbers of all kvs.data.* files in the given directory in order
+    fn data_files(path: &Path) -> Result<Vec<u64>> {
+        let mut files = Vec::new();
+        for entry in WalkDir::new(path).min_depth(1).max_depth(1) {
+            if let Some(n) = entry?
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix("kvs.data.")
+                .and_then(|n| n.parse().ok())
+            {
+                files.push(n);
+            }
+        }
+        files.sort_unstable();
+        Ok(files)
+    }
+
+    // locks the file of the given name in the given directory without blocking,
+    // the exclusive lock creates the file while the shared lock returns None if it does not exist
+    fn lock(path: &Path, name: &str, exclusive: bool) -> Result<Option<File>> {
+        let lock_path = path.join(name);
+        if !exclusive && !lock_path.exists() {
+            return Ok(None);
+        }
+
+        let file = OpenOptions::new()
+            .create(exclusive)
+            .truncate(false)
+            .read(true)
+            .write(exclusive)
+            .open(lock_path)?;
+        let locked = if exclusive {
+            FileExt::try_lock_exclusive(&file)
+        } else {
+            FileExt::try_lock_shared(&file)
+        };
+        match locked {
+            Ok(()) => Ok(Some(file)),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => Err(Error::StoreLocked),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // removes the given files unless read-only stores share the READERS file,
+    // in which case the files are left for the next call
+    fn remove_obsolete(path: &Path, obsolete: &mut Vec<u64>) -> Result<()> {
+        let _readers = match KvStore::lock(path, READERS_FILE, true) {
+            Err(Error::StoreLocked) => return Ok(()),
+            readers => readers?,
+        };
+        while let Some(&n) = obsolete.last() {
+            fs::remove_file(path.join(KvStore::file_name(n)))?;
+            obsolete.pop();
+        }
+        Ok(())
+    }
+
+    fn file_name(n: u64) -> String {
+        "kvs.data.".to_owned() + &n.to_string()
+    }
+
+    // reads each record in the given file in order,
+    // and calls `f` with the offset and the parsed record or the parsing error
+    fn replay_file(
+        path: PathBuf,
+        mut f: impl FnMut(u64, Result<Record>) -> Result<()>,
+    ) -> Result<()> {
+        let reader = BufReader::new(File::open(path)?);
+
+        let mut pos: u64 = 0;
+        for record in reader.split(b'#') {
+            let record = record?;
+            let next_pos = pos + record.len() as u64 + 1;
+
+            f(pos, serde_json::from_slice(&record).map_err(Error::from))?;
+            pos = next_pos;
+        }
+        Ok(())
+    }
+
+    // locks the writer, returns `Error::ReadOnly` for read-only stores,
+    // or the failure of the last compaction once
+    fn lock_writer(&self) -> Result<MutexGuard<'_, KvStoreWriter>> {
+        let mut writer = self.writer.lock().unwrap();
+        if writer.active_writer.is_none() {
+            return Err(Error::ReadOnly);
+        }
+        match writer.compaction_error.take() {
+            Some(e) => Err(e),
+            None => Ok(writer),
+        }
+    }
+
     // clones current map for readers
     fn get_reader(&self) -> Arc<KvStoreReader> {
         unsafe { Arc::clone(&(*self.reader.load(Ordering::Relaxed))) }
     }
 
-    // swaps the pointer with a new pointer points to the given index max atomically
-    fn swap_index(&self, index: HashMap<String, (u64, u64)>) {
-        let old = self
-            .reader
-            .swap(KvStoreReader::raw_arc(index), Ordering::Relaxed);
+    // swaps the pointer with a new pointer points to the given reader atomically
+    fn swap_reader(&self, reader: KvStoreReader) {
+        let old = self.reader.swap(reader.raw_arc(), Ordering::Relaxed);
         // drop the old index map after swaping
         unsafe {
             drop(Box::from_raw(old));
@@ -159,110 +1054,189 @@ impl KvStore {
     }
 
     fn path_at(&self, n: u64) -> PathBuf {
-        self.path.join("kvs.data.".to_owned() + &n.to_string())
+        self.path.join(KvStore::file_name(n))
     }
 
-    fn active_path(&self, writer: &KvStoreWriter) -> PathBuf {
-        self.path_at(writer.active_file)
-    }
-
-    // rewrite records to the active file
-    fn compact(&self, writer: &mut KvStoreWriter) -> Result<()> {
-        let base_file = writer.base_file;
-        let active_file = writer.active_file;
-
-        // if kvs.data.0 is used, use active file directly
-        // else write keys and values to kvs.data.0
-        let target_file = if base_file == 0 { active_file } else { 0 };
+    // rewrites each old segment whose dead ratio exceeds the threshold into a new file at its place in the MANIFEST,
+    // which keeps the replay order, and drops the records that are neither used nor needed as tombstones
+    //
+    // The writer is only locked to pick the segments and to swap them with the new files,
+    // so that writes never wait for the rewriting and its throttling. The records used when the segments
+    // are picked are kept, and the ones overwritten meanwhile are counted as dead in the new files.
+    // An rm-record is only kept for hiding older records while a segment before it has records unused since then.
+    fn compact(&self) -> Result<()> {
+        let (picked, reader, last_seq, retention, rate) = {
+            let mut writer = self.writer.lock().unwrap();
+            // the files kept for read-only stores may be removed once they are dropped
+            KvStore::remove_obsolete(&self.path, &mut writer.obsolete)?;
 
-        let reader = self.get_reader();
-        let mut new_index = HashMap::new();
-        for (key, (n, pos)) in &reader.0 {
-            // in compact, n < active_file
-            let command = KvStore::read_command_from(self.path_at(*n), *pos)?;
-            let pos = KvStore::write_command_to_writer(&mut writer.active_writer, &command)?;
-            new_index.insert(key.clone(), (target_file, pos));
+            let active_file = writer.active_file();
+            let selected: Vec<u64> = writer
+                .segments
+                .iter()
+                .copied()
+                .filter(|n| *n != active_file)
+                .filter(|n| {
+                    writer.counts.get(n).map_or(0.0, SegmentCounts::dead_ratio)
+                        > writer.compaction_threshold
+                })
+                .collect();
+            // each segment with its new file, its dead records when picked, whether it is the first segment,
+            // and the first write that left unused records in the segments before it that are not rewritten
+            let mut picked: Vec<(u64, u64, usize, bool, Option<u64>)> = Vec::new();
+            let mut first_dead: Option<u64> = None;
+            for (i, n) in writer.segments.clone().into_iter().enumerate() {
+                let counts = writer.counts.get(&n).copied().unwrap_or_default();
+                if selected.contains(&n) {
+                    picked.push((n, writer.new_file(), counts.dead, i == 0, first_dead));
+                } else {
+                    first_dead = first_dead.into_iter().chain(counts.first_dead).min();
+                }
+            }
+            // the snapshot has the same writes as the dead counts
+            let reader = self.get_reader();
+            let rate = writer.compaction_rate;
+            (picked, reader, writer.last_seq, writer.retention, rate)
+        };
+        if picked.is_empty() {
+            return Ok(());
         }
-        writer.active_writer.flush()?;
 
-        // copy active file to kvs.data.0 if kvs.data.0 is removed
-        if base_file != 0 {
-            copy(self.active_path(writer), self.path_at(target_file))?;
+        let age = retention.age;
+        let now = now();
+        let mut throttle = Throttle::new(rate);
+        let mut bytes = 0;
+        // the new positions of the kept records of each key, or None for the retained records dropped
+        let mut moves = Vec::new();
+        let mut new_counts = Vec::new();
+        for &(n, new_file, _, first, first_dead) in &picked {
+            let mut new_writer = RecordWriter::new(File::create(self.path_at(new_file))?)?;
+            let mut counts = SegmentCounts::default();
+            KvStore::replay_file(self.path_at(n), |pos, record| {
+                let record = record?;
+                let (live, retained) = reader.is_used(n, pos, &record);
+                let expired =
+                    age.is_some_and(|age| now.saturating_sub(record.ts()) > age.as_millis() as u64);
+                // older records of removed keys may be left in the segments before this one,
+                // either retained or unused since the removal
+                let hides = matches!(record, Record::Rm { .. })
+                    && !first
+                    && !reader.is_live(&record)
+                    && (retained || first_dead.is_some_and(|seq| seq <= record.seq()));
+                let keep = match record {
+                    Record::Set { .. } => live || (retained && !expired),
+                    Record::Rm { .. } => (retained && !expired) || hides,
+                };
+                let (ns, key) = record.ns_key();
+                if keep {
+                    let new_pos = KvStore::write_record_to_writer(&mut new_writer, &record)?;
+                    moves.push(((n, pos), Some((new_file, new_pos)), ns.clone(), key.clone()));
+                    counts.records += 1;
+                    if hides && !retained {
+                        counts.tombstones += 1;
+                        counts.masks = counts.masks.max(record.seq());
+                    }
+                    // the new files only contain the rewritten records before this one
+                    throttle.wait(bytes + new_pos);
+                } else if retained {
+                    moves.push(((n, pos), None, ns.clone(), key.clone()));
+                }
+                Ok(())
+            })?;
+            new_writer.flush()?;
+            new_writer.get_ref().sync_all()?;
+            bytes += new_writer.pos;
+            new_counts.push(counts);
         }
 
-        // swap and drop the index in reader
-        self.swap_index(new_index);
-
-        // remove old files (safe by file-rc in OS)
-        // TODO: When other reads occur after getting index,
-        // it may cause old files to be deleted before reading from them
-        if base_file == 0 {
-            for i in 0..active_file {
-                fs::remove_file(self.path_at(i))?;
+        let mut writer = self.writer.lock().unwrap();
+        // the positions only move if the records are still used by the latest index
+        let mut new_reader = KvStoreReader::clone(&self.get_reader());
+        for (from, to, ns, key) in moves {
+            new_reader.relocate(from, to, &ns, &key);
+        }
+        for (&(n, new_file, dead, _, _), mut counts) in picked.iter().zip(new_counts) {
+            for segment in writer.segments.iter_mut().filter(|segment| **segment == n) {
+                *segment = new_file;
             }
-        } else {
-            for i in base_file..=active_file {
-                fs::remove_file(self.path_at(i))?;
+            // the records overwritten after being picked are unused since a later write
+            let old_counts = writer.counts.remove(&n).unwrap_or_default();
+            for _ in dead..old_counts.dead {
+                counts.kill(last_seq + 1);
             }
+            writer.counts.insert(new_file, counts);
         }
+        writer.recount_tombstones();
 
-        // update fields in the writer
-        writer.base_file = target_file;
-        writer.active_file = target_file;
-        writer.active_writer = BufWriter::new(
-            OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(self.active_path(writer))?,
-        );
-        writer.unused = 0;
+        // the new files replace the old segments once the MANIFEST is replaced
+        Manifest::new(writer.segments.clone()).store(&self.path)?;
+
+        // swap and drop the index in reader
+        self.swap_reader(new_reader);
+
+        // the old files are removed only after the MANIFEST and the index no longer point to them,
+        // and are kept until the next compaction while read-only stores share the READERS lock,
+        // where a read in this process that took the index before the swap may still miss its file
+        writer
+            .obsolete
+            .extend(picked.iter().map(|&(n, _, _, _, _)| n));
+        KvStore::remove_obsolete(&self.path, &mut writer.obsolete)?;
+
+        writer.compaction_stats.compactions += 1;
+        writer.compaction_stats.segments += picked.len() as u64;
+        writer.compaction_stats.bytes += bytes;
+        writer.compaction_stats.elapsed += throttle.start.elapsed();
+        writer.compaction_stats.throttled += throttle.throttled;
 
         Ok(())
     }
 
     fn try_compact(&self, last_pos: u64, writer: &mut KvStoreWriter) -> Result<()> {
         if last_pos > SINGLE_FILE_SIZE {
-            // create new file if the active file is larger than SINGLE_FILE_SIZE
-            writer.active_file += 1;
-            writer.active_writer = BufWriter::new(
+            // create new file if the active file is larger than SINGLE_FILE_SIZE,
+            // which is recorded in the MANIFEST after being created
+            let new_file = writer.new_file();
+            writer.active_writer = Some(RecordWriter::new(
                 OpenOptions::new()
                     .create(true)
-                    .write(true)
-                    .open(self.active_path(writer))?,
-            );
+                    .append(true)
+                    .open(self.path_at(new_file))?,
+            )?);
+            writer.segments.push(new_file);
+            writer.counts.insert(new_file, SegmentCounts::default());
+            Manifest::new(writer.segments.clone()).store(&self.path)?;
 
-            // compacts logs if unused logs are more than UNUSED_LIMIT
-            if writer.unused > UNUSED_LIMIT {
-                self.compact(writer)?;
+            // compacts the old segments that are mostly garbage in the background
+            if let Some(compactor) = &self.compactor {
+                compactor.request();
             }
         }
         Ok(())
     }
 
-    // reads the command from the given path and offset
-    fn read_command_from(path: PathBuf, pos: u64) -> Result<Command> {
+    // reads the record from the given path and offset
+    fn read_record_from(path: PathBuf, pos: u64) -> Result<Record> {
         let file = File::open(path)?;
         let mut reader = BufReader::new(file);
-        KvStore::read_command_from_reader(&mut reader, pos)
+        KvStore::read_record_from_reader(&mut reader, pos)
     }
 
-    // reads the command from the given reader and offset
-    fn read_command_from_reader(reader: &mut BufReader<File>, pos: u64) -> Result<Command> {
+    // reads the record from the given reader and offset
+    fn read_record_from_reader(reader: &mut BufReader<File>, pos: u64) -> Result<Record> {
         reader.seek(SeekFrom::Start(pos))?;
 
-        let mut command = Vec::new();
-        reader.read_until(b'#', &mut command)?;
-        command.pop();
+        let mut record = Vec::new();
+        reader.read_until(b'#', &mut record)?;
+        record.pop();
 
-        Ok(serde_json::from_slice(&command)?)
+        Ok(serde_json::from_slice(&record)?)
     }
 
-    // writes the given command to the given reader and returns the written pos
-    fn write_command_to_writer(writer: &mut BufWriter<File>, command: &Command) -> Result<u64> {
-        writer.seek(SeekFrom::End(0))?;
-        let pos = writer.stream_position()?;
+    // writes the given record to the given writer and returns the written pos
+    fn write_record_to_writer(writer: &mut RecordWriter, record: &Record) -> Result<u64> {
+        let pos = writer.pos;
 
-        serde_json::to_writer(&mut *writer, command)?;
+        serde_json::to_writer(&mut *writer, record)?;
         writer.write_all(b"#")?;
 
         Ok(pos)
@@ -275,6 +1249,9 @@ impl Clone for KvStore {
             reader: Arc::clone(&self.reader),
             writer: Arc::clone(&self.writer),
             path: self.path.clone(),
+            compactor: self.compactor.clone(),
+            lock: self.lock.clone(),
+            namespace: self.namespace.clone(),
         }
     }
 }
@@ -302,22 +1279,23 @@ impl KvsEngine for KvStore {
     /// # }
     /// ```
     fn set(&self, key: String, value: String) -> Result<()> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
 
-        let command = Command::Set {
-            key: key.clone(),
-            value,
-        };
-        let pos = KvStore::write_command_to_writer(&mut writer.active_writer, &command)?;
-        writer.active_writer.flush()?;
+        let record = writer.set_record(&self.namespace, key, value);
+        let pos = KvStore::write_record_to_writer(writer.active_writer()?, &record)?;
+        writer.active_writer()?.flush()?;
 
-        let active_file = writer.active_file;
-        let mut new_index = self.get_reader().0.clone();
-        if new_index.insert(key, (active_file, pos)).is_some() {
-            writer.unused += 1;
-        }
+        let active_file = writer.active_file();
+        let mut new_reader = KvStoreReader::clone(&self.get_reader());
+        let unused = new_reader.apply(active_file, pos, &record, writer.retention.versions);
+        writer.count(active_file, unused);
 
-        self.swap_index(new_index);
+        self.swap_reader(new_reader);
+        if let Record::Set { key, value, .. } = record {
+            writer
+                .watchers
+                .notify(&self.namespace, Event::Set { key, value });
+        }
         self.try_compact(pos, &mut writer)
     }
 
@@ -341,9 +1319,14 @@ impl KvsEngine for KvStore {
     /// # }
     /// ```
     fn get(&self, key: String) -> Result<Option<String>> {
-        if let Some(&(n, pos)) = self.get_reader().0.get(&key) {
-            match KvStore::read_command_from(self.path_at(n), pos)? {
-                Command::Set { key: _, value } => Ok(Some(value)),
+        let reader = self.get_reader();
+        if let Some(&(n, pos)) = reader
+            .index
+            .get(&self.namespace)
+            .and_then(|keys| keys.get(&key))
+        {
+            match KvStore::read_record_from(self.path_at(n), pos)? {
+                Record::Set { value, .. } => Ok(Some(value)),
                 _ => Err(Error::ErrorLogMeet),
             }
         } else {
@@ -371,22 +1354,211 @@ impl KvsEngine for KvStore {
     /// # }
     /// ```
     fn remove(&self, key: String) -> Result<()> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
 
         let reader = self.get_reader();
-        if reader.0.contains_key(&key) {
-            let mut new_index = reader.0.clone();
-            new_index.remove(&key);
+        if reader
+            .index
+            .get(&self.namespace)
+            .is_some_and(|keys| keys.contains_key(&key))
+        {
+            let record = writer.rm_record(&self.namespace, key.clone());
+            let pos = KvStore::write_record_to_writer(writer.active_writer()?, &record)?;
+            writer.active_writer()?.flush()?;
 
-            let command = Command::Rm { key };
-            let pos = KvStore::write_command_to_writer(&mut writer.active_writer, &command)?;
-            writer.active_writer.flush()?;
-            writer.unused += 1;
+            let active_file = writer.active_file();
+            let mut new_reader = KvStoreReader::clone(&reader);
+            let unused = new_reader.apply(active_file, pos, &record, writer.retention.versions);
+            writer.count(active_file, unused);
 
-            self.swap_index(new_index);
+            self.swap_reader(new_reader);
+            writer.watchers.notify(&self.namespace, Event::Rm { key });
             self.try_compact(pos, &mut writer)
         } else {
             Err(Error::KeyNotFound)
         }
     }
+
+    /// Visits each live key and value of the namespace in the snapshot of the current index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tempfile::TempDir;
+    /// use kvs::KvsEngine;
+    ///
+    /// # fn main() -> kvs::Result<()> {
+    /// let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    /// let mut store = kvs::KvStore::open(temp_dir.path())?;
+    ///
+    /// store.set("k".to_owned(), "v".to_owned())?;
+    /// let mut pairs = Vec::new();
+    /// store.for_each(|key, value| {
+    ///     pairs.push((key, value));
+    ///     Ok(())
+    /// })?;
+    /// assert_eq!(pairs, vec![("k".to_owned(), "v".to_owned())]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn for_each(&self, mut f: impl FnMut(String, String) -> Result<()>) -> Result<()> {
+        // keeps one reader for each file to avoid reopening it for every key
+        let mut readers = HashMap::new();
+        let index = self.get_reader();
+        for &(n, pos) in index
+            .index
+            .get(&self.namespace)
+            .into_iter()
+            .flat_map(|keys| keys.values())
+        {
+            let reader = match readers.entry(n) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(BufReader::new(File::open(self.path_at(n))?)),
+            };
+            match KvStore::read_record_from_reader(reader, pos)? {
+                Record::Set { key, value, .. } => f(key, value)?,
+                _ => return Err(Error::ErrorLogMeet),
+            }
+        }
+        Ok(())
+    }
+
+    /// Picks the keys of the page from the snapshot of the current index,
+    /// and only reads the records of those keys.
+    fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let index = self.get_reader();
+        let mut keys: Vec<_> = index
+            .index
+            .get(&self.namespace)
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| {
+                key.starts_with(prefix) && after.is_none_or(|after| key.as_str() > after)
+            })
+            .collect();
+        keys.sort_unstable_by_key(|&(key, _)| key);
+        keys.truncate(limit);
+
+        let mut readers = HashMap::new();
+        let mut pairs = Vec::with_capacity(keys.len());
+        for (_, &(n, pos)) in keys {
+            let reader = match readers.entry(n) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(BufReader::new(File::open(self.path_at(n))?)),
+            };
+            match KvStore::read_record_from_reader(reader, pos)? {
+                Record::Set { key, value, .. } => pairs.push((key, value)),
+                _ => return Err(Error::ErrorLogMeet),
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Writes all pairs with the writer locked once and flushes only when a file is full or at the end,
+    /// the events of the pairs are sent to watchers whenever the index is published.
+    fn load(&self, pairs: impl IntoIterator<Item = Result<(String, String)>>) -> Result<usize> {
+        let mut writer = self.lock_writer()?;
+
+        // only keeps the events if anyone is watching
+        let watched = !writer.watchers.is_empty();
+        let mut events = Vec::new();
+        let mut new_reader = KvStoreReader::clone(&self.get_reader());
+        let mut count = 0;
+        for pair in pairs {
+            let (key, value) = pair?;
+            let record = writer.set_record(&self.namespace, key, value);
+            let pos = KvStore::write_record_to_writer(writer.active_writer()?, &record)?;
+            let active_file = writer.active_file();
+            let unused = new_reader.apply(active_file, pos, &record, writer.retention.versions);
+            writer.count(active_file, unused);
+            if let (true, Record::Set { key, value, .. }) = (watched, record) {
+                events.push(Event::Set { key, value });
+            }
+            count += 1;
+
+            if pos > SINGLE_FILE_SIZE {
+                // publishes the index before switching files since compaction reads from it
+                writer.active_writer()?.flush()?;
+                self.swap_reader(new_reader);
+                for event in events.drain(..) {
+                    writer.watchers.notify(&self.namespace, event);
+                }
+                self.try_compact(pos, &mut writer)?;
+                new_reader = KvStoreReader::clone(&self.get_reader());
+            }
+        }
+        writer.active_writer()?.flush()?;
+
+        self.swap_reader(new_reader);
+        for event in events {
+            writer.watchers.notify(&self.namespace, event);
+        }
+        Ok(count)
+    }
+
+    /// Gets a handle of the namespace, whose keys are kept in the same files and index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tempfile::TempDir;
+    /// use kvs::KvsEngine;
+    ///
+    /// # fn main() -> kvs::Result<()> {
+    /// let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    /// let store = kvs::KvStore::open(temp_dir.path())?;
+    /// let users = store.open_namespace("users")?;
+    ///
+    /// users.set("k".to_owned(), "v".to_owned())?;
+    /// assert_eq!(users.get("k".to_owned())?, Some("v".to_owned()));
+    /// assert_eq!(store.get("k".to_owned())?, None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn open_namespace(&self, name: &str) -> Result<Self> {
+        Ok(KvStore {
+            namespace: name.to_owned(),
+            ..self.clone()
+        })
+    }
+
+    /// Lists the namespaces that have live keys in the current index.
+    fn namespaces(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self.get_reader().index.keys().cloned().collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Watches the keys of the namespace, whose events are sent by the writer after the index is published.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tempfile::TempDir;
+    /// use kvs::{kvs_engine::Event, KvsEngine};
+    ///
+    /// # fn main() -> kvs::Result<()> {
+    /// let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    /// let store = kvs::KvStore::open(temp_dir.path())?;
+    /// let mut watcher = store.watch("k")?;
+    ///
+    /// store.set("k".to_owned(), "v".to_owned())?;
+    /// store.remove("k".to_owned())?;
+    /// assert_eq!(
+    ///     watcher.next(),
+    ///     Some(Event::Set { key: "k".to_owned(), value: "v".to_owned() })
+    /// );
+    /// assert_eq!(watcher.next(), Some(Event::Rm { key: "k".to_owned() }));
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn watch(&self, prefix: &str) -> Result<Watcher> {
+        let mut writer = self.writer.lock().unwrap();
+        Ok(writer.watchers.watch(&self.namespace, prefix))
+    }
 }
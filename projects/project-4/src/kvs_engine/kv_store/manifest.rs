@@ -0,0 +1,63 @@
+use crate::{Error, Result};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+const MANIFEST_FILE: &str = "MANIFEST";
+const MANIFEST_TMP_FILE: &str = "MANIFEST.tmp";
+// the version of the format of kvs.data.* files
+const FORMAT_VERSION: u32 = 1;
+
+// the layout of a store directory, which is replaced atomically on every change
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct Manifest {
+    // the version of the format of data files
+    pub version: u32,
+    // the numbers of live kvs.data.* files in replay order, where the last one is active
+    pub segments: Vec<u64>,
+}
+
+impl Manifest {
+    pub fn new(segments: Vec<u64>) -> Self {
+        Manifest {
+            version: FORMAT_VERSION,
+            segments,
+        }
+    }
+
+    // reads the MANIFEST in the given directory, returns None if it does not exist
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let manifest: Manifest = serde_json::from_slice(&fs::read(path)?)?;
+        if manifest.version != FORMAT_VERSION {
+            Err(Error::InvalidManifest(format!(
+                "Unsupported version {}",
+                manifest.version
+            )))
+        } else if manifest.segments.is_empty() {
+            Err(Error::InvalidManifest(String::from("No segments")))
+        } else {
+            Ok(Some(manifest))
+        }
+    }
+
+    // writes to a temporary file and then renames it to MANIFEST
+    pub fn store(&self, dir: &Path) -> Result<()> {
+        let tmp_path = dir.join(MANIFEST_TMP_FILE);
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut file, self)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, dir.join(MANIFEST_FILE))?;
+
+        // makes the rename durable
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,686 @@
+mod bloom;
+mod manifest;
+mod table;
+mod wal;
+
+use super::{Compactor, Event, KvsEngine, Watcher, Watchers};
+use crate::{Error, Result};
+use manifest::Manifest;
+use table::{Entry, Table, TableWriter};
+use wal::{Wal, WalRecord};
+
+use fs2::FileExt;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashSet},
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+// the size of writes in the memtable that triggers writing it into a level-0 table
+const MEMTABLE_SIZE: usize = 1024 * 1024;
+// the size that splits the output of compaction into tables
+const TABLE_SIZE: u64 = 2 * 1024 * 1024;
+// the number of level-0 tables that triggers compacting them into level 1
+const L0_LIMIT: usize = 4;
+// the total size of tables in level 1, where each deeper level is LEVEL_RATIO times larger
+const LEVEL_BASE_SIZE: u64 = 10 * 1024 * 1024;
+const LEVEL_RATIO: u64 = 10;
+// the file locked by the process that opens the store
+const LOCK_FILE: &str = "LOCK";
+
+// the namespace and the key of an entry, which sorts the entries by namespaces first
+type Key = (String, String);
+
+struct LsmState {
+    // the writes after the last flush, which are also in the WAL, where None is a removed key
+    memtable: BTreeMap<Key, Option<String>>,
+    // the approximate size of the memtable
+    memtable_size: usize,
+    wal: Wal,
+    // level 0 has overlapping tables from the oldest to the newest,
+    // and each deeper level has disjoint tables sorted by keys
+    levels: Vec<Vec<Arc<Table>>>,
+    // the number of the next table
+    next_file: u64,
+    // the last key compacted in each level, after which the next compaction of the level starts
+    compact_pointers: Vec<Option<Key>>,
+    // watchers are sent the events after the memtable is changed
+    watchers: Watchers,
+    // the failure of the last compaction, which is returned by the next write
+    compaction_error: Option<Error>,
+}
+
+impl LsmState {
+    // gets the tables that may contain the key from the newest to the oldest
+    fn candidates(&self, key: &Key) -> Vec<Arc<Table>> {
+        let mut tables = Vec::new();
+        for (level, level_tables) in self.levels.iter().enumerate() {
+            if level == 0 {
+                tables.extend(
+                    level_tables
+                        .iter()
+                        .rev()
+                        .filter(|table| table.first() <= key && key <= table.last())
+                        .cloned(),
+                );
+            } else {
+                let i = level_tables.partition_point(|table| table.last() < key);
+                if let Some(table) = level_tables.get(i).filter(|table| table.first() <= key) {
+                    tables.push(Arc::clone(table));
+                }
+            }
+        }
+        tables
+    }
+
+    // gets the value of the key from the memtable and then the tables
+    fn lookup(&self, key: &Key) -> Result<Option<String>> {
+        if let Some(value) = self.memtable.get(key) {
+            return Ok(value.clone());
+        }
+        for table in self.candidates(key) {
+            if let Some(value) = table.get(key)? {
+                return Ok(value);
+            }
+        }
+        Ok(None)
+    }
+
+    // gets all tables from the newest to the oldest
+    fn tables(&self) -> Vec<Arc<Table>> {
+        let mut tables: Vec<Arc<Table>> = Vec::new();
+        for (level, level_tables) in self.levels.iter().enumerate() {
+            if level == 0 {
+                tables.extend(level_tables.iter().rev().cloned());
+            } else {
+                tables.extend(level_tables.iter().cloned());
+            }
+        }
+        tables
+    }
+
+    fn store_manifest(&self, path: &Path) -> Result<()> {
+        let levels = self
+            .levels
+            .iter()
+            .map(|tables| tables.iter().map(|table| table.id).collect())
+            .collect();
+        Manifest::new(self.next_file, levels).store(path)
+    }
+
+    // fails with the error of the last compaction once
+    fn check_compaction(&mut self) -> Result<()> {
+        match self.compaction_error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // picks the tables to compact and their level, which are level 0 if it has too many tables,
+    // or a table of the first level that is too large, or returns None if no level needs compaction
+    fn pick_compaction(&mut self) -> Option<(usize, Vec<Arc<Table>>)> {
+        if self.levels[0].len() >= L0_LIMIT {
+            return Some((0, self.levels[0].iter().rev().cloned().collect()));
+        }
+
+        let mut max_size = LEVEL_BASE_SIZE;
+        for level in 1..self.levels.len() {
+            let size: u64 = self.levels[level].iter().map(|table| table.size).sum();
+            if size > max_size {
+                // picks the table after the last compacted one in a round-robin way
+                let tables = &self.levels[level];
+                let i = match &self.compact_pointers[level] {
+                    Some(pointer) => tables.partition_point(|table| table.first() <= pointer),
+                    None => 0,
+                };
+                let table = Arc::clone(tables.get(i).unwrap_or(&tables[0]));
+                self.compact_pointers[level] = Some(table.last().clone());
+                return Some((level, vec![table]));
+            }
+            max_size *= LEVEL_RATIO;
+        }
+        None
+    }
+}
+
+/// A store engine based on a log-structured merge-tree,
+/// which only keeps recent writes and sparse indexes of sorted tables in memory.
+///
+/// Writes are logged in a WAL and then kept in a memtable,
+/// which is written into a level-0 table once it is full.
+/// Tables are compacted in a background thread into deeper levels that are ten times larger
+/// than the previous ones, and a failed compaction is returned by the next write.
+pub struct LsmKvsEngine {
+    // readers share the lock, and only flushes and the swaps of compacted tables block them
+    state: Arc<RwLock<LsmState>>,
+    path: PathBuf,
+    // dropped before the lock, so that no compaction runs after the store is unlocked
+    compactor: Arc<Compactor>,
+    // the locked LOCK file, which is unlocked after all clones are dropped
+    lock: Arc<File>,
+    // the namespace of keys in this handle, where the empty string is the default namespace
+    namespace: String,
+}
+
+impl LsmKvsEngine {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path: PathBuf = path.into();
+        if !path.exists() {
+            fs::create_dir(&path)?;
+        }
+
+        let lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path.join(LOCK_FILE))?;
+        match FileExt::try_lock_exclusive(&lock) {
+            Ok(()) => (),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                return Err(Error::StoreLocked)
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let manifest = match Manifest::load(&path)? {
+            Some(manifest) => manifest,
+            None => {
+                let manifest = Manifest::new(0, vec![Vec::new()]);
+                manifest.store(&path)?;
+                manifest
+            }
+        };
+
+        let mut levels = Vec::new();
+        let mut live = HashSet::new();
+        for ids in manifest.levels {
+            let mut tables = Vec::new();
+            for id in ids {
+                let table_path = path.join(LsmKvsEngine::file_name(id));
+                if !table_path.exists() {
+                    return Err(Error::InvalidManifest(format!(
+                        "Missing {}",
+                        LsmKvsEngine::file_name(id)
+                    )));
+                }
+                tables.push(Arc::new(Table::open(table_path, id)?));
+                live.insert(id);
+            }
+            levels.push(tables);
+        }
+        if levels.is_empty() {
+            levels.push(Vec::new());
+        }
+
+        // removes the tables written by unfinished flushes and compactions
+        for id in LsmKvsEngine::table_files(&path)? {
+            if !live.contains(&id) {
+                fs::remove_file(path.join(LsmKvsEngine::file_name(id)))?;
+            }
+        }
+
+        let mut memtable = BTreeMap::new();
+        let mut memtable_size = 0;
+        let wal = Wal::open(&path, |WalRecord { ns, key, value }| {
+            memtable_size += LsmKvsEngine::entry_size(&ns, &key, value.as_deref());
+            memtable.insert((ns, key), value);
+        })?;
+
+        let state = LsmState {
+            memtable,
+            memtable_size,
+            wal,
+            compact_pointers: vec![None; levels.len()],
+            levels,
+            next_file: manifest.next_file,
+            watchers: Watchers::default(),
+            compaction_error: None,
+        };
+
+        let state = Arc::new(RwLock::new(state));
+        let compactor = {
+            let state = Arc::clone(&state);
+            let path = path.clone();
+            Compactor::spawn(move || {
+                if let Err(e) = LsmKvsEngine::compact(&state, &path) {
+                    state.write().unwrap().compaction_error = Some(e);
+                }
+            })
+        };
+        Ok(LsmKvsEngine {
+            compactor: Arc::new(compactor),
+            state,
+            path,
+            lock: Arc::new(lock),
+            namespace: String::new(),
+        })
+    }
+
+    fn file_name(id: u64) -> String {
+        format!("{}.sst", id)
+    }
+
+    // gets the numbers of *.sst files in the directory
+    fn table_files(path: &Path) -> Result<Vec<u64>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let name = entry?.file_name();
+            if let Some(id) = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".sst"))
+                .and_then(|id| id.parse().ok())
+            {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    // estimates the memory used by an entry in the memtable
+    fn entry_size(ns: &str, key: &str, value: Option<&str>) -> usize {
+        ns.len() + key.len() + value.map_or(0, str::len) + 32
+    }
+
+    // logs the write and applies it to the memtable without flushing the WAL
+    fn write(&self, state: &mut LsmState, key: String, value: Option<String>) -> Result<()> {
+        let record = WalRecord {
+            ns: self.namespace.clone(),
+            key,
+            value,
+        };
+        state.wal.append(&record)?;
+
+        let WalRecord { ns, key, value } = record;
+        state.memtable_size += LsmKvsEngine::entry_size(&ns, &key, value.as_deref());
+        let event = (!state.watchers.is_empty()).then(|| match &value {
+            Some(value) => Event::Set {
+                key: key.clone(),
+                value: value.clone(),
+            },
+            None => Event::Rm { key: key.clone() },
+        });
+        state.memtable.insert((ns.clone(), key), value);
+        if let Some(event) = event {
+            state.watchers.notify(&ns, event);
+        }
+        Ok(())
+    }
+
+    // writes the memtable into a level-0 table if it is full, and then requests a compaction
+    fn try_flush(&self, state: &mut LsmState) -> Result<()> {
+        if state.memtable_size < MEMTABLE_SIZE {
+            return Ok(());
+        }
+
+        let id = state.next_file;
+        state.next_file += 1;
+        let mut writer = TableWriter::create(self.path.join(LsmKvsEngine::file_name(id)), id)?;
+        for (key, value) in &state.memtable {
+            writer.add(key, value.as_deref())?;
+        }
+        state.levels[0].push(Arc::new(writer.finish()?));
+        state.store_manifest(&self.path)?;
+
+        // the WAL is only emptied after the table is in the MANIFEST
+        state.memtable.clear();
+        state.memtable_size = 0;
+        state.wal.reset()?;
+
+        self.compactor.request();
+        Ok(())
+    }
+
+    // compacts the levels until none of them needs compaction,
+    // where the lock is only held to pick the tables and to swap them with the outputs
+    fn compact(state: &RwLock<LsmState>, path: &Path) -> Result<()> {
+        loop {
+            let picked = state.write().unwrap().pick_compaction();
+            match picked {
+                Some((level, tables)) => LsmKvsEngine::compact_level(state, path, level, tables)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // merges the given tables of the level, which are from the newest to the oldest,
+    // with the overlapping tables in the next level, and puts the output into the next level
+    //
+    // Only compactions change the levels after level 0, so the overlapping tables stay the same
+    // while the output is written without the lock, and flushes only add tables to level 0.
+    fn compact_level(
+        state: &RwLock<LsmState>,
+        path: &Path,
+        level: usize,
+        tables: Vec<Arc<Table>>,
+    ) -> Result<()> {
+        let first = tables
+            .iter()
+            .map(|table| table.first())
+            .min()
+            .unwrap()
+            .clone();
+        let last = tables
+            .iter()
+            .map(|table| table.last())
+            .max()
+            .unwrap()
+            .clone();
+        let (start, end, inputs, bottom) = {
+            let mut state = state.write().unwrap();
+            if state.levels.len() == level + 1 {
+                state.levels.push(Vec::new());
+                state.compact_pointers.push(None);
+            }
+
+            let next = &state.levels[level + 1];
+            let start = next.partition_point(|table| table.last() < &first);
+            let end = next.partition_point(|table| table.first() <= &last);
+            let inputs: Vec<Arc<Table>> = tables.iter().chain(&next[start..end]).cloned().collect();
+
+            // removed keys can be dropped if no deeper level may have older values of them
+            let bottom = state.levels[level + 2..].iter().all(Vec::is_empty);
+            (start, end, inputs, bottom)
+        };
+
+        // the overlapping tables of the next level may start before `first`
+        let mut sources = Vec::new();
+        for table in &inputs {
+            sources.push(table.iter_from(table.first())?);
+        }
+        let mut outputs = Vec::new();
+        let mut writer: Option<TableWriter> = None;
+        for entry in MergeIter::new(sources)? {
+            let (key, value) = entry?;
+            if bottom && value.is_none() {
+                continue;
+            }
+            if writer.is_none() {
+                let id = {
+                    let mut state = state.write().unwrap();
+                    state.next_file += 1;
+                    state.next_file - 1
+                };
+                let path = path.join(LsmKvsEngine::file_name(id));
+                writer = Some(TableWriter::create(path, id)?);
+            }
+            if let Some(table_writer) = writer.as_mut() {
+                table_writer.add(&key, value.as_deref())?;
+                if table_writer.size() >= TABLE_SIZE {
+                    outputs.push(Arc::new(writer.take().unwrap().finish()?));
+                }
+            }
+        }
+        if let Some(writer) = writer {
+            outputs.push(Arc::new(writer.finish()?));
+        }
+
+        let mut state = state.write().unwrap();
+        state.levels[level].retain(|table| !tables.iter().any(|input| Arc::ptr_eq(table, input)));
+        state.levels[level + 1].splice(start..end, outputs);
+        state.store_manifest(path)?;
+
+        for table in inputs {
+            table.set_obsolete();
+        }
+        Ok(())
+    }
+
+    // merges the memtable and tables into entries of the namespace from the given key with the prefix,
+    // or all entries with None, in order, where each source seeks to the start key,
+    // and the snapshot is taken with the lock held and read after the lock is released
+    fn merge_entries(
+        &self,
+        namespace: Option<&str>,
+        prefix: &str,
+        from: &str,
+    ) -> Result<MergeIter<Box<dyn Iterator<Item = Result<Entry>>>>> {
+        let start = (namespace.unwrap_or_default().to_owned(), from.to_owned());
+        let (memtable, tables): (Vec<Entry>, _) = {
+            let state = self.state.read().unwrap();
+            let memtable = state
+                .memtable
+                .range(start.clone()..)
+                .take_while(|(key, _)| {
+                    namespace.is_none_or(|ns| key.0 == ns && key.1.starts_with(prefix))
+                })
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            (memtable, state.tables())
+        };
+
+        let mut sources: Vec<Box<dyn Iterator<Item = Result<Entry>>>> =
+            vec![Box::new(memtable.into_iter().map(Ok))];
+        for table in tables.iter().filter(|table| table.last() >= &start) {
+            sources.push(Box::new(table.iter_from(&start)?));
+        }
+        MergeIter::new(sources)
+    }
+}
+
+impl Clone for LsmKvsEngine {
+    fn clone(&self) -> Self {
+        LsmKvsEngine {
+            state: Arc::clone(&self.state),
+            path: self.path.clone(),
+            compactor: Arc::clone(&self.compactor),
+            lock: Arc::clone(&self.lock),
+            namespace: self.namespace.clone(),
+        }
+    }
+}
+
+impl KvsEngine for LsmKvsEngine {
+    fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        LsmKvsEngine::open(path)
+    }
+
+    /// Sets the given value with the given key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tempfile::TempDir;
+    /// use kvs::KvsEngine;
+    ///
+    /// # fn main() -> kvs::Result<()> {
+    /// let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    /// let store = kvs::LsmKvsEngine::open(temp_dir.path())?;
+    ///
+    /// store.set("k".to_owned(), "v".to_owned())?;
+    /// assert_eq!(store.get("k".to_owned())?, Some("v".to_owned()));
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn set(&self, key: String, value: String) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        state.check_compaction()?;
+        self.write(&mut state, key, Some(value))?;
+        state.wal.flush()?;
+        self.try_flush(&mut state)
+    }
+
+    /// Gets the value of the given key from the memtable,
+    /// and then from the tables that may contain the key after the lock is released.
+    fn get(&self, key: String) -> Result<Option<String>> {
+        let key = (self.namespace.clone(), key);
+        let tables = {
+            let state = self.state.read().unwrap();
+            if let Some(value) = state.memtable.get(&key) {
+                return Ok(value.clone());
+            }
+            state.candidates(&key)
+        };
+
+        for table in tables {
+            if let Some(value) = table.get(&key)? {
+                return Ok(value);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Removes the given key by writing a tombstone,
+    /// which is dropped once it is compacted into the deepest level.
+    fn remove(&self, key: String) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        state.check_compaction()?;
+        if state
+            .lookup(&(self.namespace.clone(), key.clone()))?
+            .is_none()
+        {
+            return Err(Error::KeyNotFound);
+        }
+
+        self.write(&mut state, key, None)?;
+        state.wal.flush()?;
+        self.try_flush(&mut state)
+    }
+
+    /// Visits each live key and value of the namespace in the order of keys,
+    /// which merges the snapshot of the memtable and tables.
+    fn for_each(&self, mut f: impl FnMut(String, String) -> Result<()>) -> Result<()> {
+        for entry in self.merge_entries(Some(&self.namespace), "", "")? {
+            let ((ns, key), value) = entry?;
+            if ns != self.namespace {
+                break;
+            }
+            if let Some(value) = value {
+                f(key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges the entries of the namespace in order from the prefix or the given key,
+    /// and stops at the first key past the prefix.
+    fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let from = after.map_or(prefix, |after| after.max(prefix));
+        let mut pairs = Vec::new();
+        for entry in self.merge_entries(Some(&self.namespace), prefix, from)? {
+            if pairs.len() >= limit {
+                break;
+            }
+            let ((ns, key), value) = entry?;
+            if ns != self.namespace || (key.as_str() > prefix && !key.starts_with(prefix)) {
+                break;
+            }
+            if !key.starts_with(prefix) || after.is_some_and(|after| key.as_str() <= after) {
+                continue;
+            }
+            if let Some(value) = value {
+                pairs.push((key, value));
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Writes all pairs with the lock held once and flushes the WAL only at the end.
+    fn load(&self, pairs: impl IntoIterator<Item = Result<(String, String)>>) -> Result<usize> {
+        let mut state = self.state.write().unwrap();
+        state.check_compaction()?;
+        let mut count = 0;
+        for pair in pairs {
+            let (key, value) = pair?;
+            self.write(&mut state, key, Some(value))?;
+            self.try_flush(&mut state)?;
+            count += 1;
+        }
+        state.wal.flush()?;
+        Ok(count)
+    }
+
+    /// Gets a handle of the namespace, whose keys are kept in the same tables.
+    fn open_namespace(&self, name: &str) -> Result<Self> {
+        Ok(LsmKvsEngine {
+            namespace: name.to_owned(),
+            ..self.clone()
+        })
+    }
+
+    /// Lists the namespaces that have live keys, which scans all the tables.
+    fn namespaces(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = Vec::new();
+        for entry in self.merge_entries(None, "", "")? {
+            if let ((ns, _), Some(_)) = entry? {
+                if names.last() != Some(&ns) {
+                    names.push(ns);
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Watches the keys of the namespace, whose events are sent after the memtable is changed.
+    fn watch(&self, prefix: &str) -> Result<Watcher> {
+        let mut state = self.state.write().unwrap();
+        Ok(state.watchers.watch(&self.namespace, prefix))
+    }
+}
+
+// merges sorted sources into sorted entries, where the source in front wins for equal keys
+struct MergeIter<I: Iterator<Item = Result<Entry>>> {
+    sources: Vec<I>,
+    // the next key of each source with the index of the source
+    heap: BinaryHeap<Reverse<(Key, usize)>>,
+    // the value of the next key of each source
+    values: Vec<Option<String>>,
+}
+
+impl<I: Iterator<Item = Result<Entry>>> MergeIter<I> {
+    fn new(sources: Vec<I>) -> Result<Self> {
+        let mut iter = MergeIter {
+            values: vec![None; sources.len()],
+            sources,
+            heap: BinaryHeap::new(),
+        };
+        for i in 0..iter.sources.len() {
+            iter.advance(i)?;
+        }
+        Ok(iter)
+    }
+
+    // reads the next entry of the source into the heap
+    fn advance(&mut self, i: usize) -> Result<()> {
+        if let Some(entry) = self.sources[i].next() {
+            let (key, value) = entry?;
+            self.values[i] = value;
+            self.heap.push(Reverse((key, i)));
+        }
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Entry>> {
+        let Reverse((key, i)) = match self.heap.pop() {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        let value = self.values[i].take();
+        self.advance(i)?;
+
+        // skips the older entries of the same key
+        while let Some(Reverse((next, _))) = self.heap.peek() {
+            if next != &key {
+                break;
+            }
+            let Reverse((_, j)) = self.heap.pop().unwrap();
+            self.advance(j)?;
+        }
+        Ok(Some((key, value)))
+    }
+}
+
+impl<I: Iterator<Item = Result<Entry>>> Iterator for MergeIter<I> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
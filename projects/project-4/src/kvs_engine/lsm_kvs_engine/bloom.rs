@@ -0,0 +1,73 @@
+use super::Key;
+
+// the number of bits for each key, which gives about 1% false positives with 7 hashes
+const BITS_PER_KEY: usize = 10;
+const HASHES: u32 = 7;
+
+// the bloom filter of keys in one table
+pub(super) struct Bloom {
+    hashes: u32,
+    bits: Vec<u8>,
+}
+
+impl Bloom {
+    // builds the filter from the hashes of keys
+    pub fn new(keys: &[u64]) -> Self {
+        let len = (keys.len() * BITS_PER_KEY).max(64).div_ceil(8);
+        let mut bloom = Bloom {
+            hashes: HASHES,
+            bits: vec![0; len],
+        };
+        for &key in keys {
+            for bit in bloom.probes(key) {
+                bloom.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        bloom
+    }
+
+    // returns false only if the key is definitely not in the table
+    pub fn contains(&self, key: u64) -> bool {
+        self.probes(key)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    // encodes the number of hashes and then the bits
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.hashes.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    // decodes the bytes written by `encode`, returns None if they are malformed
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() <= 4 {
+            return None;
+        }
+        let (hashes, bits) = bytes.split_at(4);
+        Some(Bloom {
+            hashes: u32::from_le_bytes(hashes.try_into().ok()?),
+            bits: bits.to_vec(),
+        })
+    }
+
+    // gets the bits of the key by double hashing
+    fn probes(&self, key: u64) -> impl Iterator<Item = usize> {
+        let len = self.bits.len() as u64 * 8;
+        let delta = key.rotate_right(17) | 1;
+        (0..u64::from(self.hashes))
+            .map(move |i| (key.wrapping_add(i.wrapping_mul(delta)) % len) as usize)
+    }
+}
+
+// hashes the namespace and key with FNV-1a, which is stable across builds unlike the std hasher
+pub(super) fn hash(key: &Key) -> u64 {
+    let (ns, key) = key;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let len = (ns.len() as u32).to_le_bytes();
+    for &byte in len.iter().chain(ns.as_bytes()).chain(key.as_bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
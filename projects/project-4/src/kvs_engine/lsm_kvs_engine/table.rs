@@ -0,0 +1,311 @@
+use super::{
+    bloom::{self, Bloom},
+    Key,
+};
+use crate::{Error, Result};
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Take, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+// the size of data between two keys in the sparse index
+const BLOCK_SIZE: u64 = 4096;
+// the end of each table, which follows the offsets of the index and the bloom filter
+const MAGIC: &[u8; 8] = b"KVSSST\x00\x01";
+const TRAILER_SIZE: u64 = 8 + 8 + 8;
+
+// an entry of tables, where the value is None for removed keys
+pub(super) type Entry = (Key, Option<String>);
+
+// the writer of a new table, which keeps the first key of every block for the sparse index
+//
+// the layout of a table is the sorted entries, the sparse index, the last key,
+// the bloom filter and then the trailer
+pub(super) struct TableWriter {
+    id: u64,
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+    index: Vec<(Key, u64)>,
+    last: Option<Key>,
+    hashes: Vec<u64>,
+}
+
+impl TableWriter {
+    pub fn create(path: PathBuf, id: u64) -> Result<Self> {
+        let writer = BufWriter::new(File::create(&path)?);
+        Ok(TableWriter {
+            id,
+            path,
+            writer,
+            size: 0,
+            index: Vec::new(),
+            last: None,
+            hashes: Vec::new(),
+        })
+    }
+
+    // gets the size of written entries
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // appends the entry, whose key must be greater than the previous one
+    pub fn add(&mut self, key: &Key, value: Option<&str>) -> Result<()> {
+        let block_start = self.index.last().map_or(0, |&(_, offset)| offset);
+        if self.index.is_empty() || self.size - block_start >= BLOCK_SIZE {
+            self.index.push((key.clone(), self.size));
+        }
+
+        self.size += write_key(&mut self.writer, key)?;
+        match value {
+            Some(value) => {
+                self.writer.write_all(&[1])?;
+                self.size += 1 + write_bytes(&mut self.writer, value.as_bytes())?;
+            }
+            None => {
+                self.writer.write_all(&[0])?;
+                self.size += 1;
+            }
+        }
+
+        self.hashes.push(bloom::hash(key));
+        self.last = Some(key.clone());
+        Ok(())
+    }
+
+    // writes the index, bloom filter and trailer and syncs the table,
+    // which must contain at least one entry
+    pub fn finish(mut self) -> Result<Table> {
+        let index_offset = self.size;
+        let mut len = (self.index.len() as u32).to_le_bytes().to_vec();
+        self.writer.write_all(&len)?;
+        for (key, offset) in &self.index {
+            write_key(&mut self.writer, key)?;
+            self.writer.write_all(&offset.to_le_bytes())?;
+        }
+        let last = self.last.expect("empty table");
+        write_key(&mut self.writer, &last)?;
+
+        let bloom = Bloom::new(&self.hashes);
+        let bloom_offset = self.writer.stream_position()?;
+        self.writer.write_all(&bloom.encode())?;
+
+        len.clear();
+        len.extend_from_slice(&index_offset.to_le_bytes());
+        len.extend_from_slice(&bloom_offset.to_le_bytes());
+        len.extend_from_slice(MAGIC);
+        self.writer.write_all(&len)?;
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+
+        Ok(Table {
+            id: self.id,
+            path: self.path,
+            size: self.writer.stream_position()?,
+            index: self.index,
+            last,
+            data_end: index_offset,
+            bloom,
+            obsolete: AtomicBool::new(false),
+        })
+    }
+}
+
+// a sorted table on disk with its sparse index and bloom filter in memory
+pub(super) struct Table {
+    pub id: u64,
+    path: PathBuf,
+    // the size of the file
+    pub size: u64,
+    // the first key of each block with the offset of the block
+    index: Vec<(Key, u64)>,
+    last: Key,
+    // the end of entries, which is also the offset of the index
+    data_end: u64,
+    bloom: Bloom,
+    // obsolete tables are removed from disk after the last reader drops them
+    obsolete: AtomicBool,
+}
+
+impl Table {
+    // reads the index and bloom filter of the table
+    pub fn open(path: PathBuf, id: u64) -> Result<Self> {
+        let invalid = || Error::InvalidTable(format!("Malformed {}", path.display()));
+
+        let mut file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        if size < TRAILER_SIZE {
+            return Err(invalid());
+        }
+        let mut trailer = [0; TRAILER_SIZE as usize];
+        file.seek(SeekFrom::Start(size - TRAILER_SIZE))?;
+        file.read_exact(&mut trailer)?;
+        let index_offset = u64::from_le_bytes(trailer[0..8].try_into().unwrap());
+        let bloom_offset = u64::from_le_bytes(trailer[8..16].try_into().unwrap());
+        if &trailer[16..] != MAGIC
+            || index_offset > bloom_offset
+            || bloom_offset > size - TRAILER_SIZE
+        {
+            return Err(invalid());
+        }
+
+        let mut meta = vec![0; (size - TRAILER_SIZE - index_offset) as usize];
+        file.seek(SeekFrom::Start(index_offset))?;
+        file.read_exact(&mut meta)?;
+        let (mut reader, bloom) = meta.split_at((bloom_offset - index_offset) as usize);
+
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let mut index = Vec::new();
+        for _ in 0..u32::from_le_bytes(len) {
+            let key = read_key(&mut reader)?;
+            let mut offset = [0; 8];
+            reader.read_exact(&mut offset)?;
+            index.push((key, u64::from_le_bytes(offset)));
+        }
+        let last = read_key(&mut reader)?;
+        if index.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Table {
+            id,
+            path: path.clone(),
+            size,
+            index,
+            last,
+            data_end: index_offset,
+            bloom: Bloom::decode(bloom).ok_or_else(invalid)?,
+            obsolete: AtomicBool::new(false),
+        })
+    }
+
+    pub fn first(&self) -> &Key {
+        &self.index[0].0
+    }
+
+    pub fn last(&self) -> &Key {
+        &self.last
+    }
+
+    // marks the table to be removed once it is dropped
+    pub fn set_obsolete(&self) {
+        self.obsolete.store(true, Ordering::SeqCst);
+    }
+
+    // gets the entry of the key, returns None if the table does not contain the key
+    pub fn get(&self, key: &Key) -> Result<Option<Option<String>>> {
+        if key < self.first() || key > self.last() || !self.bloom.contains(bloom::hash(key)) {
+            return Ok(None);
+        }
+
+        let block = self.index.partition_point(|(first, _)| first <= key) - 1;
+        let start = self.index[block].1;
+        let end = self
+            .index
+            .get(block + 1)
+            .map_or(self.data_end, |&(_, offset)| offset);
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut buffer = vec![0; (end - start) as usize];
+        file.read_exact(&mut buffer)?;
+
+        let mut reader = buffer.as_slice();
+        while !reader.is_empty() {
+            let (entry_key, value) = read_entry(&mut reader)?;
+            if &entry_key == key {
+                return Ok(Some(value));
+            } else if &entry_key > key {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    // iterates the entries from the first key not less than `start`
+    pub fn iter_from(&self, start: &Key) -> Result<TableIter> {
+        let block = self
+            .index
+            .partition_point(|(first, _)| first <= start)
+            .saturating_sub(1);
+        let offset = self.index[block].1;
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(TableIter {
+            reader: BufReader::new(file).take(self.data_end - offset),
+            start: start.clone(),
+        })
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        if self.obsolete.load(Ordering::SeqCst) {
+            // the file is removed when opening the store again if failed here
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// the iterator over the entries of a table in order
+pub(super) struct TableIter {
+    reader: Take<BufReader<File>>,
+    // the entries before start are skipped
+    start: Key,
+}
+
+impl Iterator for TableIter {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.reader.limit() > 0 {
+            match read_entry(&mut self.reader) {
+                Ok((key, _)) if key < self.start => continue,
+                entry => return Some(entry),
+            }
+        }
+        None
+    }
+}
+
+// writes the length as u32 in little endian and then the bytes, returns the written size
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> Result<u64> {
+    let len = u32::try_from(bytes.len())
+        .map_err(|_| Error::InvalidTable(String::from("Entry too large")))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(4 + bytes.len() as u64)
+}
+
+fn write_key(writer: &mut impl Write, (ns, key): &Key) -> Result<u64> {
+    Ok(write_bytes(writer, ns.as_bytes())? + write_bytes(writer, key.as_bytes())?)
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| Error::InvalidTable(e.to_string()))
+}
+
+fn read_key(reader: &mut impl Read) -> Result<Key> {
+    Ok((read_string(reader)?, read_string(reader)?))
+}
+
+fn read_entry(reader: &mut impl Read) -> Result<Entry> {
+    let key = read_key(reader)?;
+    let mut tag = [0; 1];
+    reader.read_exact(&mut tag)?;
+    match tag[0] {
+        0 => Ok((key, None)),
+        1 => Ok((key, Some(read_string(reader)?))),
+        tag => Err(Error::InvalidTable(format!("Unknown tag {}", tag))),
+    }
+}
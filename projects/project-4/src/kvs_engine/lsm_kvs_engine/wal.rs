@@ -0,0 +1,89 @@
+use crate::{Error, Result};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const WAL_FILE: &str = "wal.log";
+
+// one write in the WAL, which is a json object in one line
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct WalRecord {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub ns: String,
+    pub key: String,
+    // None for removed keys
+    pub value: Option<String>,
+}
+
+// the write-ahead log of the writes in the memtable
+pub(super) struct Wal {
+    writer: BufWriter<File>,
+}
+
+impl Wal {
+    // replays the records in the WAL of the directory with `f`,
+    // drops the torn last record left by a crash and then opens the WAL for appending,
+    // or fails if any other record is corrupted
+    pub fn open(dir: &Path, mut f: impl FnMut(WalRecord)) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(WAL_FILE))?;
+
+        let mut reader = BufReader::new(&file);
+        let mut line = String::new();
+        let mut len = 0;
+        loop {
+            line.clear();
+            let n = reader.read_line(&mut line)?;
+            if n == 0 {
+                break;
+            }
+            let torn = !line.ends_with('\n');
+            match serde_json::from_str(&line) {
+                Ok(record) if !torn => f(record),
+                _ if reader.fill_buf()?.is_empty() => break,
+                _ => {
+                    return Err(Error::InvalidWal(format!(
+                        "Corrupted record at offset {}",
+                        len
+                    )))
+                }
+            }
+            len += n as u64;
+        }
+
+        file.set_len(len)?;
+        file.seek(SeekFrom::Start(len))?;
+        Ok(Wal {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    // appends the record without flushing
+    pub fn append(&mut self, record: &WalRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    // empties the WAL after the memtable is written into a table
+    pub fn reset(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use super::{Event, KvsEngine, Watcher, Watchers};
+use crate::{dump, Error, Result};
+
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+#[derive(Default)]
+struct MemState {
+    // the keys and values of each namespace
+    namespaces: HashMap<String, HashMap<String, String>>,
+    // watchers are sent the events after the map is changed
+    watchers: Watchers,
+}
+
+/// A store engine that keeps all keys in memory, which are lost after all handles are dropped.
+///
+/// It is designed for tests that do not want to create store directories.
+#[derive(Clone, Default)]
+pub struct MemKvsEngine {
+    // readers share the lock, and writers only hold it to change the map
+    state: Arc<RwLock<MemState>>,
+    // the namespace of keys in this handle, where the empty string is the default namespace
+    namespace: String,
+}
+
+impl MemKvsEngine {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        MemKvsEngine::default()
+    }
+
+    /// Creates a store with the keys of a dump in either format,
+    /// which are put in the namespaces recorded in the dump.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kvs::{KvsEngine, MemKvsEngine};
+    ///
+    /// # fn main() -> kvs::Result<()> {
+    /// let dump = "{\"key\":\"k\",\"value\":\"v\"}\n";
+    /// let store = MemKvsEngine::seed(dump.as_bytes())?;
+    /// assert_eq!(store.get("k".to_owned())?, Some("v".to_owned()));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn seed(reader: impl Read) -> Result<Self> {
+        let engine = MemKvsEngine::new();
+        dump::load(&engine, None, reader)?;
+        Ok(engine)
+    }
+}
+
+impl KvsEngine for MemKvsEngine {
+    /// Creates an empty store, where the path is ignored since nothing is persisted.
+    fn open(_path: impl Into<PathBuf>) -> Result<Self> {
+        Ok(MemKvsEngine::new())
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        if !state.watchers.is_empty() {
+            let event = Event::Set {
+                key: key.clone(),
+                value: value.clone(),
+            };
+            state.watchers.notify(&self.namespace, event);
+        }
+        state
+            .namespaces
+            .entry(self.namespace.clone())
+            .or_default()
+            .insert(key, value);
+        Ok(())
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        let state = self.state.read().unwrap();
+        Ok(state
+            .namespaces
+            .get(&self.namespace)
+            .and_then(|keys| keys.get(&key))
+            .cloned())
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        let keys = state
+            .namespaces
+            .get_mut(&self.namespace)
+            .ok_or(Error::KeyNotFound)?;
+        keys.remove(&key).ok_or(Error::KeyNotFound)?;
+        if keys.is_empty() {
+            state.namespaces.remove(&self.namespace);
+        }
+        state.watchers.notify(&self.namespace, Event::Rm { key });
+        Ok(())
+    }
+
+    /// Visits each key and value in the snapshot of the namespace,
+    /// so that `f` can write the store without deadlocks.
+    fn for_each(&self, mut f: impl FnMut(String, String) -> Result<()>) -> Result<()> {
+        let pairs: Vec<(String, String)> = {
+            let state = self.state.read().unwrap();
+            state
+                .namespaces
+                .get(&self.namespace)
+                .map(|keys| {
+                    keys.iter()
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        for (key, value) in pairs {
+            f(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Sorts only the keys of the page, whose values are cloned.
+    fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let state = self.state.read().unwrap();
+        let mut pairs: Vec<_> = state
+            .namespaces
+            .get(&self.namespace)
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| {
+                key.starts_with(prefix) && after.is_none_or(|after| key.as_str() > after)
+            })
+            .collect();
+        pairs.sort_unstable_by_key(|&(key, _)| key);
+        Ok(pairs
+            .into_iter()
+            .take(limit)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn load(&self, pairs: impl IntoIterator<Item = Result<(String, String)>>) -> Result<usize> {
+        let mut count = 0;
+        for pair in pairs {
+            let (key, value) = pair?;
+            self.set(key, value)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn open_namespace(&self, name: &str) -> Result<Self> {
+        Ok(MemKvsEngine {
+            state: Arc::clone(&self.state),
+            namespace: name.to_owned(),
+        })
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>> {
+        let state = self.state.read().unwrap();
+        let mut names: Vec<String> = state.namespaces.keys().cloned().collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn watch(&self, prefix: &str) -> Result<Watcher> {
+        let mut state = self.state.write().unwrap();
+        Ok(state.watchers.watch(&self.namespace, prefix))
+    }
+}
@@ -0,0 +1,186 @@
+use super::{KvStore, KvsEngine, Watcher};
+use crate::{Error, Result};
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// the file that keeps the number of shards, which never changes after the store is created
+const SHARDS_FILE: &str = "SHARDS";
+// the number of pairs loaded into one shard at a time
+const LOAD_BATCH: usize = 1024;
+
+/// A store engine that hashes keys across independent `KvStore`s in the subdirectories `shard.N`,
+/// where each shard has its own writer, index and compaction,
+/// so that writes to different shards do not wait for each other.
+#[derive(Clone)]
+pub struct ShardedKvStore {
+    shards: Vec<KvStore>,
+}
+
+impl ShardedKvStore {
+    /// Opens the store with the number of shards it was created with,
+    /// or creates it with one shard for each cpu.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path: PathBuf = path.into();
+        let shards = ShardedKvStore::load_shards(&path)?.unwrap_or_else(num_cpus::get);
+        ShardedKvStore::open_with_shards(path, shards)
+    }
+
+    /// Opens or creates the store with the given number of shards,
+    /// which must be the same as the one it was created with.
+    pub fn open_with_shards(path: impl Into<PathBuf>, shards: usize) -> Result<Self> {
+        let path: PathBuf = path.into();
+        match ShardedKvStore::load_shards(&path)? {
+            Some(n) if n != shards => {
+                return Err(Error::InvalidManifest(format!(
+                    "Created with {} shards but opened with {}",
+                    n, shards
+                )))
+            }
+            Some(_) => (),
+            None if shards == 0 => {
+                return Err(Error::InvalidManifest(String::from("No shards")));
+            }
+            None => {
+                fs::create_dir_all(&path)?;
+                fs::write(path.join(SHARDS_FILE), shards.to_string())?;
+            }
+        }
+
+        let shards = (0..shards)
+            .map(|n| KvStore::open(path.join(format!("shard.{}", n))))
+            .collect::<Result<_>>()?;
+        Ok(ShardedKvStore { shards })
+    }
+
+    // reads the number of shards, returns None if the store is not created
+    fn load_shards(path: &Path) -> Result<Option<usize>> {
+        let path = path.join(SHARDS_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        match fs::read_to_string(path)?.trim().parse() {
+            Ok(0) | Err(_) => Err(Error::InvalidManifest(String::from("Malformed SHARDS"))),
+            Ok(n) => Ok(Some(n)),
+        }
+    }
+
+    // gets the shard number of the key by FNV-1a, which is stable across builds unlike the std hasher
+    fn shard_of(&self, key: &str) -> usize {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for &byte in key.as_bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        (hash % self.shards.len() as u64) as usize
+    }
+}
+
+impl KvsEngine for ShardedKvStore {
+    fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        ShardedKvStore::open(path)
+    }
+
+    /// Sets the value in the shard of the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tempfile::TempDir;
+    /// use kvs::{KvsEngine, ShardedKvStore};
+    ///
+    /// # fn main() -> kvs::Result<()> {
+    /// let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    /// let store = ShardedKvStore::open_with_shards(temp_dir.path(), 4)?;
+    ///
+    /// store.set("k".to_owned(), "v".to_owned())?;
+    /// assert_eq!(store.get("k".to_owned())?, Some("v".to_owned()));
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.shards[self.shard_of(&key)].set(key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.shards[self.shard_of(&key)].get(key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.shards[self.shard_of(&key)].remove(key)
+    }
+
+    /// Visits the keys of each shard in turn.
+    fn for_each(&self, mut f: impl FnMut(String, String) -> Result<()>) -> Result<()> {
+        for shard in &self.shards {
+            shard.for_each(&mut f)?;
+        }
+        Ok(())
+    }
+
+    /// Merges the pages of all shards, since the keys are spread across shards by their hashes.
+    fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let mut pairs = Vec::new();
+        for shard in &self.shards {
+            pairs.extend(shard.scan(prefix, after, limit)?);
+        }
+        pairs.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        pairs.truncate(limit);
+        Ok(pairs)
+    }
+
+    /// Groups the pairs by shards and loads them in batches.
+    fn load(&self, pairs: impl IntoIterator<Item = Result<(String, String)>>) -> Result<usize> {
+        let mut batches = vec![Vec::new(); self.shards.len()];
+        let mut count = 0;
+        for pair in pairs {
+            let (key, value) = pair?;
+            let n = self.shard_of(&key);
+            batches[n].push((key, value));
+            if batches[n].len() >= LOAD_BATCH {
+                count += self.shards[n].load(batches[n].drain(..).map(Ok))?;
+            }
+        }
+        for (shard, batch) in self.shards.iter().zip(batches) {
+            count += shard.load(batch.into_iter().map(Ok))?;
+        }
+        Ok(count)
+    }
+
+    fn open_namespace(&self, name: &str) -> Result<Self> {
+        let shards = self
+            .shards
+            .iter()
+            .map(|shard| shard.open_namespace(name))
+            .collect::<Result<_>>()?;
+        Ok(ShardedKvStore { shards })
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for shard in &self.shards {
+            names.extend(shard.namespaces()?);
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// Watches the keys of every shard, whose events are received from all shards at once,
+    /// so the events of one key are in order but the events of different keys may be not.
+    fn watch(&self, prefix: &str) -> Result<Watcher> {
+        let watchers = self
+            .shards
+            .iter()
+            .map(|shard| shard.watch(prefix))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Watcher::merge(watchers))
+    }
+}
@@ -1,19 +1,24 @@
-use super::KvsEngine;
+use super::{Event, KvsEngine, Watcher};
 use crate::{Error, Result};
 
-use std::path::PathBuf;
+use std::{ops::Bound, path::PathBuf};
+
+// the name of the default tree in sled, which keeps the keys of the default namespace
+const DEFAULT_TREE: &[u8] = b"__sled__default";
 
 /// A store that just wraps the sled.
 #[derive(Clone)]
 pub struct SledKvsEngine {
     db: sled::Db,
+    // the tree of the namespace, which is the default tree of the db for the default namespace
+    tree: sled::Tree,
 }
 
 impl SledKvsEngine {
     pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
-        Ok(SledKvsEngine {
-            db: sled::open(path.into())?,
-        })
+        let db = sled::open(path.into())?;
+        let tree = sled::Tree::clone(&db);
+        Ok(SledKvsEngine { db, tree })
     }
 }
 
@@ -23,23 +28,119 @@ impl KvsEngine for SledKvsEngine {
     }
 
     fn set(&self, key: String, value: String) -> Result<()> {
-        self.db.insert(key.as_str(), value.as_str())?;
+        self.tree.insert(key.as_str(), value.as_str())?;
         self.db.flush()?;
         Ok(())
     }
 
     fn get(&self, key: String) -> Result<Option<String>> {
-        let value = self.db.get(key.as_str())?;
+        let value = self.tree.get(key.as_str())?;
         Ok(value.map(|val| String::from_utf8_lossy(val.as_ref()).to_string()))
     }
 
     fn remove(&self, key: String) -> Result<()> {
-        if self.db.contains_key(key.as_str())? {
-            self.db.remove(key.as_str())?;
+        if self.tree.contains_key(key.as_str())? {
+            self.tree.remove(key.as_str())?;
             self.db.flush()?;
             Ok(())
         } else {
             Err(Error::KeyNotFound)
         }
     }
+
+    fn for_each(&self, mut f: impl FnMut(String, String) -> Result<()>) -> Result<()> {
+        for pair in self.tree.iter() {
+            let (key, value) = pair?;
+            f(
+                String::from_utf8_lossy(key.as_ref()).to_string(),
+                String::from_utf8_lossy(value.as_ref()).to_string(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads the range of the tree from the prefix or after the given key.
+    fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after.as_bytes()),
+            _ => Bound::Included(prefix.as_bytes()),
+        };
+        let mut pairs = Vec::new();
+        for pair in self.tree.range::<&[u8], _>((start, Bound::Unbounded)) {
+            if pairs.len() >= limit {
+                break;
+            }
+            let (key, value) = pair?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            pairs.push((
+                String::from_utf8_lossy(key.as_ref()).to_string(),
+                String::from_utf8_lossy(value.as_ref()).to_string(),
+            ));
+        }
+        Ok(pairs)
+    }
+
+    fn load(&self, pairs: impl IntoIterator<Item = Result<(String, String)>>) -> Result<usize> {
+        let mut count = 0;
+        for pair in pairs {
+            let (key, value) = pair?;
+            self.tree.insert(key.as_str(), value.as_str())?;
+            count += 1;
+        }
+        self.db.flush()?;
+        Ok(count)
+    }
+
+    fn open_namespace(&self, name: &str) -> Result<Self> {
+        let tree = if name.is_empty() {
+            sled::Tree::clone(&self.db)
+        } else {
+            self.db.open_tree(name)?
+        };
+        Ok(SledKvsEngine {
+            db: self.db.clone(),
+            tree,
+        })
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self
+            .db
+            .tree_names()
+            .into_iter()
+            .map(|name| {
+                if name == DEFAULT_TREE {
+                    String::new()
+                } else {
+                    String::from_utf8_lossy(name.as_ref()).to_string()
+                }
+            })
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn watch(&self, prefix: &str) -> Result<Watcher> {
+        Ok(Watcher::sled(self.tree.watch_prefix(prefix)))
+    }
+}
+
+// converts the event of sled into the one of engines
+pub(super) fn event_of(event: sled::Event) -> Event {
+    match event {
+        sled::Event::Insert { key, value } => Event::Set {
+            key: String::from_utf8_lossy(key.as_ref()).to_string(),
+            value: String::from_utf8_lossy(value.as_ref()).to_string(),
+        },
+        sled::Event::Remove { key } => Event::Rm {
+            key: String::from_utf8_lossy(key.as_ref()).to_string(),
+        },
+    }
 }
@@ -1,20 +1,56 @@
-use crate::{kvs_engine::KvsEngine, thread_pool::*, Command, Error, Response, Result};
+use crate::{
+    acl::{Acl, Permission},
+    auth::Credentials,
+    codec::MAX_PREFIX_LEN,
+    kvs_engine::KvsEngine,
+    stream::Stream,
+    thread_pool::*,
+    tls::ServerConfig,
+    Codec, Command, Error, Handshake, Request, Response, Result, MIN_PROTOCOL_VERSION,
+};
 
-use slog::{info, Logger};
+use crossbeam_channel::RecvTimeoutError;
+use slog::{info, warn, Logger};
 use std::{
-    io::{BufRead, BufReader, Read, Write},
-    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
 };
 
+// the default limit of the length of one request
+const MAX_REQUEST_SIZE: usize = 16 * 1024 * 1024;
+// the default limit of the connections served at the same time
+const MAX_CONNECTIONS: usize = 1024;
+// the default limit of the watches streamed at the same time
+const MAX_WATCHES: usize = 256;
+// the failed authentications allowed on one connection before it is closed
+const MAX_AUTH_FAILURES: usize = 5;
+// the default time a connection may stay silent before it is closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+// the interval to check whether the clients of watches without events are still connected
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 /// A type that abstracts the kvs server.
 pub struct KvsServer<E: KvsEngine, T: ThreadPool> {
     logger: Logger,
     listener: TcpListener,
     engine: E,
-    thread_pool: T,
+    thread_pool: Arc<T>,
+    max_request_size: usize,
+    max_connections: usize,
+    max_watches: usize,
+    idle_timeout: Option<Duration>,
+    tls: Option<Arc<ServerConfig>>,
+    credentials: Option<Arc<Credentials>>,
+    acl: Option<Arc<Acl>>,
 }
 
-impl<E: KvsEngine, T: ThreadPool> KvsServer<E, T> {
+impl<E: KvsEngine, T: ThreadPool + Send + Sync + 'static> KvsServer<E, T> {
     /// Creates a server with a logger, a listening address, a store engine and a thread pool.
     pub fn new(logger: Logger, addr: SocketAddr, engine: E, thread_pool: T) -> Result<Self> {
         let listener = TcpListener::bind(addr)?;
@@ -22,33 +58,107 @@ impl<E: KvsEngine, T: ThreadPool> KvsServer<E, T> {
             logger,
             listener,
             engine,
-            thread_pool,
+            thread_pool: Arc::new(thread_pool),
+            max_request_size: MAX_REQUEST_SIZE,
+            max_connections: MAX_CONNECTIONS,
+            max_watches: MAX_WATCHES,
+            idle_timeout: Some(IDLE_TIMEOUT),
+            tls: None,
+            credentials: None,
+            acl: None,
         })
     }
 
+    /// Encrypts the following connections with TLS in the given configuration,
+    /// which can be built by [`tls::server_config`](crate::tls::server_config).
+    ///
+    /// Clients must then connect with [`KvsClient::connect_tls`](crate::KvsClient::connect_tls).
+    pub fn set_tls(&mut self, config: Arc<ServerConfig>) {
+        self.tls = Some(config);
+    }
+
+    /// Requires the following connections to authenticate with `Command::Auth` by the given credentials
+    /// before any other commands, which are answered with `Response::Unauthenticated` until then.
+    ///
+    /// Connections are closed after 5 failed authentications.
+    pub fn set_credentials(&mut self, credentials: Arc<Credentials>) {
+        self.credentials = Some(credentials);
+    }
+
+    /// Authorizes the commands of the following connections by the rules of the ACL,
+    /// where the users are the ones authenticated by the credentials.
+    ///
+    /// Commands not granted are answered with `Response::PermissionDenied` and logged.
+    pub fn set_acl(&mut self, acl: Arc<Acl>) {
+        self.acl = Some(acl);
+    }
+
+    /// Sets the limit of the length of one request in bytes, which is 16 MiB by default,
+    /// larger requests are discarded and answered with `Response::Fail`,
+    /// and the connection is closed after requests longer than twice the limit.
+    pub fn set_max_request_size(&mut self, size: usize) {
+        self.max_request_size = size;
+    }
+
+    /// Sets the limit of the connections served at the same time, which is 1024 by default,
+    /// further connections are closed as soon as they are accepted.
+    pub fn set_max_connections(&mut self, connections: usize) {
+        self.max_connections = connections;
+    }
+
+    /// Sets the limit of the watches streamed at the same time, which is 256 by default,
+    /// further watches are answered with `Response::Fail`.
+    pub fn set_max_watches(&mut self, watches: usize) {
+        self.max_watches = watches;
+    }
+
+    /// Sets the time a connection may stay silent between requests before it is closed,
+    /// which is 5 minutes by default, or never if `None`. Watches are never closed for idleness.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
     /// Starts receiving requests and replying responses.
     ///
-    /// Quits after processing `N` tasks with `Some(N)` as `tasks`,
-    /// or keeps running if `tasks` is `None`.
+    /// Each connection is read by its own thread until the client closes it, while its requests
+    /// are processed one by one in the pool, so that idle connections never hold threads of the pool.
+    /// Quits after accepting `N` connections with `Some(N)` as `tasks`, or keeps running if `tasks` is `None`.
     ///
     /// NOTE: the tasks is designed for benchmarks
     pub fn run(&mut self, tasks: Option<usize>) -> Result<()> {
+        let connections = Limit::new(self.max_connections);
+        let watches = Limit::new(self.max_watches);
         let mut tasks_cnt = 0;
         for stream in self.listener.incoming() {
-            let mut stream = stream?;
+            let stream = stream?;
             info!(
                 self.logger,
                 "Accept connection from: {:?}",
                 stream.peer_addr()?
             );
 
-            let logger = self.logger.clone();
-            let engine = self.engine.clone();
-            self.thread_pool.spawn(move || {
-                let command = read_command(&logger, &stream).unwrap();
-                let response = process_command(engine.clone(), command).unwrap();
-                respond(&logger, &mut stream, response).unwrap();
-            });
+            match connections.acquire() {
+                Some(slot) => {
+                    stream.set_read_timeout(self.idle_timeout)?;
+                    let context = self.context(&watches);
+                    let tls = self.tls.clone();
+                    thread::spawn(move || {
+                        let _slot = slot;
+                        // the handshake of TLS runs in the thread, so that slow clients never block accepting
+                        let stream = match tls {
+                            Some(config) => Stream::accept(stream, config),
+                            None => Ok(Stream::Tcp(stream)),
+                        };
+                        if let Err(e) = stream
+                            .map_err(Error::from)
+                            .and_then(|stream| serve(&context, stream))
+                        {
+                            info!(context.logger, "Connection closed: {}", e);
+                        }
+                    });
+                }
+                None => warn!(self.logger, "Rejected connection: too many connections"),
+            }
 
             tasks_cnt += 1;
             if let Some(tasks) = tasks {
@@ -61,59 +171,441 @@ impl<E: KvsEngine, T: ThreadPool> KvsServer<E, T> {
         Ok(())
     }
 
+    // gets the state shared with the thread of a connection
+    fn context(&self, watches: &Limit) -> Context<E, T> {
+        Context {
+            logger: self.logger.clone(),
+            engine: self.engine.clone(),
+            thread_pool: Arc::clone(&self.thread_pool),
+            max_request_size: self.max_request_size,
+            watches: watches.clone(),
+            credentials: self.credentials.clone(),
+            acl: self.acl.clone(),
+        }
+    }
+
     // Gets the local address of the server
     pub fn local_addr(&self) -> SocketAddr {
         self.listener.local_addr().unwrap()
     }
 }
 
-// reads one command from the stream
-fn read_command(logger: &Logger, stream: &TcpStream) -> Result<Command> {
-    let mut reader = BufReader::new(stream);
+// A type that represents the state shared by the server and the threads of its connections.
+struct Context<E, T> {
+    logger: Logger,
+    engine: E,
+    thread_pool: Arc<T>,
+    max_request_size: usize,
+    watches: Limit,
+    credentials: Option<Arc<Credentials>>,
+    acl: Option<Arc<Acl>>,
+}
+
+// A type that counts the connections or the watches in progress up to a limit.
+#[derive(Clone)]
+pub(crate) struct Limit {
+    count: Arc<AtomicUsize>,
+    max: usize,
+}
+
+// A type that represents one taken slot of a limit, which is released when it is dropped.
+pub(crate) struct Slot(Arc<AtomicUsize>);
+
+impl Limit {
+    pub(crate) fn new(max: usize) -> Self {
+        Limit {
+            count: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    // takes one slot, or returns None if all slots are taken
+    pub(crate) fn acquire(&self) -> Option<Slot> {
+        self.count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < self.max).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Slot(Arc::clone(&self.count)))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// serves the requests on the connection in order until the client closes it,
+// or streams events if a watch is requested
+//
+// The connection may start with a handshake, otherwise it is served once as the version 1 of the protocol.
+// If credentials are given, only authentications are served until one succeeds.
+// Requests are processed in the pool, and this thread only waits for them between reads and writes.
+fn serve<E: KvsEngine, T: ThreadPool>(context: &Context<E, T>, stream: Stream) -> Result<()> {
+    let logger = &context.logger;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    let mut first = true;
+    let mut codec = Codec::Text;
+    let mut user = None;
+    let mut auth_failures = 0;
+    loop {
+        let payload = match read_payload(&mut reader, context.max_request_size) {
+            Ok(Some(payload)) => payload,
+            Ok(None) => return Ok(()),
+            Err(e @ Error::RequestTooLarge(len)) => {
+                info!(logger, "Rejected request: {}", e);
+                // clients of the version 1 send no handshakes and read unframed responses until closing
+                if first {
+                    stream.write_all(&Codec::Text.encode(&Response::Fail(e.to_string()))?)?;
+                    stream.shutdown(Shutdown::Write)?;
+                    return Err(e);
+                }
+                write_frame(&mut stream, codec, &Response::Fail(e.to_string()))?;
+                // skips the payload to keep the following requests framed,
+                // unless it is too large to be worth reading
+                if len > context.max_request_size.saturating_mul(2) {
+                    return Err(e);
+                }
+                io::copy(&mut (&mut reader).take(len as u64), &mut io::sink())?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        if std::mem::take(&mut first) {
+            if let Ok(handshake) = Codec::Text.decode::<Handshake>(&payload) {
+                info!(logger, "Received handshake: {:?}", handshake);
+                let response = negotiate(handshake);
+                write_frame(&mut stream, Codec::Text, &response)?;
+                info!(logger, "Response: {:?}", response);
+                match response {
+                    Response::Handshake(handshake) => codec = Codec::of(&handshake),
+                    Response::Fail(e) => return Err(Error::IncompatibleProtocol(e)),
+                    _ => unreachable!(),
+                }
+                continue;
+            }
+            return serve_once(context, stream, &payload);
+        }
+        let request = parse_request(codec, &payload)?;
+        if let Command::Auth { user: name, secret } = request.command {
+            // never logs the secret
+            info!(logger, "Received authentication: {:?}", name);
+            let response = match &context.credentials {
+                Some(credentials) => {
+                    // hashing passwords is slow, so it runs in the pool like other requests
+                    let credentials = Arc::clone(credentials);
+                    let verified = execute(&*context.thread_pool, move || {
+                        credentials.verify(name.as_deref(), &secret)
+                    })?;
+                    // a failed authentication also drops the user authenticated before
+                    user = None;
+                    match verified {
+                        Ok(name) => {
+                            user = Some(name.clone());
+                            Response::SuccessAuth(name)
+                        }
+                        Err(Error::Unauthenticated(msg)) => {
+                            auth_failures += 1;
+                            Response::Unauthenticated(msg)
+                        }
+                        Err(e) => Response::Fail(e.to_string()),
+                    }
+                }
+                None => Response::Fail(String::from("Authentication is not enabled")),
+            };
+            write_frame(&mut stream, codec, &response)?;
+            info!(logger, "Response: {:?}", response);
+            // guessing secrets needs reconnecting after a few failures
+            if auth_failures >= MAX_AUTH_FAILURES {
+                warn!(
+                    logger,
+                    "Closed connection after {} failed authentications", auth_failures
+                );
+                return Ok(());
+            }
+            continue;
+        }
+        info!(logger, "Received request: {:?}", request);
+        if context.credentials.is_some() && user.is_none() {
+            let response = Response::Unauthenticated(String::from("Authentication required"));
+            write_frame(&mut stream, codec, &response)?;
+            info!(logger, "Response: {:?}", response);
+            continue;
+        }
+
+        if let Command::Watch { key, prefix } = &request.command {
+            let acl = context.acl.as_deref();
+            let namespace = &request.namespace;
+            if let Err(e) = authorize(logger, acl, user.as_deref(), namespace, &request.command) {
+                let response = response_of(e);
+                write_frame(&mut stream, codec, &response)?;
+                info!(logger, "Response: {:?}", response);
+                continue;
+            }
+            let _slot = match context.watches.acquire() {
+                Some(slot) => slot,
+                None => {
+                    let response = Response::Fail(String::from("Too many watches"));
+                    write_frame(&mut stream, codec, &response)?;
+                    info!(logger, "Response: {:?}", response);
+                    continue;
+                }
+            };
+            let (key, prefix) = (key.clone(), *prefix);
+            // the connection only streams events from now on
+            let engine = context.engine.clone();
+            if let Err(e) = watch(
+                logger,
+                engine,
+                &request.namespace,
+                key,
+                prefix,
+                codec,
+                stream,
+            ) {
+                info!(logger, "Watch ended: {}", e);
+            }
+            return Ok(());
+        }
+
+        let response = process(context, request, user.clone())?;
+        write_frame(&mut stream, codec, &response)?;
+        info!(logger, "Response: {:?}", response);
+    }
+}
+
+// serves the only command of the version 1, whose clients send it without handshakes in the text codec,
+// and read the response without its length until the connection is closed
+fn serve_once<E: KvsEngine, T: ThreadPool>(
+    context: &Context<E, T>,
+    mut stream: Stream,
+    payload: &[u8],
+) -> Result<()> {
+    let logger = &context.logger;
+    let request = parse_request(Codec::Text, payload)?;
+    info!(logger, "Received request: {:?}", request);
+    let response = match request.command {
+        Command::Watch { .. } | Command::Auth { .. } => Response::Fail(String::from(
+            "The command is not supported without handshakes",
+        )),
+        _ if context.credentials.is_some() => {
+            Response::Unauthenticated(String::from("Authentication required"))
+        }
+        _ => process(context, request, None)?,
+    };
+    stream.write_all(&Codec::Text.encode(&response)?)?;
+    stream.shutdown(Shutdown::Write)?;
+    info!(logger, "Response: {:?}", response);
+    Ok(())
+}
+
+// processes the request of the user in the pool, and waits for its response
+fn process<E: KvsEngine, T: ThreadPool>(
+    context: &Context<E, T>,
+    request: Request,
+    user: Option<String>,
+) -> Result<Response> {
+    let logger = context.logger.clone();
+    let engine = context.engine.clone();
+    let acl = context.acl.clone();
+    execute(&*context.thread_pool, move || {
+        process_request(&logger, engine, request, user.as_deref(), acl.as_deref())
+            .unwrap_or_else(response_of)
+    })
+}
+
+// replies the handshake with the version and the features supported by both sides,
+// or fails if the version of the client is too old to be served
+fn negotiate(handshake: Handshake) -> Response {
+    let server = Handshake::new();
+    let version = handshake.version.min(server.version);
+    if version < MIN_PROTOCOL_VERSION {
+        return Response::Fail(format!(
+            "Unsupported protocol version {}, the server supports versions {} to {}",
+            handshake.version, MIN_PROTOCOL_VERSION, server.version
+        ));
+    }
 
+    let features = handshake
+        .features
+        .into_iter()
+        .filter(|feature| server.supports(feature))
+        .collect();
+    Response::Handshake(Handshake { version, features })
+}
+
+// reads one payload written with its length, returns None if the connection is closed,
+// and leaves the payload unread if it is longer than the given limit
+fn read_payload(reader: &mut impl BufRead, max_request_size: usize) -> Result<Option<Vec<u8>>> {
     let mut buffer = Vec::new();
-    let len = reader.read_until(b'#', &mut buffer)?;
+    let len = reader
+        .by_ref()
+        .take(MAX_PREFIX_LEN)
+        .read_until(b'#', &mut buffer)?;
+    if len == 0 {
+        return Ok(None);
+    }
 
-    let len: usize = std::str::from_utf8(&buffer[0..len - 1])
-        .unwrap()
-        .parse()
-        .unwrap();
-    let mut buffer = [0; 1024];
-    reader.read_exact(&mut buffer[0..len])?;
+    let len: usize = buffer
+        .strip_suffix(b"#")
+        .and_then(|len| std::str::from_utf8(len).ok())
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| Error::SerdeError(String::from("Expected length")))?;
+    if len > max_request_size {
+        return Err(Error::RequestTooLarge(len));
+    }
+    let mut buffer = vec![0; len];
+    reader.read_exact(&mut buffer)?;
+
+    Ok(Some(buffer))
+}
 
-    let request = std::str::from_utf8(&buffer[0..len]).unwrap();
-    let command = crate::de::from_str(request)?;
-    info!(logger, "Received command: {:?}", command);
+// parses the request in the codec,
+// and commands in the default namespace are sent without namespaces in the text codec
+fn parse_request(codec: Codec, payload: &[u8]) -> Result<Request> {
+    match codec {
+        Codec::Text => codec.decode(payload).or_else(|_| {
+            codec.decode(payload).map(|command| Request {
+                namespace: String::new(),
+                command,
+            })
+        }),
+        Codec::Binary => codec.decode(payload),
+    }
+}
 
-    Ok(command)
+// processes a request of the user in the given store engine and returns the response,
+// or fails if the ACL does not grant the request
+fn process_request(
+    logger: &Logger,
+    engine: impl KvsEngine,
+    request: Request,
+    user: Option<&str>,
+    acl: Option<&Acl>,
+) -> Result<Response> {
+    authorize(logger, acl, user, &request.namespace, &request.command)?;
+    let engine = engine.open_namespace(&request.namespace)?;
+    process_command(engine, request.command)
 }
 
-// processes a command in the given store engine and returns the response string
-fn process_command(engine: impl KvsEngine, command: Command) -> Result<String> {
+// processes a command in the given store engine and returns the response
+fn process_command(engine: impl KvsEngine, command: Command) -> Result<Response> {
     Ok(match command {
         Command::Set { key, value } => {
             engine.set(key, value)?;
-            crate::ser::to_string(&Response::SuccessSet())?
-        }
-        Command::Get { key } => {
-            let value = engine.get(key)?;
-            crate::ser::to_string(&Response::SuccessGet(value))?
+            Response::SuccessSet()
         }
+        Command::Get { key } => Response::SuccessGet(engine.get(key)?),
         Command::Rm { key } => match engine.remove(key) {
-            Ok(()) => crate::ser::to_string(&Response::SuccessRm())?,
-            Err(Error::KeyNotFound) => {
-                crate::ser::to_string(&Response::Fail(String::from("Key not found")))?
-            }
+            Ok(()) => Response::SuccessRm(),
+            Err(Error::KeyNotFound) => Response::Fail(String::from("Key not found")),
             Err(e) => return Err(e),
         },
+        Command::Watch { .. } => unreachable!("watches are streamed by `watch`"),
+        Command::Auth { .. } => unreachable!("authentications are checked by `serve`"),
     })
 }
 
-// responds to the stream with the given response string
-fn respond(logger: &Logger, stream: &mut TcpStream, response: String) -> Result<()> {
-    stream.write_all(response.as_bytes())?;
+// checks the ACL grants the command in the namespace to the user, and logs the denial if not
+fn authorize(
+    logger: &Logger,
+    acl: Option<&Acl>,
+    user: Option<&str>,
+    namespace: &str,
+    command: &Command,
+) -> Result<()> {
+    let acl = match acl {
+        Some(acl) => acl,
+        None => return Ok(()),
+    };
+    let (permission, key, prefix) = match command {
+        Command::Set { key, .. } => (Permission::Write, key, false),
+        Command::Get { key } => (Permission::Read, key, false),
+        Command::Rm { key } => (Permission::Delete, key, false),
+        Command::Watch { key, prefix } => (Permission::Read, key, *prefix),
+        Command::Auth { .. } => return Ok(()),
+    };
+    if acl.allows(user, permission, namespace, key, prefix) {
+        return Ok(());
+    }
+
+    let keys = match (prefix, namespace) {
+        (false, "") => format!("key {:?}", key),
+        (true, "") => format!("keys starting with {:?}", key),
+        (false, ns) => format!("key {:?} in namespace {:?}", key, ns),
+        (true, ns) => format!("keys starting with {:?} in namespace {:?}", key, ns),
+    };
+    warn!(
+        logger,
+        "Permission denied: {} on {} to {}",
+        permission,
+        keys,
+        user.unwrap_or("unauthenticated connection")
+    );
+    Err(Error::PermissionDenied(format!(
+        "{} on {} is not granted",
+        permission, keys
+    )))
+}
+
+// translates the failure of the command into the response
+fn response_of(e: Error) -> Response {
+    match e {
+        Error::PermissionDenied(msg) => Response::PermissionDenied(msg),
+        e => Response::Fail(e.to_string()),
+    }
+}
+
+// streams the events of the watched keys as frames until the client disconnects,
+// which is checked whenever no events arrive for a while
+//
+// Clients send nothing after watches, so any bytes from the client end the watch like closing does.
+fn watch(
+    logger: &Logger,
+    engine: impl KvsEngine,
+    namespace: &str,
+    key: String,
+    prefix: bool,
+    codec: Codec,
+    mut stream: Stream,
+) -> Result<()> {
+    let mut watcher = match engine
+        .open_namespace(namespace)
+        .and_then(|engine| engine.watch(&key))
+    {
+        Ok(watcher) => watcher,
+        Err(e) => return write_frame(&mut stream, codec, &Response::Fail(e.to_string())),
+    };
+    write_frame(&mut stream, codec, &Response::SuccessWatch())?;
+    info!(logger, "Watching: {:?}, prefix: {}", key, prefix);
+
+    loop {
+        match watcher.next_timeout(WATCH_POLL_INTERVAL) {
+            Ok(event) if prefix || event.key() == key => {
+                write_frame(&mut stream, codec, &Response::Event(event))?;
+            }
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                if stream.poll_read()? {
+                    info!(logger, "Watch closed by the client");
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
     stream.shutdown(Shutdown::Write)?;
+    Ok(())
+}
 
-    info!(logger, "Response: {:?}", response);
+// writes the response in the codec with its length in the same format as requests
+fn write_frame(stream: &mut impl Write, codec: Codec, response: &Response) -> Result<()> {
+    let response = codec.encode(response)?;
+    let mut frame = format!("{}#", response.len()).into_bytes();
+    frame.extend_from_slice(&response);
+    stream.write_all(&frame)?;
     Ok(())
 }
@@ -2,29 +2,49 @@
 //!
 //! `kvs` is a multi-threaded, persistent key/value store server and client with synchronous networking over a custom protocol.
 
+mod codec;
 mod de;
 mod error;
+mod http_server;
 mod kvs_client;
 mod kvs_server;
+mod resp_server;
 mod ser;
+mod stream;
 
+pub mod acl;
+pub mod auth;
+pub mod dump;
 pub mod kvs_engine;
+pub mod migrate;
 pub mod thread_pool;
+pub mod tls;
 
+pub use codec::Codec;
 pub use error::{Error, Result};
-pub use kvs_client::KvsClient;
-pub use kvs_engine::{KvStore, KvsEngine, SledKvsEngine};
+pub use http_server::HttpServer;
+pub use kvs_client::{EventStream, KvsClient, Pipeline};
+#[cfg(feature = "async")]
+pub use kvs_engine::AsyncKvsEngine;
+pub use kvs_engine::{
+    KvStore, KvsEngine, LsmKvsEngine, MemKvsEngine, ShardedKvStore, SledKvsEngine,
+};
 pub use kvs_server::KvsServer;
+pub use resp_server::RespServer;
 pub use thread_pool::ThreadPool;
 
 use clap::Parser;
+use kvs_engine::Event;
 use serde::{Deserialize, Serialize};
 
-/// A type that represents either set ([`Set`]), get ([`Get`]) or rm ([`Rm`]).
+/// A type that represents either set ([`Set`]), get ([`Get`]), rm ([`Rm`]), watch ([`Watch`])
+/// or the authentication of the connection ([`Auth`]).
 ///
 /// [`Set`]: Command::Set
 /// [`Get`]: Command::Get
 /// [`Rm`]: Command::Rm
+/// [`Watch`]: Command::Watch
+/// [`Auth`]: Command::Auth
 #[derive(Parser, Clone, Serialize, Deserialize, Debug)]
 pub enum Command {
     /// Contains the key and value
@@ -33,20 +53,97 @@ pub enum Command {
     Get { key: String },
     /// Contains the key
     Rm { key: String },
+    /// Contains the key and whether the keys starting with it are watched too
+    Watch { key: String, prefix: bool },
+    /// Contains the user and the password, or no user and a token
+    Auth {
+        user: Option<String>,
+        secret: String,
+    },
 }
 
-/// A type that represents the possible response, which may be either success ([`SuccessSet`], [`SuccessGet`], [`SuccessRm`]) or failure ([`Fail`])
+// A type that represents a command to run in the given namespace.
+//
+// Commands in the default namespace are sent without it, which keeps them readable by older servers.
+// Unknown fields are denied so that bare commands fail fast instead of being skipped.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Request {
+    namespace: String,
+    command: Command,
+}
+
+/// The version of the protocol between clients and servers.
+///
+/// The version 1 is the protocol without handshakes, whose clients send one command in the text codec
+/// on each connection, and read the response without its length until the server closes the connection.
+/// The version 2 starts connections with handshakes, and serves requests on them until clients close them.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The oldest version of the protocol that is still served.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The optional features of the protocol supported by this version.
+pub const FEATURES: &[&str] = &["namespaces", "watch", "pipeline", "binary", "auth"];
+
+/// A type that represents the handshake at the start of connections.
+///
+/// Clients send the newest version and the features they support, and servers reply with the version
+/// and the features both sides support. Its fields never change, so that any versions can parse it.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Handshake {
+    pub version: u32,
+    pub features: Vec<String>,
+}
+
+impl Handshake {
+    // builds the handshake of this version
+    pub(crate) fn new() -> Self {
+        Handshake {
+            version: PROTOCOL_VERSION,
+            features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+        }
+    }
+
+    /// Checks whether the given feature is supported by both sides.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// A type that represents the possible response, which may be either success ([`SuccessSet`], [`SuccessGet`], [`SuccessRm`], [`SuccessWatch`],
+/// [`SuccessAuth`]), failure ([`Fail`], [`Unauthenticated`], [`PermissionDenied`]), a change event of watched keys ([`Event`])
+/// or the reply to handshakes ([`Handshake`])
 ///
 /// [`SuccessSet`]: Response::SuccessSet
 /// [`SuccessGet`]: Response::SuccessGet
 /// [`SuccessRm`]: Response::SuccessRm
+/// [`SuccessWatch`]: Response::SuccessWatch
+/// [`SuccessAuth`]: Response::SuccessAuth
 /// [`Fail`]: Response::Fail
+/// [`Unauthenticated`]: Response::Unauthenticated
+/// [`PermissionDenied`]: Response::PermissionDenied
+/// [`Event`]: Response::Event
+/// [`Handshake`]: Response::Handshake
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum Response {
     SuccessSet(),
     /// Contains the success value for get-command, which is None if the key is not found
     SuccessGet(Option<String>),
     SuccessRm(),
+    /// Tells that the watch is started and followed by events
+    SuccessWatch(),
     /// Contains the error info
     Fail(String),
+    /// Contains a change of watched keys
+    Event(Event),
+    /// Contains the version and the features agreed by the server
+    Handshake(Handshake),
+    /// Tells that the connection is authenticated as the contained user
+    SuccessAuth(String),
+    /// Tells that the credentials are wrong, or the command is sent before authenticating
+    Unauthenticated(String),
+    /// Tells that the command is not granted to the user by the ACL of the server
+    PermissionDenied(String),
 }
@@ -0,0 +1,62 @@
+//! A module for migrating all live data of one store engine into another.
+
+use crate::{Error, KvsEngine, Result};
+
+use std::thread;
+
+// the number of pairs buffered between the reading and the writing engine
+const BUFFER_SIZE: usize = 1024;
+
+/// Copies all namespaces of `source` into the empty `target` and verifies the counts of keys,
+/// returns the number of copied namespaces and keys.
+pub fn migrate(source: &impl KvsEngine, target: &impl KvsEngine) -> Result<(usize, usize)> {
+    let names = source.namespaces()?;
+    let mut keys = 0;
+    for name in &names {
+        let target = target.open_namespace(name)?;
+        // stops at the first live key, if any
+        target.for_each(|_, _| Err(Error::StoreNotEmpty))?;
+
+        let (read, loaded) = copy(source.open_namespace(name)?, &target)?;
+        let mut found = 0;
+        target.for_each(|_, _| {
+            found += 1;
+            Ok(())
+        })?;
+
+        if read != loaded || read != found {
+            return Err(Error::MigrationError(format!(
+                "Namespace {:?} has {} keys but {} are loaded and {} are found",
+                name, read, loaded, found
+            )));
+        }
+        keys += read;
+    }
+    Ok((names.len(), keys))
+}
+
+// streams the pairs of `source` into `target` through a bounded channel,
+// returns the numbers of read and loaded pairs
+fn copy(source: impl KvsEngine, target: &impl KvsEngine) -> Result<(usize, usize)> {
+    let (sender, receiver) = crossbeam_channel::bounded(BUFFER_SIZE);
+    thread::scope(|scope| {
+        let reader = scope.spawn(move || {
+            let mut read = 0;
+            let result = source.for_each(|key, value| {
+                read += 1;
+                sender
+                    .send(Ok((key, value)))
+                    .map_err(|_| Error::MigrationError(String::from("Loading stopped")))
+            });
+            if let Err(e) = result {
+                // the error is dropped if loading has stopped with its own error
+                let _ = sender.send(Err(e));
+            }
+            read
+        });
+
+        let loaded = target.load(receiver);
+        let read = reader.join().unwrap();
+        Ok((read, loaded?))
+    })
+}
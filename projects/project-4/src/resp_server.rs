@@ -0,0 +1,469 @@
+use crate::{
+    kvs_engine::KvsEngine,
+    kvs_server::Limit,
+    thread_pool::{self, *},
+    Error, Result,
+};
+
+use slog::{info, warn, Logger};
+use std::{
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+// the default limit of the length of the bulk strings in one command
+const MAX_REQUEST_SIZE: usize = 16 * 1024 * 1024;
+// the limit of the arguments of one command, which is the one of Redis
+const MAX_ARGS: usize = 1024 * 1024;
+// the default limit of the connections served at the same time
+const MAX_CONNECTIONS: usize = 1024;
+// the default time a connection may stay silent before it is closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A type that abstracts the server speaking the Redis protocol (RESP2 and RESP3),
+/// which maps GET, SET, DEL, EXISTS, INCR, KEYS, PING and HELLO onto the keys of the default namespace.
+///
+/// INCRs are only atomic against the other commands of this server,
+/// so SETs of the same key through the kvs protocol or the HTTP gateway may be lost in between.
+pub struct RespServer<E: KvsEngine, T: ThreadPool> {
+    logger: Logger,
+    listener: TcpListener,
+    engine: E,
+    thread_pool: Arc<T>,
+    max_request_size: usize,
+    max_connections: usize,
+    idle_timeout: Option<Duration>,
+    // serializes the INCRs of this server, whose reads and writes are not atomic in the engines
+    incr_lock: Arc<Mutex<()>>,
+}
+
+impl<E: KvsEngine, T: ThreadPool + Send + Sync + 'static> RespServer<E, T> {
+    /// Creates a server with a logger, a listening address, a store engine and a thread pool.
+    pub fn new(logger: Logger, addr: SocketAddr, engine: E, thread_pool: T) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(RespServer {
+            logger,
+            listener,
+            engine,
+            thread_pool: Arc::new(thread_pool),
+            max_request_size: MAX_REQUEST_SIZE,
+            max_connections: MAX_CONNECTIONS,
+            idle_timeout: Some(IDLE_TIMEOUT),
+            incr_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Sets the limit of the total length of the bulk strings in one command in bytes,
+    /// which is 16 MiB by default, and larger commands close the connection after a protocol error.
+    pub fn set_max_request_size(&mut self, size: usize) {
+        self.max_request_size = size;
+    }
+
+    /// Sets the limit of the connections served at the same time, which is 1024 by default,
+    /// further connections are closed as soon as they are accepted.
+    pub fn set_max_connections(&mut self, connections: usize) {
+        self.max_connections = connections;
+    }
+
+    /// Sets the time a connection may stay silent between commands before it is closed,
+    /// which is 5 minutes by default, or never if `None`.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Starts receiving commands and replying them.
+    ///
+    /// Each connection is read by its own thread until the client closes it, while its commands
+    /// are processed one by one in the pool, so that idle connections never hold threads of the pool.
+    /// Quits after accepting `N` connections with `Some(N)` as `tasks`, or keeps running if `tasks` is `None`.
+    pub fn run(&mut self, tasks: Option<usize>) -> Result<()> {
+        let connections = Limit::new(self.max_connections);
+        let mut tasks_cnt = 0;
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            info!(
+                self.logger,
+                "Accept RESP connection from: {:?}",
+                stream.peer_addr()?
+            );
+
+            match connections.acquire() {
+                Some(slot) => {
+                    stream.set_read_timeout(self.idle_timeout)?;
+                    let context = Context {
+                        logger: self.logger.clone(),
+                        engine: self.engine.clone(),
+                        thread_pool: Arc::clone(&self.thread_pool),
+                        max_request_size: self.max_request_size,
+                        incr_lock: Arc::clone(&self.incr_lock),
+                    };
+                    thread::spawn(move || {
+                        let _slot = slot;
+                        if let Err(e) = serve(&context, stream) {
+                            info!(context.logger, "RESP connection closed: {}", e);
+                        }
+                    });
+                }
+                None => warn!(
+                    self.logger,
+                    "Rejected RESP connection: too many connections"
+                ),
+            }
+
+            tasks_cnt += 1;
+            if let Some(tasks) = tasks {
+                if tasks_cnt >= tasks {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Gets the local address of the server
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+}
+
+// A type that represents the replies, where maps are sent as flat arrays in RESP2
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+    Map(Vec<(Reply, Reply)>),
+}
+
+impl Reply {
+    fn bulk(value: impl Into<String>) -> Self {
+        Reply::Bulk(Some(value.into()))
+    }
+
+    fn write(&self, writer: &mut impl Write, protocol: u8) -> Result<()> {
+        match self {
+            Reply::Simple(s) => write!(writer, "+{}\r\n", s)?,
+            Reply::Error(e) => write!(writer, "-{}\r\n", e)?,
+            Reply::Integer(n) => write!(writer, ":{}\r\n", n)?,
+            Reply::Bulk(Some(s)) => write!(writer, "${}\r\n{}\r\n", s.len(), s)?,
+            Reply::Bulk(None) if protocol == 3 => write!(writer, "_\r\n")?,
+            Reply::Bulk(None) => write!(writer, "$-1\r\n")?,
+            Reply::Array(replies) => {
+                write!(writer, "*{}\r\n", replies.len())?;
+                for reply in replies {
+                    reply.write(writer, protocol)?;
+                }
+            }
+            Reply::Map(entries) => {
+                if protocol == 3 {
+                    write!(writer, "%{}\r\n", entries.len())?;
+                } else {
+                    write!(writer, "*{}\r\n", entries.len() * 2)?;
+                }
+                for (key, value) in entries {
+                    key.write(writer, protocol)?;
+                    value.write(writer, protocol)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// A type that represents the state shared by the server and the threads of its connections.
+struct Context<E, T> {
+    logger: Logger,
+    engine: E,
+    thread_pool: Arc<T>,
+    max_request_size: usize,
+    incr_lock: Arc<Mutex<()>>,
+}
+
+// serves the commands on the connection in order until the client closes it,
+// and closes the connection after a protocol error like Redis does
+//
+// Commands are processed in the pool, and this thread only waits for them between reads and writes.
+fn serve<E: KvsEngine, T: ThreadPool>(context: &Context<E, T>, stream: TcpStream) -> Result<()> {
+    let logger = &context.logger;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    // connections start in RESP2 until HELLO 3
+    let mut protocol = 2;
+    loop {
+        let args = match read_command(&mut reader, context.max_request_size) {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(Error::RespError(msg)) => {
+                let reply = Reply::Error(format!("ERR Protocol error: {}", msg));
+                reply.write(&mut writer, protocol)?;
+                writer.flush()?;
+                return Err(Error::RespError(msg));
+            }
+            Err(e) => return Err(e),
+        };
+        if args.is_empty() {
+            continue;
+        }
+        info!(logger, "Received RESP command: {:?}", args);
+
+        let engine = context.engine.clone();
+        let incr_lock = Arc::clone(&context.incr_lock);
+        let (reply, new_protocol) = thread_pool::execute(&*context.thread_pool, move || {
+            let mut protocol = protocol;
+            let reply = process_command(&engine, &incr_lock, &mut protocol, args)
+                .unwrap_or_else(|e| Reply::Error(format!("ERR {}", e)));
+            (reply, protocol)
+        })?;
+        protocol = new_protocol;
+        reply.write(&mut writer, protocol)?;
+        // replies of pipelined commands are flushed together
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+}
+
+// reads one command, which is either an array of bulk strings or an inline command,
+// whose bulk strings are at most `max_size` bytes in total, returns None if the connection is closed
+fn read_command(reader: &mut impl BufRead, max_size: usize) -> Result<Option<Vec<String>>> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let count = match line.strip_prefix('*') {
+        Some(count) => parse_len(count)?,
+        None => return Ok(Some(line.split_whitespace().map(String::from).collect())),
+    };
+    if count > MAX_ARGS {
+        return Err(protocol_error("invalid multibulk length"));
+    }
+
+    let mut size: usize = 0;
+    let mut args = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let line = read_line(reader)?.ok_or_else(|| protocol_error("unexpected EOF"))?;
+        let len = line
+            .strip_prefix('$')
+            .ok_or_else(|| protocol_error("expected '$'"))
+            .and_then(parse_len)?;
+        size = size.saturating_add(len);
+        if size > max_size {
+            return Err(protocol_error("invalid bulk length"));
+        }
+
+        let mut buffer = vec![0; len + 2];
+        reader.read_exact(&mut buffer)?;
+        if !buffer.ends_with(b"\r\n") {
+            return Err(protocol_error("expected CRLF"));
+        }
+        buffer.truncate(len);
+        args.push(String::from_utf8(buffer).map_err(|_| protocol_error("expected utf-8"))?);
+    }
+    Ok(Some(args))
+}
+
+// reads one line without the CRLF, returns None if the connection is closed
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader
+        .by_ref()
+        .take(64 * 1024)
+        .read_until(b'\n', &mut line)?
+        == 0
+    {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(protocol_error("too long line"));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| protocol_error("expected utf-8"))
+}
+
+fn parse_len(len: &str) -> Result<usize> {
+    len.parse().map_err(|_| protocol_error("invalid length"))
+}
+
+fn protocol_error(msg: &str) -> Error {
+    Error::RespError(String::from(msg))
+}
+
+// processes a command in the given store engine and returns the reply
+fn process_command(
+    engine: &impl KvsEngine,
+    incr_lock: &Mutex<()>,
+    protocol: &mut u8,
+    args: Vec<String>,
+) -> Result<Reply> {
+    let name = args[0].to_ascii_uppercase();
+    let mut args = args.into_iter().skip(1);
+    let arity = args.len();
+    let wrong_arity = || {
+        Reply::Error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name.to_ascii_lowercase()
+        ))
+    };
+
+    Ok(match (name.as_str(), arity) {
+        ("PING", 0) => Reply::Simple("PONG"),
+        ("PING", 1) => Reply::bulk(args.next().unwrap()),
+        ("HELLO", _) => match args.next().map(|version| version.parse::<u8>()) {
+            None => hello(*protocol),
+            Some(Ok(version @ (2 | 3))) => {
+                *protocol = version;
+                hello(version)
+            }
+            Some(_) => Reply::Error(String::from("NOPROTO unsupported protocol version")),
+        },
+        ("GET", 1) => Reply::Bulk(engine.get(args.next().unwrap())?),
+        ("SET", 2) => {
+            engine.set(args.next().unwrap(), args.next().unwrap())?;
+            Reply::Simple("OK")
+        }
+        ("DEL", 1..) => {
+            let mut removed = 0;
+            for key in args {
+                match engine.remove(key) {
+                    Ok(()) => removed += 1,
+                    Err(Error::KeyNotFound) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            Reply::Integer(removed)
+        }
+        ("EXISTS", 1..) => {
+            let mut exists = 0;
+            for key in args {
+                if engine.get(key)?.is_some() {
+                    exists += 1;
+                }
+            }
+            Reply::Integer(exists)
+        }
+        ("INCR", 1) => {
+            let key = args.next().unwrap();
+            let _guard = incr_lock.lock().unwrap();
+            let value = match engine.get(key.clone())? {
+                Some(value) => value.parse::<i64>().ok(),
+                None => Some(0),
+            };
+            match value.and_then(|value| value.checked_add(1)) {
+                Some(value) => {
+                    engine.set(key, value.to_string())?;
+                    Reply::Integer(value)
+                }
+                None => Reply::Error(String::from("ERR value is not an integer or out of range")),
+            }
+        }
+        ("KEYS", 1) => {
+            let pattern: Vec<char> = args.next().unwrap().chars().collect();
+            let mut keys = Vec::new();
+            engine.for_each(|key, _| {
+                if matches(&pattern, &key.chars().collect::<Vec<_>>()) {
+                    keys.push(Reply::bulk(key));
+                }
+                Ok(())
+            })?;
+            Reply::Array(keys)
+        }
+        ("PING" | "GET" | "SET" | "DEL" | "EXISTS" | "INCR" | "KEYS", _) => wrong_arity(),
+        _ => Reply::Error(format!(
+            "ERR unknown command '{}'",
+            name.to_ascii_lowercase()
+        )),
+    })
+}
+
+// replies HELLO with the properties of the server in the given protocol
+fn hello(protocol: u8) -> Reply {
+    Reply::Map(vec![
+        (Reply::bulk("server"), Reply::bulk("kvs")),
+        (
+            Reply::bulk("version"),
+            Reply::bulk(env!("CARGO_PKG_VERSION")),
+        ),
+        (Reply::bulk("proto"), Reply::Integer(i64::from(protocol))),
+        (Reply::bulk("mode"), Reply::bulk("standalone")),
+        (Reply::bulk("role"), Reply::bulk("master")),
+        (Reply::bulk("modules"), Reply::Array(Vec::new())),
+    ])
+}
+
+// matches the key with the glob-style pattern of KEYS,
+// which supports `*`, `?`, `[...]` with ranges and `^`, and `\` to escape
+//
+// Only the last star is backtracked to, since a later star can match anything an earlier one would,
+// so that matching takes at most the length of the pattern times the length of the key.
+fn matches(pattern: &[char], key: &[char]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    // the pattern after the last star, and the position in the key it is matched from
+    let mut star = None;
+    while k < key.len() {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            star = Some((p, k));
+        } else if let Some(next) = match_char(pattern, p, key[k]) {
+            p = next;
+            k += 1;
+        } else if let Some((after_star, from)) = star {
+            // the last star takes one more char of the key
+            p = after_star;
+            k = from + 1;
+            star = Some((after_star, k));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// matches the char with the part of the pattern at the given position other than a star,
+// returns the position of the next part if it matches
+fn match_char(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match *pattern.get(p)? {
+        '?' => Some(p + 1),
+        '[' => {
+            let end = match pattern.iter().skip(p + 2).position(|&c| c == ']') {
+                Some(end) => end + p + 2,
+                None => return (c == '[').then_some(p + 1),
+            };
+            let (negated, class) = match pattern[p + 1] {
+                '^' => (true, &pattern[p + 2..end]),
+                _ => (false, &pattern[p + 1..end]),
+            };
+            (in_class(class, c) != negated).then_some(end + 1)
+        }
+        '\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        literal => (literal == c).then_some(p + 1),
+    }
+}
+
+// checks whether the char is in the class of a pattern, such as `abc` or `a-z`
+fn in_class(class: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            let (low, high) = (class[i].min(class[i + 2]), class[i].max(class[i + 2]));
+            if (low..=high).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
@@ -0,0 +1,184 @@
+use rustls::{ClientConfig, ClientConnection, Connection, ServerConfig, ServerConnection};
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+// the length of the raw bytes read from the socket at once, which keeps the decrypted bytes
+// within the buffer of rustls
+const TLS_READ_SIZE: usize = 8 * 1024;
+
+// A type that represents the connections between clients and servers, which are either plain
+// or encrypted by TLS, and can be cloned to read and write in different threads like `TcpStream`.
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    Tls(TlsStream),
+}
+
+impl Stream {
+    // accepts the TLS handshake of the client on the connection
+    pub(crate) fn accept(socket: TcpStream, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let conn = ServerConnection::new(config).map_err(io::Error::other)?;
+        TlsStream::handshake(socket, conn.into()).map(Stream::Tls)
+    }
+
+    // starts the TLS handshake with the server on the connection, whose certificate must be valid
+    // for the given name
+    pub(crate) fn connect(
+        socket: TcpStream,
+        server_name: &str,
+        config: Arc<ClientConfig>,
+    ) -> io::Result<Self> {
+        let server_name = server_name
+            .to_owned()
+            .try_into()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let conn = ClientConnection::new(config, server_name).map_err(io::Error::other)?;
+        TlsStream::handshake(socket, conn.into()).map(Stream::Tls)
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Stream::Tcp(socket) => Stream::Tcp(socket.try_clone()?),
+            Stream::Tls(stream) => Stream::Tls(stream.try_clone()?),
+        })
+    }
+
+    // checks without blocking whether the peer has sent anything or closed the connection,
+    // which only looks at the socket, so that the bytes of TLS records count too
+    pub(crate) fn poll_read(&self) -> io::Result<bool> {
+        let socket = match self {
+            Stream::Tcp(socket) => socket,
+            Stream::Tls(stream) => &stream.socket,
+        };
+        socket.set_nonblocking(true)?;
+        let result = socket.peek(&mut [0]);
+        socket.set_nonblocking(false)?;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // closes the connection, and tells the peer it is closed on purpose before closing writes in TLS
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(socket) => socket.shutdown(how),
+            Stream::Tls(stream) => {
+                if how != Shutdown::Read {
+                    let mut conn = stream.conn.lock().unwrap();
+                    conn.send_close_notify();
+                    stream.flush_tls(conn)?;
+                }
+                stream.socket.shutdown(how)
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(socket) => socket.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(socket) => socket.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(socket) => socket.flush(),
+            Stream::Tls(_) => Ok(()),
+        }
+    }
+}
+
+// A type that represents one TLS connection shared by its clones.
+//
+// The state of TLS is locked only while bytes are encrypted or decrypted, but never while waiting
+// for the socket, so that one clone can block on reading while another clone is writing.
+pub(crate) struct TlsStream {
+    conn: Arc<Mutex<Connection>>,
+    socket: TcpStream,
+    // keeps the encrypted records in the order they are encrypted
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl TlsStream {
+    // completes the handshake before any frames are sent
+    fn handshake(mut socket: TcpStream, mut conn: Connection) -> io::Result<Self> {
+        while conn.is_handshaking() {
+            conn.complete_io(&mut socket)?;
+        }
+        Ok(TlsStream {
+            conn: Arc::new(Mutex::new(conn)),
+            socket,
+            write_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(TlsStream {
+            conn: Arc::clone(&self.conn),
+            socket: self.socket.try_clone()?,
+            write_lock: Arc::clone(&self.write_lock),
+        })
+    }
+
+    // writes the encrypted records waiting to be sent, and takes the lock of writes before
+    // releasing the state so that no records written later are sent before them
+    fn flush_tls(&self, mut conn: MutexGuard<Connection>) -> io::Result<()> {
+        let mut records = Vec::new();
+        while conn.wants_write() {
+            conn.write_tls(&mut records)?;
+        }
+        let _write_lock = self.write_lock.lock().unwrap();
+        drop(conn);
+        (&self.socket).write_all(&records)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = [0; TLS_READ_SIZE];
+        loop {
+            match self.conn.lock().unwrap().reader().read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                // `Ok(0)` means the peer has closed the connection on purpose
+                result => return result,
+            }
+
+            let len = self.socket.read(&mut raw)?;
+            let mut conn = self.conn.lock().unwrap();
+            let mut records = &raw[..len];
+            let result = loop {
+                // reading nothing tells rustls the socket is closed
+                conn.read_tls(&mut records)?;
+                if let Err(e) = conn.process_new_packets() {
+                    break Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                if records.is_empty() {
+                    break Ok(());
+                }
+            };
+            // replies the records such as alerts or key updates
+            self.flush_tls(conn)?;
+            result?;
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let len = conn.writer().write(buf)?;
+        self.flush_tls(conn)?;
+        Ok(len)
+    }
+}
@@ -8,7 +8,7 @@ pub use naive_thread_pool::NaiveThreadPool;
 pub use rayon_thread_pool::RayonThreadPool;
 pub use shared_queue_thread_pool::SharedQueueThreadPool;
 
-use crate::Result;
+use crate::{Error, Result};
 
 /// A simple trait for thread pools,
 /// which only provides methods `new` and `spawn`.
@@ -21,3 +21,19 @@ pub trait ThreadPool: Sized {
     where
         F: FnOnce() + Send + 'static;
 }
+
+// executes the job on one thread in the pool and waits for its result,
+// which fails only if the job panics
+pub(crate) fn execute<R: Send + 'static>(
+    pool: &impl ThreadPool,
+    job: impl FnOnce() -> R + Send + 'static,
+) -> Result<R> {
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    pool.spawn(move || {
+        // the receiver is never dropped before the result is sent
+        let _ = sender.send(job());
+    });
+    receiver
+        .recv()
+        .map_err(|_| Error::ServerError(String::from("The job panicked")))
+}
@@ -39,9 +39,7 @@ impl ThreadPool for SharedQueueThreadPool {
                         }
                     });
 
-                    if exit_now.is_err() {
-                        continue;
-                    } else if exit_now.unwrap() {
+                    if let Ok(true) = exit_now {
                         break;
                     }
                 }
@@ -65,8 +63,7 @@ impl Drop for SharedQueueThreadPool {
             self.channel.0.send(Message::Exit).unwrap();
         }
 
-        while !self.handles.is_empty() {
-            let handle = self.handles.pop().unwrap();
+        while let Some(handle) = self.handles.pop() {
             handle.join().unwrap();
         }
     }
@@ -0,0 +1,106 @@
+//! Builds the TLS configurations of servers and clients from PEM files.
+//!
+//! Servers are given a certificate chain and its private key, and optionally the certificates of
+//! the CAs that sign the certificates of clients, which makes clients without them rejected.
+//! Clients trust either the given CAs or the bundled roots of Mozilla, and optionally present
+//! their own certificates to the servers that require them.
+
+use crate::{Error, Result};
+
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore,
+};
+use std::{path::Path, sync::Arc};
+
+pub use rustls::{ClientConfig, ServerConfig};
+
+/// Builds the configuration of servers with the certificate chain in `cert` and the private key
+/// in `key`, and requires clients to present certificates signed by the CAs in `client_ca` if given.
+pub fn server_config(
+    cert: impl AsRef<Path>,
+    key: impl AsRef<Path>,
+    client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>> {
+    let builder = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?;
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let roots = Arc::new(load_roots(client_ca)?);
+            let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider())
+                .build()
+                .map_err(tls_error)?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(load_certs(cert)?, load_key(key)?)
+        .map_err(tls_error)?;
+    Ok(Arc::new(config))
+}
+
+/// Builds the configuration of clients that trusts the CAs in `ca`, or the roots of Mozilla if `None`,
+/// and presents the certificate chain and the private key in `identity` if given.
+pub fn client_config(
+    ca: Option<&Path>,
+    identity: Option<(&Path, &Path)>,
+) -> Result<Arc<ClientConfig>> {
+    let roots = match ca {
+        Some(ca) => load_roots(ca)?,
+        None => RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        },
+    };
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?
+        .with_root_certificates(roots);
+    let config = match identity {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .map_err(tls_error)?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+// uses ring for the cryptography regardless of the default provider of the process
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn load_certs(path: impl AsRef<Path>) -> Result<Vec<CertificateDer<'static>>> {
+    let path = path.as_ref();
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| Error::TlsError(format!("{}: {}", path.display(), e)))?;
+    if certs.is_empty() {
+        return Err(Error::TlsError(format!(
+            "{}: No certificates found",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: impl AsRef<Path>) -> Result<PrivateKeyDer<'static>> {
+    let path = path.as_ref();
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| Error::TlsError(format!("{}: {}", path.display(), e)))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(tls_error)?;
+    }
+    Ok(roots)
+}
+
+fn tls_error(e: impl std::fmt::Display) -> Error {
+    Error::TlsError(e.to_string())
+}
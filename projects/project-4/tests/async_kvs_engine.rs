@@ -0,0 +1,48 @@
+#![cfg(feature = "async")]
+
+use kvs::{AsyncKvsEngine, KvStore, KvsEngine, Result, SledKvsEngine};
+use tempfile::TempDir;
+
+async fn set_get_and_remove<E: AsyncKvsEngine + KvsEngine>() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = E::open_async(temp_dir.path()).await?;
+
+    let tasks: Vec<_> = (0..100)
+        .map(|i| {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                engine
+                    .set_async(format!("key{}", i), format!("value{}", i))
+                    .await
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap()?;
+    }
+
+    for i in 0..100 {
+        assert_eq!(
+            engine.get_async(format!("key{}", i)).await?,
+            Some(format!("value{}", i))
+        );
+    }
+    engine.remove_async("key0".to_owned()).await?;
+    assert_eq!(engine.get_async("key0".to_owned()).await?, None);
+    assert!(engine.remove_async("key0".to_owned()).await.is_err());
+    // the blocking methods of KvsEngine are not ambiguous with the async ones
+    assert_eq!(engine.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+// Should serve concurrent tasks without blocking the runtime
+#[tokio::test(flavor = "multi_thread")]
+async fn kv_store_set_get_and_remove() -> Result<()> {
+    set_get_and_remove::<KvStore>().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sled_set_get_and_remove() -> Result<()> {
+    set_get_and_remove::<SledKvsEngine>().await
+}
@@ -1,7 +1,10 @@
 use assert_cmd::prelude::*;
+use kvs::{KvStore, KvsEngine, SledKvsEngine};
+use predicates::prelude::*;
 use predicates::str::{contains, is_empty};
+use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
 use std::fs::{self, File};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
@@ -20,28 +23,28 @@ fn client_cli_invalid_get() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get"])
+        .args(["get"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "extra", "field"])
+        .args(["get", "extra", "field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key", "--addr", "invalid-addr"])
+        .args(["get", "key", "--addr", "invalid-addr"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key", "--unknown-flag"])
+        .args(["get", "key", "--unknown-flag"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -52,35 +55,35 @@ fn client_cli_invalid_set() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set"])
+        .args(["set"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "missing_field"])
+        .args(["set", "missing_field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key", "value", "extra_field"])
+        .args(["set", "key", "value", "extra_field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key", "value", "--addr", "invalid-addr"])
+        .args(["set", "key", "value", "--addr", "invalid-addr"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key", "--unknown-flag"])
+        .args(["get", "key", "--unknown-flag"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -91,28 +94,28 @@ fn client_cli_invalid_rm() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm"])
+        .args(["rm"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "extra", "field"])
+        .args(["rm", "extra", "field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "key", "--addr", "invalid-addr"])
+        .args(["rm", "key", "--addr", "invalid-addr"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "key", "--unknown-flag"])
+        .args(["rm", "key", "--unknown-flag"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -123,7 +126,7 @@ fn client_cli_invalid_subcommand() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["unknown"])
+        .args(["unknown"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -134,7 +137,7 @@ fn client_cli_invalid_subcommand() {
 fn client_cli_version() {
     let temp_dir = TempDir::new().unwrap();
     let mut cmd = Command::cargo_bin("kvs-client").unwrap();
-    cmd.args(&["-V"])
+    cmd.args(["-V"])
         .current_dir(&temp_dir)
         .assert()
         .stdout(contains(env!("CARGO_PKG_VERSION")));
@@ -145,7 +148,7 @@ fn client_cli_version() {
 fn server_cli_version() {
     let temp_dir = TempDir::new().unwrap();
     let mut cmd = Command::cargo_bin("kvs-server").unwrap();
-    cmd.args(&["-V"])
+    cmd.args(["-V"])
         .current_dir(&temp_dir)
         .assert()
         .stdout(contains(env!("CARGO_PKG_VERSION")));
@@ -157,13 +160,14 @@ fn cli_log_configuration() {
     let stderr_path = temp_dir.path().join("stderr");
     let mut cmd = Command::cargo_bin("kvs-server").unwrap();
     let mut child = cmd
-        .args(&["--engine", "kvs", "--addr", "127.0.0.1:4001"])
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4001"])
         .current_dir(&temp_dir)
         .stderr(File::create(&stderr_path).unwrap())
         .spawn()
         .unwrap();
     thread::sleep(Duration::from_secs(1));
     child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
 
     let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
     assert!(content.contains(env!("CARGO_PKG_VERSION")));
@@ -178,15 +182,16 @@ fn cli_wrong_engine() {
         let temp_dir = TempDir::new().unwrap();
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
         let mut child = cmd
-            .args(&["--engine", "sled", "--addr", "127.0.0.1:4002"])
+            .args(["--engine", "sled", "--addr", "127.0.0.1:4002"])
             .current_dir(&temp_dir)
             .spawn()
             .unwrap();
         thread::sleep(Duration::from_secs(1));
         child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait on server");
 
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
-        cmd.args(&["--engine", "kvs", "--addr", "127.0.0.1:4003"])
+        cmd.args(["--engine", "kvs", "--addr", "127.0.0.1:4003"])
             .current_dir(&temp_dir)
             .assert()
             .failure();
@@ -197,15 +202,16 @@ fn cli_wrong_engine() {
         let temp_dir = TempDir::new().unwrap();
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
         let mut child = cmd
-            .args(&["--engine", "kvs", "--addr", "127.0.0.1:4002"])
+            .args(["--engine", "kvs", "--addr", "127.0.0.1:4002"])
             .current_dir(&temp_dir)
             .spawn()
             .unwrap();
         thread::sleep(Duration::from_secs(1));
         child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait on server");
 
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
-        cmd.args(&["--engine", "sled", "--addr", "127.0.0.1:4003"])
+        cmd.args(["--engine", "sled", "--addr", "127.0.0.1:4003"])
             .current_dir(&temp_dir)
             .assert()
             .failure();
@@ -217,19 +223,20 @@ fn cli_access_server(engine: &str, addr: &str) {
     let temp_dir = TempDir::new().unwrap();
     let mut server = Command::cargo_bin("kvs-server").unwrap();
     let mut child = server
-        .args(&["--engine", engine, "--addr", addr])
+        .args(["--engine", engine, "--addr", addr])
         .current_dir(&temp_dir)
         .spawn()
         .unwrap();
     let handle = thread::spawn(move || {
         let _ = receiver.recv(); // wait for main thread to finish
         child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait on server");
     });
     thread::sleep(Duration::from_secs(1));
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key1", "value1", "--addr", addr])
+        .args(["set", "key1", "value1", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -237,7 +244,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key1", "--addr", addr])
+        .args(["get", "key1", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -245,7 +252,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key1", "value2", "--addr", addr])
+        .args(["set", "key1", "value2", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -253,7 +260,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key1", "--addr", addr])
+        .args(["get", "key1", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -261,7 +268,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key2", "--addr", addr])
+        .args(["get", "key2", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -269,7 +276,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "key2", "--addr", addr])
+        .args(["rm", "key2", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .failure()
@@ -277,7 +284,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key2", "value3", "--addr", addr])
+        .args(["set", "key2", "value3", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -285,7 +292,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "key1", "--addr", addr])
+        .args(["rm", "key1", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -298,26 +305,27 @@ fn cli_access_server(engine: &str, addr: &str) {
     let (sender, receiver) = mpsc::sync_channel(0);
     let mut server = Command::cargo_bin("kvs-server").unwrap();
     let mut child = server
-        .args(&["--engine", engine, "--addr", addr])
+        .args(["--engine", engine, "--addr", addr])
         .current_dir(&temp_dir)
         .spawn()
         .unwrap();
     let handle = thread::spawn(move || {
         let _ = receiver.recv(); // wait for main thread to finish
         child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait on server");
     });
     thread::sleep(Duration::from_secs(1));
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key2", "--addr", addr])
+        .args(["get", "key2", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
         .stdout(contains("value3"));
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key1", "--addr", addr])
+        .args(["get", "key1", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -335,3 +343,650 @@ fn cli_access_server_kvs_engine() {
 fn cli_access_server_sled_engine() {
     cli_access_server("sled", "127.0.0.1:4005");
 }
+
+#[test]
+fn cli_access_server_lsm_engine() {
+    cli_access_server("lsm", "127.0.0.1:4010");
+}
+
+// `kvs-admin dump` and `kvs-admin load` should move data of all namespaces between engines,
+// where dumping does not wait for the writer of the kvs engine
+#[test]
+fn cli_admin_dump_and_load() {
+    let temp_dir = TempDir::new().unwrap();
+    let store = KvStore::open(temp_dir.path().join("db.kvs")).unwrap();
+    store.set("key1".to_owned(), "value1".to_owned()).unwrap();
+    store.set("key2".to_owned(), "value2".to_owned()).unwrap();
+    let users = store.open_namespace("users").unwrap();
+    users.set("key3".to_owned(), "value3".to_owned()).unwrap();
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["dump", "db.kvs", "--format", "binary", "-o", "dump.bin"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stderr(contains("dumped 3 keys"));
+    drop((store, users));
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["load", "db.sled", "-i", "dump.bin"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stderr(contains("loaded 3 keys"));
+
+    // loading twice should fail since the store is not empty
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["load", "db.sled", "-i", "dump.bin"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["dump", "db.sled"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("{\"key\":\"key1\",\"value\":\"value1\"}"))
+        .stdout(contains(
+            "{\"ns\":\"users\",\"key\":\"key3\",\"value\":\"value3\"}",
+        ));
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["dump", "db.sled", "--ns", "users"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stderr(contains("dumped 1 keys"));
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["dump", "missing"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+// `kvs-admin migrate` should move all namespaces to the other engine and keep the old store
+#[test]
+fn cli_admin_migrate() {
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let store = KvStore::open(temp_dir.path().join("db.kvs")).unwrap();
+        store.set("key1".to_owned(), "value1".to_owned()).unwrap();
+        let users = store.open_namespace("users").unwrap();
+        users.set("key1".to_owned(), "value2".to_owned()).unwrap();
+        users.set("key2".to_owned(), "value3".to_owned()).unwrap();
+    }
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["migrate", "db.kvs", "--to", "kvs"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["migrate", "db.kvs", "--to", "sled"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stderr(contains("migrated 3 keys in 2 namespaces"));
+    assert!(!temp_dir.path().join("db.kvs").exists());
+    assert!(temp_dir.path().join("db.kvs.bak").exists());
+
+    {
+        let engine = SledKvsEngine::open(temp_dir.path().join("db.sled")).unwrap();
+        let users = engine.open_namespace("users").unwrap();
+        assert_eq!(
+            engine.get("key1".to_owned()).unwrap(),
+            Some("value1".to_owned())
+        );
+        assert_eq!(
+            users.get("key2".to_owned()).unwrap(),
+            Some("value3".to_owned())
+        );
+    }
+
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["migrate", "db.sled", "--to", "kvs"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stderr(contains("migrated 3 keys in 2 namespaces"));
+
+    let store = KvStore::open(temp_dir.path().join("db.kvs")).unwrap();
+    let users = store.open_namespace("users").unwrap();
+    assert_eq!(
+        users.get("key1".to_owned()).unwrap(),
+        Some("value2".to_owned())
+    );
+    assert_eq!(store.get("key2".to_owned()).unwrap(), None);
+}
+
+// `kvs-server --engine memory` should serve the seeded keys without creating a store directory
+#[test]
+fn cli_memory_engine() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("dump.json"),
+        "{\"key\":\"key1\",\"value\":\"value1\"}\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--seed", "dump.json"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args([
+            "--engine",
+            "memory",
+            "--seed",
+            "dump.json",
+            "--addr",
+            "127.0.0.1:4011",
+        ])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", "127.0.0.1:4011"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+    assert!(!temp_dir.path().join("db.memory").exists());
+    assert!(!temp_dir.path().join("db.kvs").exists());
+}
+
+// `kvs-check` should report the records and fail on a corrupt directory
+#[test]
+fn cli_check() {
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let store = KvStore::open(temp_dir.path().join("db.kvs")).unwrap();
+        store.set("key1".to_owned(), "value1".to_owned()).unwrap();
+        store.set("key1".to_owned(), "value2".to_owned()).unwrap();
+    }
+
+    Command::cargo_bin("kvs-check")
+        .unwrap()
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("live records: 1").and(contains("overwritten records: 1")));
+
+    // appends a corrupt record to the active file
+    let data_file = temp_dir.path().join("db.kvs").join("kvs.data.0");
+    let mut content = fs::read(&data_file).unwrap();
+    let offset = content.len();
+    content.extend_from_slice(b"garbage#");
+    fs::write(&data_file, content).unwrap();
+    Command::cargo_bin("kvs-check")
+        .unwrap()
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stdout(contains(format!("kvs.data.0 at offset {}", offset)));
+
+    Command::cargo_bin("kvs-check")
+        .unwrap()
+        .args(["db.kvs", "--repair", "db.kvs.new"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("salvaged 1 live records"));
+
+    Command::cargo_bin("kvs-check")
+        .unwrap()
+        .args(["db.kvs.new"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("corrupt records: 0"));
+}
+
+fn cli_namespaces(engine: &str, addr: &str) {
+    let temp_dir = TempDir::new().unwrap();
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", engine, "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value2", "--addr", addr, "--ns", "users"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", addr, "--ns", "users"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value2\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rm", "key1", "--addr", addr, "--ns", "orders"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Key not found"));
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+}
+
+#[test]
+fn cli_namespaces_kvs_engine() {
+    cli_namespaces("kvs", "127.0.0.1:4006");
+}
+
+#[test]
+fn cli_namespaces_sled_engine() {
+    cli_namespaces("sled", "127.0.0.1:4007");
+}
+
+fn cli_watch(engine: &str, addr: &str) {
+    let temp_dir = TempDir::new().unwrap();
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", engine, "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let mut watcher = Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args([
+            "watch", "user.", "--prefix", "--addr", addr, "--ns", "users",
+        ])
+        .current_dir(&temp_dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    for args in [
+        vec!["set", "user.1", "value1", "--addr", addr, "--ns", "users"],
+        vec!["set", "user.1", "value2", "--addr", addr],
+        vec!["set", "order.1", "value3", "--addr", addr, "--ns", "users"],
+        vec!["rm", "user.1", "--addr", addr, "--ns", "users"],
+    ] {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(args)
+            .current_dir(&temp_dir)
+            .assert()
+            .success();
+    }
+    thread::sleep(Duration::from_secs(1));
+
+    watcher.kill().expect("watcher exited before killed");
+    let output = watcher.wait_with_output().unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "set user.1 value1\nrm user.1\n"
+    );
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+}
+
+#[test]
+fn cli_watch_kvs_engine() {
+    cli_watch("kvs", "127.0.0.1:4008");
+}
+
+#[test]
+fn cli_watch_sled_engine() {
+    cli_watch("sled", "127.0.0.1:4009");
+}
+
+// `kvs-server --resp-addr` should serve Redis clients on the same engine
+#[test]
+fn cli_resp_addr() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args([
+            "--engine",
+            "memory",
+            "--addr",
+            "127.0.0.1:4019",
+            "--resp-addr",
+            "127.0.0.1:4020",
+        ])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4019"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    let client = redis::Client::open("redis://127.0.0.1:4020/").unwrap();
+    let mut con = client.get_connection().unwrap();
+    let value: Option<String> = redis::cmd("GET").arg("key1").query(&mut con).unwrap();
+    assert_eq!(value.as_deref(), Some("value1"));
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+}
+
+// `kvs-server --http-addr` should serve the HTTP/JSON gateway on the same engine
+#[test]
+fn cli_http_addr() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args([
+            "--engine",
+            "memory",
+            "--addr",
+            "127.0.0.1:4023",
+            "--http-addr",
+            "127.0.0.1:4024",
+        ])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4023"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    let body = ureq::get("http://127.0.0.1:4024/keys/key1")
+        .call()
+        .unwrap()
+        .into_string()
+        .unwrap();
+    assert_eq!(body, "{\"key\":\"key1\",\"value\":\"value1\"}");
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+}
+
+// `kvs-client --ca --cert --key` should talk to `kvs-server --tls-cert --tls-key --tls-client-ca`,
+// while plain clients and clients without certificates are rejected
+#[test]
+fn cli_tls() {
+    let temp_dir = TempDir::new().unwrap();
+    let ca_key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::default();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = params.self_signed(&ca_key).unwrap();
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec!["127.0.0.1".to_owned()]).unwrap();
+    params.extended_key_usages = vec![
+        ExtendedKeyUsagePurpose::ServerAuth,
+        ExtendedKeyUsagePurpose::ClientAuth,
+    ];
+    let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+    fs::write(temp_dir.path().join("ca.pem"), ca.pem()).unwrap();
+    fs::write(temp_dir.path().join("cert.pem"), cert.pem()).unwrap();
+    fs::write(temp_dir.path().join("key.pem"), key.serialize_pem()).unwrap();
+
+    // the RESP and HTTP listeners would serve plaintext next to the encrypted one
+    for listener in ["--resp-addr", "--http-addr"] {
+        Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--tls-cert", "cert.pem", "--tls-key", "key.pem"])
+            .args([listener, "127.0.0.1:4039"])
+            .current_dir(&temp_dir)
+            .assert()
+            .failure()
+            .stderr(contains("cannot be used with"));
+    }
+
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args([
+            "--engine",
+            "memory",
+            "--addr",
+            "127.0.0.1:4027",
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+            "--tls-client-ca",
+            "ca.pem",
+        ])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let tls = ["--ca", "ca.pem", "--cert", "cert.pem", "--key", "key.pem"];
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4027"])
+        .args(tls)
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", "127.0.0.1:4027"])
+        .args(tls)
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", "127.0.0.1:4027", "--ca", "ca.pem"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", "127.0.0.1:4027"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+}
+
+// `kvs-admin add-user` and `kvs-admin add-token` should write the credentials file of `kvs-server --credentials`,
+// which rejects `kvs-client` without the right `--user` and `--password`, or `KVS_TOKEN`
+#[test]
+fn cli_credentials() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["add-user", "alice", "-c", "credentials"])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("secret\n")
+        .assert()
+        .success()
+        .stdout(is_empty());
+    let output = Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["add-token", "bot", "-c", "credentials"])
+        .current_dir(&temp_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let token = String::from_utf8(output.stdout).unwrap();
+    // the password is replaced
+    Command::cargo_bin("kvs-admin")
+        .unwrap()
+        .args(["add-user", "alice", "-c", "credentials"])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("secret2\n")
+        .assert()
+        .success()
+        .stderr(contains("replaced the password of alice"));
+    let content = fs::read_to_string(temp_dir.path().join("credentials")).unwrap();
+    assert!(!content.contains("secret") && !content.contains(token.trim()));
+    assert_eq!(content.matches("alice:").count(), 1);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = fs::metadata(temp_dir.path().join("credentials")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args([
+            "--engine",
+            "memory",
+            "--addr",
+            "127.0.0.1:4029",
+            "--credentials",
+            "credentials",
+        ])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4029"])
+        .env_remove("KVS_TOKEN")
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Authentication required"));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4029"])
+        .args(["--user", "alice", "--password", "secret"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Invalid credentials"));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4029"])
+        .args(["--user", "alice", "--password", "secret2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", "127.0.0.1:4029"])
+        .env("KVS_TOKEN", token.trim())
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+}
+
+// `kvs-server --acl` should deny `kvs-client` the commands not granted by the rules
+#[test]
+fn cli_acl() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("acl"), "* read,write pub/*\n").unwrap();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--acl", "acl", "--resp-addr", "127.0.0.1:4032"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args([
+            "--engine",
+            "memory",
+            "--addr",
+            "127.0.0.1:4031",
+            "--acl",
+            "acl",
+        ])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "pub/key1", "value1", "--addr", "127.0.0.1:4031"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "pub/key1", "--addr", "127.0.0.1:4031"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rm", "pub/key1", "--addr", "127.0.0.1:4031"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("not granted"));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4031"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("not granted"));
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+}
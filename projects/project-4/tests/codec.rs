@@ -0,0 +1,63 @@
+use kvs::{kvs_engine::Event, Codec, Command, Response, Result};
+
+// Should decode what is encoded in both codecs, where the binary payloads are shorter
+#[test]
+fn round_trip() -> Result<()> {
+    let commands = vec![
+        Command::Set {
+            key: "key1".to_owned(),
+            value: "välue1".to_owned(),
+        },
+        Command::Get { key: String::new() },
+        Command::Rm {
+            key: "key1".to_owned(),
+        },
+        Command::Watch {
+            key: "key".to_owned(),
+            prefix: true,
+        },
+    ];
+    let responses = vec![
+        Response::SuccessSet(),
+        Response::SuccessGet(Some("value1".repeat(100))),
+        Response::SuccessGet(None),
+        Response::SuccessRm(),
+        Response::SuccessWatch(),
+        Response::Fail("Key not found".to_owned()),
+        Response::Event(Event::Set {
+            key: "key1".to_owned(),
+            value: "value1".to_owned(),
+        }),
+        Response::Event(Event::Rm {
+            key: "key1".to_owned(),
+        }),
+    ];
+
+    for command in &commands {
+        let text = Codec::Text.encode(command)?;
+        let binary = Codec::Binary.encode(command)?;
+        assert!(binary.len() < text.len());
+        for (codec, payload) in [(Codec::Text, text), (Codec::Binary, binary)] {
+            let decoded: Command = codec.decode(&payload)?;
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", command));
+        }
+    }
+    for response in &responses {
+        let text = Codec::Text.encode(response)?;
+        let binary = Codec::Binary.encode(response)?;
+        assert!(binary.len() < text.len());
+        assert_eq!(Codec::Text.decode::<Response>(&text)?, *response);
+        assert_eq!(Codec::Binary.decode::<Response>(&binary)?, *response);
+    }
+
+    // truncated or trailing bytes are rejected
+    let payload = Codec::Binary.encode(&responses[1])?;
+    assert!(Codec::Binary
+        .decode::<Response>(&payload[..payload.len() - 1])
+        .is_err());
+    let mut payload = payload;
+    payload.push(0);
+    assert!(Codec::Binary.decode::<Response>(&payload).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use kvs::{dump, Error, KvStore, KvsEngine, MemKvsEngine, Result, SledKvsEngine};
+use tempfile::TempDir;
+
+// Should move all live keys of every namespace from one engine to another through a dump
+fn dump_and_load(format: dump::Format) -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path().join("db.kvs"))?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.set("key0".to_owned(), "new\nvalue".to_owned())?;
+    store.remove("key1".to_owned())?;
+    let users = store.open_namespace("users")?;
+    users.set("key1".to_owned(), "alice".to_owned())?;
+    users.set("key2".to_owned(), "bob".to_owned())?;
+
+    let mut buffer = Vec::new();
+    assert_eq!(dump::dump(&store, None, format, &mut buffer)?, 101);
+    assert_eq!(dump::Records::new(buffer.as_slice())?.format(), format);
+
+    let engine = SledKvsEngine::open(temp_dir.path().join("db.sled"))?;
+    assert_eq!(dump::load(&engine, None, buffer.as_slice())?, 101);
+    let users = engine.open_namespace("users")?;
+    assert_eq!(users.get("key1".to_owned())?, Some("alice".to_owned()));
+    assert_eq!(users.get("key2".to_owned())?, Some("bob".to_owned()));
+    assert_eq!(
+        engine.get("key0".to_owned())?,
+        Some("new\nvalue".to_owned())
+    );
+    assert_eq!(engine.get("key1".to_owned())?, None);
+    for i in 2..100 {
+        assert_eq!(
+            engine.get(format!("key{}", i))?,
+            Some(format!("value{}", i))
+        );
+    }
+
+    // only the given namespace is dumped or loaded
+    let mut buffer = Vec::new();
+    assert_eq!(dump::dump(&store, Some("users"), format, &mut buffer)?, 2);
+    let engine = SledKvsEngine::open(temp_dir.path().join("users.sled"))?;
+    assert_eq!(dump::load(&engine, Some("users"), buffer.as_slice())?, 2);
+    assert_eq!(engine.get("key0".to_owned())?, None);
+    let mut buffer = Vec::new();
+    dump::dump(&store, None, format, &mut buffer)?;
+    let engine = SledKvsEngine::open(temp_dir.path().join("default.sled"))?;
+    assert_eq!(dump::load(&engine, Some(""), buffer.as_slice())?, 99);
+    assert_eq!(
+        engine.open_namespace("users")?.get("key1".to_owned())?,
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn dump_and_load_json() -> Result<()> {
+    dump_and_load(dump::Format::Json)
+}
+
+#[test]
+fn dump_and_load_binary() -> Result<()> {
+    dump_and_load(dump::Format::Binary)
+}
+
+// Should refuse to load a dump into a store with live keys
+#[test]
+fn load_into_non_empty_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    let input = "{\"key\":\"key2\",\"value\":\"value2\"}\n";
+    assert!(matches!(
+        dump::load(&store, None, input.as_bytes()),
+        Err(Error::StoreNotEmpty)
+    ));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should read binary dumps written before namespaces into the default namespace
+#[test]
+fn load_binary_without_namespaces() -> Result<()> {
+    let mut input = b"KVSDUMP\x01".to_vec();
+    for bytes in [&b"key1"[..], b"value1"] {
+        input.extend((bytes.len() as u32).to_le_bytes());
+        input.extend(bytes);
+    }
+
+    let engine = MemKvsEngine::new();
+    assert_eq!(dump::load(&engine, None, input.as_slice())?, 1);
+    assert_eq!(engine.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+// Should reject a record whose length exceeds the dump without allocating the length
+#[test]
+fn load_truncated_binary() -> Result<()> {
+    let mut input = b"KVSDUMP\x02".to_vec();
+    input.extend(u32::MAX.to_le_bytes());
+    input.extend(b"ns");
+
+    let engine = MemKvsEngine::new();
+    assert!(matches!(
+        dump::load(&engine, None, input.as_slice()),
+        Err(Error::InvalidDump(_))
+    ));
+
+    Ok(())
+}
+
+// Bulk loading should survive file switching and compaction
+#[test]
+fn load_many_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let pairs = (0..20000).map(|i| Ok((format!("key{}", i % 5000), format!("value{}", i))));
+    assert_eq!(store.load(pairs)?, 20000);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 15000..20000 {
+        assert_eq!(
+            store.get(format!("key{}", i % 5000))?,
+            Some(format!("value{}", i))
+        );
+    }
+
+    Ok(())
+}
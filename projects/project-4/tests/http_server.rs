@@ -0,0 +1,168 @@
+use kvs::{thread_pool::*, HttpServer, KvsEngine, Result, ShardedKvStore, SledKvsEngine};
+use serde_json::{json, Value};
+use sloggers::{null::NullLoggerBuilder, Build};
+use std::{
+    io::{Cursor, Read, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+use tempfile::TempDir;
+
+// sends the request and returns the status and the JSON body, which is null for empty bodies
+fn call(request: ureq::Request, body: Option<&str>) -> (u16, Value) {
+    let result = match body {
+        Some(body) => request.send_string(body),
+        None => request.call(),
+    };
+    let response = match result {
+        Ok(response) => response,
+        Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => panic!("{}", e),
+    };
+    let status = response.status();
+    let body = response.into_string().unwrap();
+    let body = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(&body).unwrap()
+    };
+    (status, body)
+}
+
+fn http_gateway<E: KvsEngine>(engine: E, addr: &str) -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let thread_pool = SharedQueueThreadPool::new(2)?;
+    let mut server = HttpServer::new(logger, addr.parse().unwrap(), engine, thread_pool)?;
+    server.set_max_request_size(2 * 1024 * 1024);
+    let handle = thread::spawn(move || server.run(Some(39)));
+    let url = format!("http://{}", addr);
+
+    let (status, body) = call(ureq::put(&format!("{}/keys/key%201", url)), Some("value1"));
+    assert_eq!((status, body), (204, Value::Null));
+    let (status, body) = call(ureq::get(&format!("{}/keys/key%201", url)), None);
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({"key": "key 1", "value": "value1"}));
+    let (status, body) = call(ureq::get(&format!("{}/keys/key%201?ns=users", url)), None);
+    assert_eq!(status, 404);
+    assert_eq!(body["error"], "KeyNotFound");
+
+    let (status, _) = call(ureq::delete(&format!("{}/keys/key%201", url)), None);
+    assert_eq!(status, 204);
+    let (status, body) = call(ureq::delete(&format!("{}/keys/key%201", url)), None);
+    assert_eq!((status, body["error"].as_str()), (404, Some("KeyNotFound")));
+    let (status, body) = call(ureq::post(&format!("{}/keys/key1", url)), Some(""));
+    assert_eq!(
+        (status, body["error"].as_str()),
+        (405, Some("InvalidCommand"))
+    );
+    match ureq::post(&format!("{}/keys", url)).send_string("") {
+        Err(ureq::Error::Status(405, response)) => {
+            assert_eq!(response.header("Allow"), Some("GET"))
+        }
+        result => panic!(
+            "Expected 405, got {:?}",
+            result.map(|response| response.status())
+        ),
+    }
+    let (status, _) = call(ureq::get(&format!("{}/values", url)), None);
+    assert_eq!(status, 404);
+
+    // large values are streamed in chunks
+    let value = "v".repeat(1024 * 1024);
+    let response = ureq::put(&format!("{}/keys/large", url))
+        .send(Cursor::new(value.clone()))
+        .unwrap();
+    assert_eq!(response.status(), 204);
+    let (_, body) = call(ureq::get(&format!("{}/keys/large", url)), None);
+    assert_eq!(body["value"].as_str(), Some(value.as_str()));
+    let (status, body) = call(
+        ureq::put(&format!("{}/keys/larger", url)),
+        Some(&"v".repeat(3 * 1024 * 1024)),
+    );
+    assert_eq!(
+        (status, body["error"].as_str()),
+        (413, Some("RequestTooLarge"))
+    );
+
+    for i in (0..25).rev() {
+        let (status, _) = call(
+            ureq::put(&format!("{}/keys/user.{:02}", url, i)),
+            Some(&format!("value{}", i)),
+        );
+        assert_eq!(status, 204);
+    }
+    let mut keys = Vec::new();
+    let mut after: Option<String> = None;
+    loop {
+        let mut request = ureq::get(&format!("{}/keys", url))
+            .query("prefix", "user.")
+            .query("limit", "10");
+        if let Some(after) = &after {
+            request = request.query("after", after);
+        }
+        let (status, body) = call(request, None);
+        assert_eq!(status, 200);
+        for item in body["items"].as_array().unwrap() {
+            keys.push(item["key"].as_str().unwrap().to_owned());
+        }
+        match body["next"].as_str() {
+            Some(next) => after = Some(next.to_owned()),
+            None => break,
+        }
+    }
+    let expected: Vec<_> = (0..25).map(|i| format!("user.{:02}", i)).collect();
+    assert_eq!(keys, expected);
+
+    handle.join().unwrap()
+}
+
+// Should serve other requests while a client is slow to send the body, which times out
+#[test]
+fn http_gateway_slow_body() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let logger = NullLoggerBuilder.build()?;
+    let engine = SledKvsEngine::open(temp_dir.path())?;
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let addr = "127.0.0.1:4038";
+    let mut server = HttpServer::new(logger, addr.parse().unwrap(), engine, thread_pool)?;
+    server.set_read_timeout(Duration::from_millis(200));
+    let handle = thread::spawn(move || server.run(Some(3)));
+    let url = format!("http://{}", addr);
+
+    // bodies up to 1 KiB are received by tiny_http itself before the request is handed over
+    let mut stream = TcpStream::connect(addr)?;
+    stream.write_all(b"PUT /keys/key1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n")?;
+    stream.write_all(b"Content-Length: 2048\r\n\r\n")?;
+    stream.write_all(&[b'v'; 1024])?;
+    // the only thread of the pool is not held by the slow body
+    let (status, _) = call(ureq::get(&format!("{}/keys/key2", url)), None);
+    assert_eq!(status, 404);
+
+    thread::sleep(Duration::from_millis(500));
+    stream.write_all(&[b'v'; 1024])?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+    let (status, _) = call(ureq::get(&format!("{}/keys/key1", url)), None);
+    assert_eq!(status, 404);
+
+    handle.join().unwrap()
+}
+
+// Should serve the routes and scan pages in order of keys across the shards
+#[test]
+fn http_gateway_sharded_kv_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    http_gateway(
+        ShardedKvStore::open_with_shards(temp_dir.path(), 4)?,
+        "127.0.0.1:4021",
+    )
+}
+
+// Should serve the routes and scan pages by the ranges of sled
+#[test]
+fn http_gateway_sled_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    http_gateway(SledKvsEngine::open(temp_dir.path())?, "127.0.0.1:4022")
+}
@@ -1,6 +1,12 @@
-use kvs::{KvStore, KvsEngine, Result};
+use kvs::{
+    kvs_engine::{CompactionStats, Event, KvStoreOptions, Retention},
+    Error, KvStore, KvsEngine, Result,
+};
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::sync::{Arc, Barrier};
 use std::thread;
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
@@ -139,6 +145,8 @@ fn concurrent_set() -> Result<()> {
             store
                 .set(format!("key{}", i), format!("value{}", i))
                 .unwrap();
+            // releases the lock of the store before reopening
+            drop(store);
             barrier.wait();
         });
     }
@@ -209,3 +217,649 @@ fn concurrent_get() -> Result<()> {
 
     Ok(())
 }
+
+// Should count live, overwritten and tombstone records and find corrupt ones
+#[test]
+fn check_and_repair() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("db.kvs");
+    let store = KvStore::open(&path)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    store.remove("key2".to_owned())?;
+    store.set("key3".to_owned(), "value4".to_owned())?;
+    drop(store);
+
+    let report = KvStore::check(&path)?;
+    assert_eq!(report.files, 1);
+    assert_eq!(report.live, 2);
+    assert_eq!(report.overwritten, 2);
+    assert_eq!(report.tombstones, 1);
+    assert!(report.corrupt.is_empty());
+
+    // appends a torn record to the data file
+    let data_file = path.join("kvs.data.0");
+    let offset = std::fs::metadata(&data_file)?.len();
+    OpenOptions::new()
+        .append(true)
+        .open(&data_file)?
+        .write_all(b"{\"Set\":{\"key\":\"key4\"#")?;
+    assert!(KvStore::open(&path).is_err());
+
+    let report = KvStore::check(&path)?;
+    assert_eq!(report.live, 2);
+    assert_eq!(report.corrupt, vec![(0, offset)]);
+
+    let new_path = temp_dir.path().join("db.kvs.repaired");
+    KvStore::repair(&path, &new_path)?;
+    assert!(KvStore::check(&new_path)?.corrupt.is_empty());
+
+    let store = KvStore::open(&new_path)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key3".to_owned())?, Some("value4".to_owned()));
+    assert_eq!(store.get("key4".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should salvage the data files when the MANIFEST cannot be parsed
+#[test]
+fn repair_corrupt_manifest() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("db.kvs");
+    let store = KvStore::open(&path)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key2".to_owned())?;
+    drop(store);
+
+    std::fs::write(path.join("MANIFEST"), b"{\"version\":")?;
+    assert!(KvStore::open(&path).is_err());
+    assert!(KvStore::check(&path).is_err());
+
+    let new_path = temp_dir.path().join("db.kvs.repaired");
+    let report = KvStore::repair(&path, &new_path)?;
+    assert_eq!((report.files, report.live), (1, 1));
+
+    let store = KvStore::open(&new_path)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should not open a store that has been opened and not dropped
+#[test]
+fn open_locked_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    assert!(matches!(
+        KvStore::open(temp_dir.path()),
+        Err(Error::StoreLocked)
+    ));
+    assert!(matches!(
+        KvStore::check(temp_dir.path()),
+        Err(Error::StoreLocked)
+    ));
+
+    // the lock is held until all clones are dropped
+    let cloned = store.clone();
+    drop(store);
+    assert!(matches!(
+        KvStore::open(temp_dir.path()),
+        Err(Error::StoreLocked)
+    ));
+    drop(cloned);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    drop(store);
+    assert_eq!(KvStore::check(temp_dir.path())?.live, 1);
+
+    Ok(())
+}
+
+// Should replay the segments listed in the MANIFEST even if their numbers are not contiguous
+#[test]
+fn open_with_manifest() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    // older stores have no MANIFEST and may have gaps between file numbers
+    std::fs::remove_file(temp_dir.path().join("MANIFEST"))?;
+    std::fs::rename(
+        temp_dir.path().join("kvs.data.0"),
+        temp_dir.path().join("kvs.data.3"),
+    )?;
+    std::fs::write(
+        temp_dir.path().join("kvs.data.7"),
+        "{\"Set\":{\"key\":\"key1\",\"value\":\"value2\"}}#",
+    )?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    drop(store);
+    let manifest = std::fs::read_to_string(temp_dir.path().join("MANIFEST"))?;
+    assert!(manifest.contains("[3,7]"));
+
+    // files not in the MANIFEST are left by interrupted rotations and ignored
+    std::fs::write(
+        temp_dir.path().join("kvs.data.8"),
+        "{\"Set\":{\"key\":\"key1\",\"value\":\"value3\"}}#",
+    )?;
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    store.set("key2".to_owned(), "value4".to_owned())?;
+    drop(store);
+    assert!(!temp_dir.path().join("kvs.data.8").exists());
+
+    // segments listed in the MANIFEST must exist
+    std::fs::remove_file(temp_dir.path().join("kvs.data.3"))?;
+    assert!(matches!(
+        KvStore::open(temp_dir.path()),
+        Err(Error::InvalidManifest(_))
+    ));
+
+    Ok(())
+}
+
+// Keys in different namespaces should not affect each other
+#[test]
+fn namespaces() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let users = store.open_namespace("users")?;
+    let orders = store.open_namespace("orders")?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    users.set("key1".to_owned(), "value2".to_owned())?;
+    orders.set("key2".to_owned(), "value3".to_owned())?;
+    users.remove("key1".to_owned())?;
+    assert!(orders.remove("key1".to_owned()).is_err());
+    users.set("key3".to_owned(), "value4".to_owned())?;
+
+    let mut keys = Vec::new();
+    users.for_each(|key, _| {
+        keys.push(key);
+        Ok(())
+    })?;
+    assert_eq!(keys, vec!["key3".to_owned()]);
+
+    // Open from disk again and check persistent data
+    drop((store, users, orders));
+    let store = KvStore::open(temp_dir.path())?;
+    let users = store.open_namespace("users")?;
+    let orders = store.open_namespace("orders")?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(users.get("key1".to_owned())?, None);
+    assert_eq!(users.get("key3".to_owned())?, Some("value4".to_owned()));
+    assert_eq!(orders.get("key2".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.open_namespace("")?.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should disconnect the watcher that falls behind after the events it has buffered
+#[test]
+fn watch_falling_behind() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let watcher = store.watch("key")?;
+    for i in 0..1100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let events: Vec<Event> = watcher.collect();
+    assert_eq!(events.len(), 1024);
+    assert_eq!(events[1023].key(), "key1023");
+    store.set("key".to_owned(), "value".to_owned())?;
+
+    Ok(())
+}
+
+// Should send the changes of watched keys in order until all handles are dropped
+#[test]
+fn watch_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let users = store.open_namespace("users")?;
+    let watcher = store.watch("user.")?;
+    let dropped = store.watch("")?;
+    drop(dropped);
+
+    store.set("user.1".to_owned(), "value1".to_owned())?;
+    store.set("order.1".to_owned(), "value2".to_owned())?;
+    users.set("user.2".to_owned(), "value3".to_owned())?;
+    store.remove("user.1".to_owned())?;
+    store.load(vec![Ok(("user.3".to_owned(), "value4".to_owned()))])?;
+
+    drop((store, users));
+    let events: Vec<Event> = watcher.collect();
+    assert_eq!(
+        events,
+        vec![
+            Event::Set {
+                key: "user.1".to_owned(),
+                value: "value1".to_owned()
+            },
+            Event::Rm {
+                key: "user.1".to_owned()
+            },
+            Event::Set {
+                key: "user.3".to_owned(),
+                value: "value4".to_owned()
+            },
+        ]
+    );
+
+    Ok(())
+}
+
+// Should keep the retained versions of keys across reopening and compaction
+#[test]
+fn version_history() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let retention = Retention {
+        versions: 2,
+        age: None,
+    };
+    let options = KvStoreOptions {
+        retention,
+        ..KvStoreOptions::default()
+    };
+    let store = KvStore::open_with(temp_dir.path(), options)?;
+
+    for i in 1..=3 {
+        store.set("key1".to_owned(), format!("value{}", i))?;
+    }
+    store.remove("key1".to_owned())?;
+
+    let history = store.history("key1", 10)?;
+    let values: Vec<_> = history
+        .iter()
+        .map(|version| version.value.clone())
+        .collect();
+    assert_eq!(
+        values,
+        vec![None, Some("value3".to_owned()), Some("value2".to_owned())]
+    );
+    assert!(history.windows(2).all(|pair| pair[0].seq > pair[1].seq));
+    assert_eq!(store.history("key1", 1)?.len(), 1);
+
+    let seq = history[1].seq;
+    assert_eq!(store.get_at("key1", seq)?, Some("value3".to_owned()));
+    assert_eq!(store.get_at("key1", seq - 1)?, Some("value2".to_owned()));
+    assert_eq!(store.get_at("key1", history[0].seq)?, None);
+    // value1 is no longer retained
+    assert_eq!(store.get_at("key1", seq - 2)?, None);
+
+    drop(store);
+    let store = KvStore::open_with(temp_dir.path(), options)?;
+    assert_eq!(store.history("key1", 10)?, history);
+
+    // new writes keep increasing the sequence number and survive compaction
+    store.set("key1".to_owned(), "value4".to_owned())?;
+    let value = "v".repeat(1024);
+    for _ in 0..2500 {
+        store.set("key2".to_owned(), value.clone())?;
+    }
+    let new_history = store.history("key1", 10)?;
+    assert_eq!(new_history.len(), 3);
+    assert!(new_history[0].seq > history[0].seq);
+    assert_eq!(&new_history[1..], &history[..2]);
+
+    drop(store);
+    let store = KvStore::open_with(temp_dir.path(), options)?;
+    assert_eq!(store.history("key1", 10)?, new_history);
+    assert_eq!(store.history("key2", 10)?.len(), 3);
+
+    Ok(())
+}
+
+// Compaction should drop old versions older than the retention age
+#[test]
+fn version_retention_age() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let retention = Retention {
+        versions: 2,
+        age: Some(Duration::ZERO),
+    };
+    let options = KvStoreOptions {
+        retention,
+        ..KvStoreOptions::default()
+    };
+    let store = KvStore::open_with(temp_dir.path(), options)?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.history("key1", 10)?.len(), 2);
+    thread::sleep(Duration::from_millis(10));
+
+    let value = "v".repeat(1024);
+    for _ in 0..2500 {
+        store.set("key2".to_owned(), value.clone())?;
+    }
+    store.wait_for_compaction();
+    let history = store.history("key1", 10)?;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].value, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Should read a store without creating files or accepting writes
+#[test]
+fn open_read_only() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions {
+        read_only: true,
+        ..KvStoreOptions::default()
+    };
+    assert!(KvStore::open_with(temp_dir.path().join("db"), options).is_err());
+    assert!(!temp_dir.path().join("db").exists());
+
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    std::fs::remove_file(temp_dir.path().join("LOCK"))?;
+    let files = WalkDir::new(temp_dir.path()).into_iter().count();
+
+    let store = KvStore::open_with(temp_dir.path(), options)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert!(matches!(
+        store.set("key2".to_owned(), "value2".to_owned()),
+        Err(Error::ReadOnly)
+    ));
+    assert!(matches!(
+        store.remove("key1".to_owned()),
+        Err(Error::ReadOnly)
+    ));
+    assert!(matches!(
+        store.load(vec![Ok(("key2".to_owned(), "value2".to_owned()))]),
+        Err(Error::ReadOnly)
+    ));
+    assert_eq!(WalkDir::new(temp_dir.path()).into_iter().count(), files);
+
+    // does not hold the lock of the writer
+    let writer = KvStore::open(temp_dir.path())?;
+    writer.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+// Should keep the files replaced by compaction until the read-only stores are dropped
+#[test]
+fn read_only_during_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let writer = KvStore::open(temp_dir.path())?;
+    writer.set("key1".to_owned(), "value1".to_owned())?;
+
+    let options = KvStoreOptions {
+        read_only: true,
+        ..KvStoreOptions::default()
+    };
+    let store = KvStore::open_with(temp_dir.path(), options)?;
+
+    let value = "v".repeat(1024);
+    for _ in 0..2500 {
+        writer.set("key2".to_owned(), value.clone())?;
+    }
+    writer.wait_for_compaction();
+    assert!(writer.compaction_stats().compactions > 0);
+    assert!(temp_dir.path().join("kvs.data.0").exists());
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    drop(store);
+    for _ in 0..1500 {
+        writer.set("key2".to_owned(), value.clone())?;
+    }
+    writer.wait_for_compaction();
+    assert!(!temp_dir.path().join("kvs.data.0").exists());
+    assert_eq!(writer.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+// Should follow the records appended by the writer, even after compaction
+#[test]
+fn follow_writer() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let writer = KvStore::open(temp_dir.path())?;
+    writer.set("key1".to_owned(), "value1".to_owned())?;
+
+    let options = KvStoreOptions {
+        read_only: true,
+        follow: Some(Duration::from_millis(10)),
+        ..KvStoreOptions::default()
+    };
+    let store = KvStore::open_with(temp_dir.path(), options)?;
+    let mut watcher = store.watch("key")?;
+
+    writer.set("key2".to_owned(), "value2".to_owned())?;
+    writer.remove("key1".to_owned())?;
+    assert_eq!(
+        watcher.next(),
+        Some(Event::Set {
+            key: "key2".to_owned(),
+            value: "value2".to_owned()
+        })
+    );
+    assert_eq!(
+        watcher.next(),
+        Some(Event::Rm {
+            key: "key1".to_owned()
+        })
+    );
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    // compaction replaces the segments, which are reread
+    let value = "v".repeat(1024);
+    for _ in 0..2500 {
+        writer.set("key3".to_owned(), value.clone())?;
+    }
+    writer.set("key4".to_owned(), "value4".to_owned())?;
+    for _ in 0..100 {
+        if store.get("key4".to_owned())?.is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(store.get("key4".to_owned())?, Some("value4".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some(value));
+
+    Ok(())
+}
+
+// Compaction should keep within the rate and count the time it was throttled
+#[test]
+fn compaction_rate() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions {
+        compaction_rate: Some(200 * 1024),
+        ..KvStoreOptions::default()
+    };
+    let store = KvStore::open_with(temp_dir.path(), options)?;
+
+    let value = "v".repeat(1024);
+    for i in 0..200 {
+        store.set(format!("key{}", i), value.clone())?;
+    }
+    assert_eq!(store.compaction_stats(), CompactionStats::default());
+    for _ in 0..2500 {
+        store.set("key0".to_owned(), value.clone())?;
+    }
+
+    store.wait_for_compaction();
+    let stats = store.compaction_stats();
+    assert!(stats.compactions > 0);
+    assert!(stats.bytes >= 200 * 1024);
+    // about one second for rewriting the values
+    assert!(stats.throttled >= Duration::from_millis(500));
+    assert!(stats.elapsed >= stats.throttled);
+    for i in 0..200 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(value.clone()));
+    }
+
+    Ok(())
+}
+
+// Writes should not wait for a throttled compaction, which runs in the background
+#[test]
+fn background_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = KvStoreOptions {
+        compaction_rate: Some(100 * 1024),
+        ..KvStoreOptions::default()
+    };
+    let store = KvStore::open_with(temp_dir.path(), options)?;
+
+    let value = "v".repeat(1024);
+    for i in 0..200 {
+        store.set(format!("key{}", i), value.clone())?;
+    }
+    let mut latency = Duration::ZERO;
+    for _ in 0..2500 {
+        let start = Instant::now();
+        store.set("key0".to_owned(), value.clone())?;
+        latency = latency.max(start.elapsed());
+    }
+    // about two seconds for rewriting the values
+    assert!(latency < Duration::from_millis(250));
+
+    store.wait_for_compaction();
+    let stats = store.compaction_stats();
+    assert!(stats.compactions > 0);
+    assert!(stats.throttled >= Duration::from_millis(1000));
+    for i in 0..200 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(value.clone()));
+    }
+
+    Ok(())
+}
+
+// Compaction should only rewrite the segments that are mostly garbage, and keep the tombstones
+// of keys that may be set in the segments before them
+#[test]
+fn incremental_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let value = "v".repeat(1024);
+    for i in 0..1100 {
+        store.set(format!("key{}", i), value.clone())?;
+    }
+    for i in 0..10 {
+        store.remove(format!("key{}", i))?;
+    }
+    for _ in 0..2500 {
+        store.set("key".to_owned(), value.clone())?;
+    }
+
+    store.wait_for_compaction();
+    let stats = store.compaction_stats();
+    assert!(stats.segments > 0);
+    assert!(stats.bytes < 512 * 1024);
+    assert!(temp_dir.path().join("kvs.data.0").exists());
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..10 {
+        assert_eq!(store.get(format!("key{}", i))?, None);
+    }
+    for i in 10..1100 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(value.clone()));
+    }
+    assert_eq!(store.get("key".to_owned())?, Some(value));
+
+    Ok(())
+}
+
+// Compaction should drop the rm-records of removed keys once the segments before them
+// no longer have the older records, so that the space of removed keys is reclaimed
+#[test]
+fn reclaim_removed_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let dir_size = || {
+        let entries = WalkDir::new(temp_dir.path()).into_iter();
+        let len: walkdir::Result<u64> = entries
+            .map(|res| {
+                res.and_then(|entry| entry.metadata())
+                    .map(|metadata| metadata.len())
+            })
+            .sum();
+        len.expect("fail to get directory size")
+    };
+    let key = |i: usize| format!("{:0>4096}", i);
+    let value = "v".repeat(1024);
+    let fill = |store: &KvStore| -> Result<()> {
+        for _ in 0..2500 {
+            store.set("filler".to_owned(), value.clone())?;
+        }
+        store.wait_for_compaction();
+        Ok(())
+    };
+
+    for i in 0..1000 {
+        store.set(key(i), "value".to_owned())?;
+    }
+    // the rm-records are kept while the segments of the keys are below the threshold
+    for i in (0..1000).filter(|i| i % 5 < 2) {
+        store.remove(key(i))?;
+    }
+    fill(&store)?;
+    for i in (0..1000).filter(|i| i % 5 == 2) {
+        store.remove(key(i))?;
+    }
+    fill(&store)?;
+    fill(&store)?;
+
+    // the live keys and at most the active file
+    assert!(dir_size() < 3 * 1024 * 1024);
+    drop(store);
+    assert_eq!(KvStore::check(temp_dir.path())?.tombstones, 0);
+
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..1000 {
+        let expected = (i % 5 > 2).then(|| "value".to_owned());
+        assert_eq!(store.get(key(i))?, expected);
+    }
+
+    Ok(())
+}
+
+// Should scan pages of the live keys with the prefix in order, including the ones in older files
+#[test]
+fn scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for i in (0..30).rev() {
+        store.set(format!("key{:02}", i), format!("value{}", i))?;
+    }
+    store.set("other".to_owned(), "value".to_owned())?;
+    store.remove("key05".to_owned())?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    let page = store.scan("key", None, 10)?;
+    assert_eq!(page.len(), 10);
+    assert_eq!(page[0], ("key00".to_owned(), "value0".to_owned()));
+    assert_eq!(page[9].0, "key10");
+    let page = store.scan("key", Some(&page[9].0), 100)?;
+    let keys: Vec<_> = page.into_iter().map(|(key, _)| key).collect();
+    let expected: Vec<_> = (11..30).map(|i| format!("key{:02}", i)).collect();
+    assert_eq!(keys, expected);
+    assert!(store.open_namespace("ns")?.scan("", None, 10)?.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,633 @@
+use kvs::{
+    acl::Acl,
+    auth::{self, Credentials},
+    kvs_engine::Event,
+    thread_pool::*,
+    Codec, Command, Error, KvsClient, KvsServer, MemKvsEngine, Response, Result, FEATURES,
+    PROTOCOL_VERSION,
+};
+use sloggers::{null::NullLoggerBuilder, Build};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tempfile::TempDir;
+
+// Should serve many commands on one connection until the client closes it
+#[test]
+fn persistent_connection() -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4012".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    let handle = thread::spawn(move || server.run(Some(2)));
+
+    let mut client = KvsClient::connect(addr)?;
+    for i in 0..100 {
+        let response = client.send(Command::Set {
+            key: format!("key{}", i),
+            value: format!("value{}", i),
+        })?;
+        assert!(matches!(response, Response::SuccessSet()));
+    }
+    for i in 0..100 {
+        let response = client.send(Command::Get {
+            key: format!("key{}", i),
+        })?;
+        assert!(
+            matches!(response, Response::SuccessGet(Some(value)) if value == format!("value{}", i))
+        );
+    }
+    let response = client.send(Command::Rm {
+        key: "key100".to_owned(),
+    })?;
+    assert!(matches!(response, Response::Fail(_)));
+    client.close()?;
+
+    // the only thread of the pool is released after the first connection is closed
+    let mut client = KvsClient::connect(addr)?;
+    client.set_namespace("ns");
+    let response = client.send(Command::Get {
+        key: "key0".to_owned(),
+    })?;
+    assert!(matches!(response, Response::SuccessGet(None)));
+    client.set_namespace("");
+    let response = client.send(Command::Get {
+        key: "key0".to_owned(),
+    })?;
+    assert!(matches!(response, Response::SuccessGet(Some(_))));
+    client.close()?;
+
+    handle.join().unwrap()
+}
+
+// Should serve more open connections than the threads of the pool,
+// and close the idle ones and the ones over the limit
+#[test]
+fn many_connections() -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4033".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    server.set_max_connections(4);
+    server.set_idle_timeout(Some(Duration::from_millis(500)));
+    let handle = thread::spawn(move || server.run(Some(6)));
+
+    let mut clients = (0..4)
+        .map(|_| KvsClient::connect(addr))
+        .collect::<Result<Vec<_>>>()?;
+    assert!(KvsClient::connect(addr).is_err());
+    for round in 0..10 {
+        for (i, client) in clients.iter_mut().enumerate() {
+            let response = client.send(Command::Set {
+                key: format!("key{}", i),
+                value: format!("value{}", round),
+            })?;
+            assert_eq!(response, Response::SuccessSet());
+        }
+    }
+    for client in clients.drain(1..) {
+        client.close()?;
+    }
+
+    // the silent connection is closed, which frees its slot for new ones
+    thread::sleep(Duration::from_secs(1));
+    assert!(clients[0]
+        .send(Command::Get {
+            key: "key0".to_owned(),
+        })
+        .is_err());
+    let mut client = KvsClient::connect(addr)?;
+    let response = client.send(Command::Get {
+        key: "key3".to_owned(),
+    })?;
+    assert_eq!(response, Response::SuccessGet(Some("value9".to_owned())));
+    client.close()?;
+
+    handle.join().unwrap()
+}
+
+// Should accept requests larger than 1 KiB, and reject the ones over the limit without closing the connection
+#[test]
+fn max_request_size() -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4013".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    server.set_max_request_size(64 * 1024);
+    let handle = thread::spawn(move || server.run(Some(3)));
+
+    let mut client = KvsClient::connect(addr)?;
+    let value = "v".repeat(32 * 1024);
+    let response = client.send(Command::Set {
+        key: "key1".to_owned(),
+        value: value.clone(),
+    })?;
+    assert!(matches!(response, Response::SuccessSet()));
+
+    let response = client.send(Command::Set {
+        key: "key2".to_owned(),
+        value: "v".repeat(100 * 1024),
+    })?;
+    assert!(matches!(response, Response::Fail(msg) if msg.contains("too large")));
+
+    let response = client.send(Command::Get {
+        key: "key1".to_owned(),
+    })?;
+    assert!(matches!(response, Response::SuccessGet(Some(v)) if v == value));
+    let response = client.send(Command::Get {
+        key: "key2".to_owned(),
+    })?;
+    assert!(matches!(response, Response::SuccessGet(None)));
+    client.close()?;
+
+    // requests far over the limit and lengths longer than any numbers close the connection,
+    // where the first request is answered like the clients of the version 1 expect
+    let mut stream = TcpStream::connect(addr)?;
+    stream.write_all(b"1000000000#")?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    assert!(
+        matches!(Codec::Text.decode::<Response>(&response)?, Response::Fail(msg) if msg.contains("too large"))
+    );
+    let mut stream = TcpStream::connect(addr)?;
+    stream.write_all(&[b'9'; 100])?;
+    // the connection may be reset for the bytes left unread
+    assert!(!matches!(stream.read(&mut [0; 1]), Ok(len) if len > 0));
+
+    handle.join().unwrap()
+}
+
+// Should send pipelined commands and get their responses in order, even if they overflow socket buffers
+#[test]
+fn pipeline() -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4014".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    let handle = thread::spawn(move || server.run(Some(1)));
+
+    let mut client = KvsClient::connect(addr)?;
+    let value = "v".repeat(4096);
+    let mut pipeline = client.pipeline();
+    for i in 0..1000 {
+        pipeline = pipeline.command(Command::Set {
+            key: format!("key{}", i),
+            value: value.clone(),
+        });
+    }
+    let responses = pipeline.send()?;
+    assert_eq!(responses.len(), 1000);
+    assert!(responses
+        .iter()
+        .all(|response| matches!(response, Response::SuccessSet())));
+
+    let mut pipeline = client.pipeline().command(Command::Rm {
+        key: "key1000".to_owned(),
+    });
+    for i in 0..1000 {
+        pipeline = pipeline.command(Command::Get {
+            key: format!("key{}", i),
+        });
+    }
+    let responses = pipeline.send()?;
+    assert!(matches!(responses[0], Response::Fail(_)));
+    assert!(responses[1..]
+        .iter()
+        .all(|response| matches!(response, Response::SuccessGet(Some(v)) if *v == value)));
+
+    let result = client
+        .pipeline()
+        .command(Command::Watch {
+            key: "key".to_owned(),
+            prefix: true,
+        })
+        .send();
+    assert!(matches!(result, Err(Error::InvalidCommand(_))));
+
+    // the connection is still usable after pipelines
+    let response = client.send(Command::Get {
+        key: "key0".to_owned(),
+    })?;
+    assert!(matches!(response, Response::SuccessGet(Some(_))));
+    client.close()?;
+
+    handle.join().unwrap()
+}
+
+// Should agree on the protocol with clients, keep serving clients without handshakes,
+// and reject clients whose version is too old
+#[test]
+fn handshake() -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4015".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    let handle = thread::spawn(move || server.run(Some(4)));
+
+    let client = KvsClient::connect(addr)?;
+    assert_eq!(client.handshake().version, PROTOCOL_VERSION);
+    assert!(FEATURES
+        .iter()
+        .all(|feature| client.handshake().supports(feature)));
+    client.close()?;
+
+    // clients of the version 1 send one command without handshakes on each connection
+    let response = send_once(addr, "=5,'3,Set,'3,key,'4,key1,'5,value,'6,value1,")?;
+    assert_eq!(
+        Codec::Text.decode::<Response>(&response)?,
+        Response::SuccessSet()
+    );
+    let response = send_once(addr, "=3,'3,Get,'3,key,'4,key1,")?;
+    assert_eq!(
+        Codec::Text.decode::<Response>(&response)?,
+        Response::SuccessGet(Some("value1".to_owned()))
+    );
+
+    let mut stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = exchange(
+        &mut stream,
+        &mut reader,
+        "=4,'7,version,'1,0,'8,features,=0,",
+    )?;
+    assert!(response.contains("Fail") && response.contains("Unsupported protocol version 0"));
+    // the server closes the connection after rejecting the handshake
+    assert_eq!(reader.read(&mut [0; 1])?, 0);
+    handle.join().unwrap()?;
+
+    // servers older than handshakes fail to parse them as commands and close the connection
+    let listener = TcpListener::bind("127.0.0.1:4034")?;
+    let addr = listener.local_addr()?;
+    let old_server = thread::spawn(move || -> Result<()> {
+        let mut reader = BufReader::new(listener.accept()?.0);
+        let mut len = Vec::new();
+        reader.read_until(b'#', &mut len)?;
+        let len: usize = String::from_utf8_lossy(&len[..len.len() - 1])
+            .parse()
+            .unwrap();
+        reader.read_exact(&mut vec![0; len])?;
+        Ok(())
+    });
+    assert!(matches!(
+        KvsClient::connect(addr),
+        Err(Error::IncompatibleProtocol(_))
+    ));
+    old_server.join().unwrap()
+}
+
+// Should serve clients in the text and binary codecs, including the events of watches
+#[test]
+fn codecs() -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4016".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(3)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    let handle = thread::spawn(move || server.run(Some(3)));
+
+    let mut binary = KvsClient::connect(addr)?;
+    assert!(binary.handshake().supports("binary"));
+    let mut text = KvsClient::connect_with(addr, Codec::Text)?;
+    assert!(!text.handshake().supports("binary"));
+
+    let events = KvsClient::connect(addr)?.watch("key".to_owned(), true)?;
+    for client in [&mut binary, &mut text] {
+        client.set_namespace("");
+        let response = client.send(Command::Set {
+            key: "key1".to_owned(),
+            value: "välue1".to_owned(),
+        })?;
+        assert_eq!(response, Response::SuccessSet());
+        client.set_namespace("ns");
+        let response = client.send(Command::Get {
+            key: "key1".to_owned(),
+        })?;
+        assert_eq!(response, Response::SuccessGet(None));
+    }
+    binary.set_namespace("");
+    let response = binary.send(Command::Rm {
+        key: "key1".to_owned(),
+    })?;
+    assert_eq!(response, Response::SuccessRm());
+    let response = text.send(Command::Rm {
+        key: "key1".to_owned(),
+    })?;
+    assert!(matches!(response, Response::Fail(_)));
+
+    let events: Vec<Event> = events.take(3).collect::<Result<_>>()?;
+    let set = Event::Set {
+        key: "key1".to_owned(),
+        value: "välue1".to_owned(),
+    };
+    let rm = Event::Rm {
+        key: "key1".to_owned(),
+    };
+    assert_eq!(events, vec![set.clone(), set, rm]);
+
+    binary.close()?;
+    text.close()?;
+    handle.join().unwrap()
+}
+
+// Should limit the watches streamed at the same time, and end the watches whose clients disconnect
+#[test]
+fn watch_limit() -> Result<()> {
+    let logs = Arc::new(Mutex::new(Vec::new()));
+    let logger = slog::Logger::root(Collector(Arc::clone(&logs)), slog::o!());
+    let addr = "127.0.0.1:4035".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    server.set_max_watches(1);
+    let handle = thread::spawn(move || server.run(Some(4)));
+
+    let events = KvsClient::connect(addr)?.watch("key".to_owned(), true)?;
+    assert!(matches!(
+        KvsClient::connect(addr)?.watch("key".to_owned(), true),
+        Err(Error::ServerError(msg)) if msg == "Too many watches"
+    ));
+
+    // the watch without events ends soon after its client disconnects, which frees its slot
+    drop(events);
+    thread::sleep(Duration::from_secs(1));
+    let mut events = KvsClient::connect(addr)?.watch("key".to_owned(), true)?;
+    let mut client = KvsClient::connect(addr)?;
+    let response = client.send(Command::Set {
+        key: "key1".to_owned(),
+        value: "value1".to_owned(),
+    })?;
+    assert_eq!(response, Response::SuccessSet());
+    assert_eq!(
+        events.next().unwrap()?,
+        Event::Set {
+            key: "key1".to_owned(),
+            value: "value1".to_owned(),
+        }
+    );
+    client.close()?;
+    handle.join().unwrap()?;
+
+    assert!(logs
+        .lock()
+        .unwrap()
+        .contains(&String::from("Watch closed by the client")));
+    Ok(())
+}
+
+// Should reject commands until the connection is authenticated by a password or a token
+#[test]
+fn authentication() -> Result<()> {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("credentials");
+    let (token, line) = auth::hash_token("bot")?;
+    let lines = [
+        String::from("# users of tests"),
+        auth::hash_password("alice", "secret")?,
+        line,
+    ];
+    fs::write(&path, lines.join("\n"))?;
+    let credentials = Credentials::open(&path)?;
+    assert!(auth::hash_password("a:b", "secret").is_err());
+    fs::write(temp_dir.path().join("invalid"), "alice:plain$secret")?;
+    assert!(matches!(
+        Credentials::open(temp_dir.path().join("invalid")),
+        Err(Error::InvalidCredentials(_))
+    ));
+
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4028".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(3)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    server.set_credentials(Arc::new(credentials));
+    let handle = thread::spawn(move || server.run(Some(4)));
+
+    let mut client = KvsClient::connect(addr)?;
+    let get = Command::Get {
+        key: "key1".to_owned(),
+    };
+    assert!(matches!(
+        client.send(get.clone())?,
+        Response::Unauthenticated(_)
+    ));
+    assert!(matches!(
+        client.authenticate(Some("alice"), "wrong"),
+        Err(Error::Unauthenticated(_))
+    ));
+    assert!(matches!(
+        client.authenticate(Some("bob"), "secret"),
+        Err(Error::Unauthenticated(_))
+    ));
+    assert!(matches!(
+        client.authenticate(None, "secret"),
+        Err(Error::Unauthenticated(_))
+    ));
+    assert!(matches!(
+        client.send(get.clone())?,
+        Response::Unauthenticated(_)
+    ));
+    assert_eq!(client.authenticate(Some("alice"), "secret")?, "alice");
+    let response = client.send(Command::Set {
+        key: "key1".to_owned(),
+        value: "value1".to_owned(),
+    })?;
+    assert_eq!(response, Response::SuccessSet());
+    client.close()?;
+
+    assert!(matches!(
+        KvsClient::connect(addr)?.watch("key1".to_owned(), false),
+        Err(Error::Unauthenticated(_))
+    ));
+
+    let mut client = KvsClient::connect_with(addr, Codec::Text)?;
+    assert_eq!(client.authenticate(None, &token)?, "bot");
+    let response = client.send(get.clone())?;
+    assert_eq!(response, Response::SuccessGet(Some("value1".to_owned())));
+    // a failed authentication drops the user authenticated before
+    assert!(matches!(
+        client.authenticate(Some("alice"), "wrong"),
+        Err(Error::Unauthenticated(_))
+    ));
+    assert!(matches!(
+        client.send(get.clone())?,
+        Response::Unauthenticated(_)
+    ));
+    client.close()?;
+
+    // the connection is closed after too many failures
+    let mut client = KvsClient::connect(addr)?;
+    for _ in 0..5 {
+        assert!(matches!(
+            client.authenticate(Some("alice"), "guess"),
+            Err(Error::Unauthenticated(_))
+        ));
+    }
+    assert!(client.send(get).is_err());
+
+    handle.join().unwrap()
+}
+
+// Should deny the commands not granted to the authenticated users by the ACL, and log the denials
+#[test]
+fn access_control() -> Result<()> {
+    let temp_dir = TempDir::new().unwrap();
+    let (token, line) = auth::hash_token("bob")?;
+    let credentials = [auth::hash_password("alice", "secret")?, line];
+    fs::write(temp_dir.path().join("credentials"), credentials.join("\n"))?;
+    let rules = [
+        "# users own their keys",
+        "alice read,write,delete users/alice/*",
+        "alice read shared/*",
+        "alice read,write notes:drafts/*",
+        "bob admin *",
+        "* read public",
+    ];
+    fs::write(temp_dir.path().join("acl"), rules.join("\n"))?;
+    fs::write(temp_dir.path().join("invalid"), "alice execute *")?;
+    assert!(matches!(
+        Acl::open(temp_dir.path().join("invalid")),
+        Err(Error::InvalidAcl(_))
+    ));
+
+    let logs = Arc::new(Mutex::new(Vec::new()));
+    let logger = slog::Logger::root(Collector(Arc::clone(&logs)), slog::o!());
+    let addr = "127.0.0.1:4030".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(3)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    server.set_credentials(Arc::new(Credentials::open(
+        temp_dir.path().join("credentials"),
+    )?));
+    server.set_acl(Arc::new(Acl::open(temp_dir.path().join("acl"))?));
+    let handle = thread::spawn(move || server.run(Some(3)));
+
+    let set = |key: &str| Command::Set {
+        key: key.to_owned(),
+        value: "value".to_owned(),
+    };
+    let get = |key: &str| Command::Get {
+        key: key.to_owned(),
+    };
+    let mut alice = KvsClient::connect(addr)?;
+    alice.authenticate(Some("alice"), "secret")?;
+    alice.set_namespace("ns");
+    assert_eq!(alice.send(set("users/alice/k1"))?, Response::SuccessSet());
+    // rules with namespaces grant nothing in the other namespaces
+    alice.set_namespace("notes");
+    assert_eq!(alice.send(set("drafts/k1"))?, Response::SuccessSet());
+    alice.set_namespace("ns");
+    assert!(matches!(
+        alice.send(set("drafts/k1"))?,
+        Response::PermissionDenied(_)
+    ));
+    alice.set_namespace("");
+    assert!(matches!(
+        alice.send(set("drafts/k1"))?,
+        Response::PermissionDenied(_)
+    ));
+    assert_eq!(alice.send(set("users/alice/k1"))?, Response::SuccessSet());
+    let response = alice.send(Command::Rm {
+        key: "users/alice/k1".to_owned(),
+    })?;
+    assert_eq!(response, Response::SuccessRm());
+    assert!(matches!(
+        alice.send(set("users/bob/k1"))?,
+        Response::PermissionDenied(_)
+    ));
+    assert!(matches!(
+        alice.send(set("shared/k1"))?,
+        Response::PermissionDenied(_)
+    ));
+    assert!(matches!(
+        alice.send(set("public"))?,
+        Response::PermissionDenied(_)
+    ));
+    assert_eq!(alice.send(get("shared/k1"))?, Response::SuccessGet(None));
+    assert_eq!(alice.send(get("public"))?, Response::SuccessGet(None));
+    let response = alice.send(Command::Rm {
+        key: "shared/k1".to_owned(),
+    })?;
+    assert!(matches!(response, Response::PermissionDenied(_)));
+
+    // prefix watches need the rules granting all keys with the prefix
+    let mut watcher = KvsClient::connect(addr)?;
+    watcher.authenticate(Some("alice"), "secret")?;
+    assert!(matches!(
+        watcher.watch("users/".to_owned(), true),
+        Err(Error::PermissionDenied(_))
+    ));
+
+    let mut bob = KvsClient::connect(addr)?;
+    bob.authenticate(None, &token)?;
+    assert_eq!(bob.send(set("shared/k1"))?, Response::SuccessSet());
+    assert_eq!(
+        alice.send(get("shared/k1"))?,
+        Response::SuccessGet(Some("value".to_owned()))
+    );
+
+    alice.close()?;
+    bob.close()?;
+    handle.join().unwrap()?;
+
+    let logs = logs.lock().unwrap();
+    let denials: Vec<_> = logs
+        .iter()
+        .filter(|msg| msg.starts_with("Permission denied"))
+        .collect();
+    assert_eq!(denials.len(), 7);
+    assert!(denials.contains(&&String::from(
+        "Permission denied: write on key \"shared/k1\" to alice"
+    )));
+    assert!(denials.contains(&&String::from(
+        "Permission denied: read on keys starting with \"users/\" to alice"
+    )));
+    assert!(denials.contains(&&String::from(
+        "Permission denied: write on key \"drafts/k1\" in namespace \"ns\" to alice"
+    )));
+    Ok(())
+}
+
+// collects the messages of the logs
+struct Collector(Arc<Mutex<Vec<String>>>);
+
+impl slog::Drain for Collector {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &slog::Record,
+        _: &slog::OwnedKVList,
+    ) -> std::result::Result<(), slog::Never> {
+        self.0.lock().unwrap().push(record.msg().to_string());
+        Ok(())
+    }
+}
+
+// sends the payload with its length on a new connection like the clients of the version 1,
+// which read the response until the server closes the connection
+fn send_once(addr: SocketAddr, payload: &str) -> Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.write_all(format!("{}#{}", payload.len(), payload).as_bytes())?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    Ok(response)
+}
+
+// writes the payload with its length and reads the payload of the response
+fn exchange(
+    stream: &mut TcpStream,
+    reader: &mut BufReader<TcpStream>,
+    payload: &str,
+) -> Result<String> {
+    stream.write_all(format!("{}#{}", payload.len(), payload).as_bytes())?;
+    let mut len = Vec::new();
+    reader.read_until(b'#', &mut len)?;
+    let len: usize = String::from_utf8_lossy(&len[..len.len() - 1])
+        .parse()
+        .unwrap();
+    let mut response = vec![0; len];
+    reader.read_exact(&mut response)?;
+    Ok(String::from_utf8(response).unwrap())
+}
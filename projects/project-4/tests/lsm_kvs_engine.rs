@@ -0,0 +1,175 @@
+use kvs::{Error, KvsEngine, LsmKvsEngine, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use tempfile::TempDir;
+
+// Should get previously stored values from the memtable and after reopening
+#[test]
+fn get_stored_value() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmKvsEngine::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    store.remove("key2".to_owned())?;
+    assert!(matches!(
+        store.remove("key2".to_owned()),
+        Err(Error::KeyNotFound)
+    ));
+
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    // Open from disk again and replay the WAL
+    drop(store);
+    let store = LsmKvsEngine::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should keep the latest values and removals through flushes and compactions
+#[test]
+fn flush_and_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmKvsEngine::open(temp_dir.path())?;
+    let users = store.open_namespace("users")?;
+
+    // about 6 MiB of writes, which fills the memtable six times
+    let value = "v".repeat(1000);
+    for iter in 0..3 {
+        for key_id in 0..2000 {
+            store.set(format!("key{}", key_id), format!("{}{}", value, iter))?;
+        }
+    }
+    for key_id in (0..2000).step_by(2) {
+        store.remove(format!("key{}", key_id))?;
+    }
+    users.set("key1".to_owned(), "value".to_owned())?;
+
+    let tables = || {
+        fs::read_dir(temp_dir.path())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_str().unwrap().ends_with(".sst")
+            })
+            .count()
+    };
+    let check = |store: &LsmKvsEngine| -> Result<()> {
+        for key_id in 0..2000 {
+            let expected = (key_id % 2 == 1).then(|| format!("{}2", value));
+            assert_eq!(store.get(format!("key{}", key_id))?, expected);
+        }
+
+        let mut keys = Vec::new();
+        store.for_each(|key, _| {
+            keys.push(key);
+            Ok(())
+        })?;
+        let mut expected: Vec<String> = (1..2000)
+            .step_by(2)
+            .map(|id| format!("key{}", id))
+            .collect();
+        expected.sort();
+        assert_eq!(keys, expected);
+
+        assert_eq!(store.namespaces()?, vec!["".to_owned(), "users".to_owned()]);
+        assert_eq!(
+            store.open_namespace("users")?.get("key1".to_owned())?,
+            Some("value".to_owned())
+        );
+        Ok(())
+    };
+    check(&store)?;
+
+    // Open from disk again and check persistent data,
+    // where dropping the store waits for the compaction in the background
+    drop((store, users));
+    assert!(tables() > 0);
+    assert!(tables() < 6, "level 0 should have been compacted");
+    let store = LsmKvsEngine::open(temp_dir.path())?;
+    check(&store)
+}
+
+// Should refuse to open a store that is already opened
+#[test]
+fn open_locked_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmKvsEngine::open(temp_dir.path())?;
+    assert!(matches!(
+        LsmKvsEngine::open(temp_dir.path()),
+        Err(Error::StoreLocked)
+    ));
+
+    drop(store);
+    LsmKvsEngine::open(temp_dir.path())?;
+    Ok(())
+}
+
+// Should scan live keys with the prefix in order across the memtable and tables
+#[test]
+fn scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmKvsEngine::open(temp_dir.path())?;
+    let users = store.open_namespace("users")?;
+
+    for i in 0..3000 {
+        store.set(format!("key{:04}", i), format!("value{}", i))?;
+    }
+    for i in (0..3000).step_by(2) {
+        store.remove(format!("key{:04}", i))?;
+    }
+    store.set("other".to_owned(), "value".to_owned())?;
+    users.set("key0001".to_owned(), "value".to_owned())?;
+
+    let pairs = store.scan("key", None, 3)?;
+    let keys: Vec<_> = pairs.into_iter().map(|(key, _)| key).collect();
+    assert_eq!(keys, vec!["key0001", "key0003", "key0005"]);
+    let pairs = store.scan("key", Some("key2995"), 10)?;
+    assert_eq!(
+        pairs,
+        vec![
+            ("key2997".to_owned(), "value2997".to_owned()),
+            ("key2999".to_owned(), "value2999".to_owned())
+        ]
+    );
+    assert_eq!(store.scan("", Some("key2999"), 10)?.len(), 1);
+    assert_eq!(users.scan("", None, 10)?.len(), 1);
+
+    Ok(())
+}
+
+// Should drop only the torn last record of the WAL, and refuse to open a WAL corrupted before it
+#[test]
+fn torn_wal() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmKvsEngine::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    let wal = temp_dir.path().join("wal.log");
+    let mut file = OpenOptions::new().append(true).open(&wal)?;
+    file.write_all(b"{\"key\":\"key3\",\"val")?;
+    drop(file);
+    let store = LsmKvsEngine::open(temp_dir.path())?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, None);
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    drop(store);
+
+    let store = LsmKvsEngine::open(temp_dir.path())?;
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    drop(store);
+
+    let content = fs::read_to_string(&wal)?;
+    fs::write(&wal, content.replacen("value1", "value1\"\n", 1))?;
+    assert!(matches!(
+        LsmKvsEngine::open(temp_dir.path()),
+        Err(Error::InvalidWal(_))
+    ));
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use kvs::{Error, KvsEngine, MemKvsEngine, Result};
+
+// Should set, get and remove keys of each namespace without any files
+#[test]
+fn set_get_and_remove() -> Result<()> {
+    let store = MemKvsEngine::new();
+    let users = store.open_namespace("users")?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    users.set("key1".to_owned(), "value2".to_owned())?;
+    store.clone().set("key2".to_owned(), "value3".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(users.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(users.get("key2".to_owned())?, None);
+    assert_eq!(store.namespaces()?, vec!["".to_owned(), "users".to_owned()]);
+
+    users.remove("key1".to_owned())?;
+    assert!(matches!(
+        users.remove("key1".to_owned()),
+        Err(Error::KeyNotFound)
+    ));
+    assert_eq!(store.namespaces()?, vec!["".to_owned()]);
+
+    let mut pairs = Vec::new();
+    store.for_each(|key, value| {
+        pairs.push((key, value));
+        Ok(())
+    })?;
+    pairs.sort();
+    assert_eq!(
+        pairs,
+        vec![
+            ("key1".to_owned(), "value1".to_owned()),
+            ("key2".to_owned(), "value3".to_owned())
+        ]
+    );
+
+    Ok(())
+}
+
+// Should seed the store from a dump and reject malformed dumps
+#[test]
+fn seed_from_dump() -> Result<()> {
+    let input =
+        "{\"key\":\"key1\",\"value\":\"value1\"}\n{\"key\":\"key2\",\"value\":\"value2\"}\n";
+    let store = MemKvsEngine::seed(input.as_bytes())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    assert!(MemKvsEngine::seed("not a dump\n".as_bytes()).is_err());
+    Ok(())
+}
+
+// Should scan pages of the keys with the prefix in order
+#[test]
+fn scan() -> Result<()> {
+    let store = MemKvsEngine::new();
+    for i in (0..30).rev() {
+        store.set(format!("key{:02}", i), format!("value{}", i))?;
+    }
+    store.set("other".to_owned(), "value".to_owned())?;
+
+    let page = store.scan("key", None, 10)?;
+    assert_eq!(page.len(), 10);
+    assert_eq!(page[0], ("key00".to_owned(), "value0".to_owned()));
+    let page = store.scan("key", Some(&page[9].0), 100)?;
+    let keys: Vec<_> = page.into_iter().map(|(key, _)| key).collect();
+    let expected: Vec<_> = (10..30).map(|i| format!("key{:02}", i)).collect();
+    assert_eq!(keys, expected);
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use kvs::{thread_pool::*, KvsEngine, MemKvsEngine, RespServer, Result};
+use redis::{Commands, Value};
+use sloggers::{null::NullLoggerBuilder, Build};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+// Should map the commands of Redis clients in RESP2 and RESP3 onto the engine
+#[test]
+fn redis_client() -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4017".parse().unwrap();
+    let engine = MemKvsEngine::new();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = RespServer::new(logger, addr, engine.clone(), thread_pool)?;
+    let handle = thread::spawn(move || server.run(Some(2)));
+
+    let client = redis::Client::open("redis://127.0.0.1:4017/").unwrap();
+    let mut con = client.get_connection().unwrap();
+    let pong: String = redis::cmd("PING").query(&mut con).unwrap();
+    assert_eq!(pong, "PONG");
+    let _: () = con.set("key1", "value1").unwrap();
+    let value: Option<String> = con.get("key1").unwrap();
+    assert_eq!(value.as_deref(), Some("value1"));
+    assert_eq!(engine.get("key1".to_owned())?.as_deref(), Some("value1"));
+    let value: Option<String> = con.get("key2").unwrap();
+    assert_eq!(value, None);
+
+    let count: i64 = redis::cmd("INCR").arg("counter").query(&mut con).unwrap();
+    assert_eq!(count, 1);
+    let count: i64 = redis::cmd("INCR").arg("counter").query(&mut con).unwrap();
+    assert_eq!(count, 2);
+    let result: redis::RedisResult<i64> = redis::cmd("INCR").arg("key1").query(&mut con);
+    assert!(result.is_err());
+
+    let _: () = con.set("key2", "value2").unwrap();
+    let _: () = con.set("other", "value").unwrap();
+    let mut keys: Vec<String> = con.keys("key?").unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["key1", "key2"]);
+    let mut keys: Vec<String> = con.keys("[^k]*").unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["counter", "other"]);
+
+    let exists: i64 = con.exists(&["key1", "key2", "key3"]).unwrap();
+    assert_eq!(exists, 2);
+    let removed: i64 = con.del(&["key1", "key3"]).unwrap();
+    assert_eq!(removed, 1);
+    let exists: bool = con.exists("key1").unwrap();
+    assert!(!exists);
+    drop(con);
+
+    // RESP3 is started by HELLO 3
+    let client = redis::Client::open("redis://127.0.0.1:4017/?protocol=resp3").unwrap();
+    let mut con = client.get_connection().unwrap();
+    let value: Value = redis::cmd("GET").arg("key1").query(&mut con).unwrap();
+    assert_eq!(value, Value::Nil);
+    let value: Option<String> = con.get("key2").unwrap();
+    assert_eq!(value.as_deref(), Some("value2"));
+    let hello: Value = redis::cmd("HELLO").arg(3).query(&mut con).unwrap();
+    assert!(matches!(hello, Value::Map(_)));
+    drop(con);
+
+    handle.join().unwrap()
+}
+
+// Should match KEYS patterns in time linear in the pattern for each key, even with many stars
+#[test]
+fn keys_pattern() -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4037".parse().unwrap();
+    let engine = MemKvsEngine::new();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = RespServer::new(logger, addr, engine.clone(), thread_pool)?;
+    let handle = thread::spawn(move || server.run(Some(1)));
+
+    engine.set("a".repeat(100), "value".to_owned())?;
+    engine.set("a*b".to_owned(), "value".to_owned())?;
+    engine.set("a[b]".to_owned(), "value".to_owned())?;
+    let client = redis::Client::open("redis://127.0.0.1:4037/").unwrap();
+    let mut con = client.get_connection().unwrap();
+
+    let start = Instant::now();
+    let keys: Vec<String> = con.keys("*a*a*a*a*a*a*a*a*a*a*a*a*b").unwrap();
+    assert!(keys.is_empty());
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    let keys: Vec<String> = con.keys("a\\**").unwrap();
+    assert_eq!(keys, vec!["a*b"]);
+    let keys: Vec<String> = con.keys("a[[]b?").unwrap();
+    assert_eq!(keys, vec!["a[b]"]);
+    let mut keys: Vec<String> = con.keys("*a*[^a]").unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["a*b", "a[b]"]);
+    drop(con);
+
+    handle.join().unwrap()
+}
+
+// Should serve inline commands, and close the connection after protocol errors
+#[test]
+fn inline_commands() -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4018".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = RespServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    let handle = thread::spawn(move || server.run(Some(1)));
+
+    let mut stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    stream.write_all(b"PING\r\nset key1 value1\r\nGET key1\r\nfoo\r\n")?;
+    for expected in ["+PONG\r\n", "+OK\r\n", "$6\r\n", "value1\r\n"] {
+        line.clear();
+        reader.read_line(&mut line)?;
+        assert_eq!(line, expected);
+    }
+    line.clear();
+    reader.read_line(&mut line)?;
+    assert!(line.starts_with("-ERR unknown command 'foo'"));
+
+    stream.write_all(b"*1\r\n#4\r\nPING\r\n")?;
+    line.clear();
+    reader.read_line(&mut line)?;
+    assert!(line.starts_with("-ERR Protocol error"));
+    line.clear();
+    assert_eq!(reader.read_line(&mut line)?, 0);
+
+    handle.join().unwrap()
+}
+
+// Should serve more open connections than the threads of the pool, and close the connections
+// whose commands are too large or too long
+#[test]
+fn limits() -> Result<()> {
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4036".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = RespServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    server.set_max_request_size(16);
+    let handle = thread::spawn(move || server.run(Some(4)));
+
+    let read_line = |reader: &mut BufReader<TcpStream>| -> Result<String> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        Ok(line)
+    };
+
+    // the idle connection keeps no thread of the pool
+    let idle = TcpStream::connect(addr)?;
+    let mut stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    stream.write_all(b"PING\r\n")?;
+    assert_eq!(read_line(&mut reader)?, "+PONG\r\n");
+
+    stream.write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$20\r\n")?;
+    assert!(read_line(&mut reader)?.starts_with("-ERR Protocol error: invalid bulk length"));
+    assert_eq!(read_line(&mut reader)?, "");
+
+    let mut stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    stream.write_all(b"*2000000\r\n")?;
+    assert!(read_line(&mut reader)?.starts_with("-ERR Protocol error: invalid multibulk length"));
+    assert_eq!(read_line(&mut reader)?, "");
+
+    let mut stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    stream.write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n")?;
+    assert_eq!(read_line(&mut reader)?, "+OK\r\n");
+    drop(idle);
+
+    handle.join().unwrap()
+}
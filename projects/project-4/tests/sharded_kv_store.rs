@@ -0,0 +1,140 @@
+use kvs::{kvs_engine::Event, Error, KvStore, KvsEngine, Result, ShardedKvStore};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use tempfile::TempDir;
+
+// Should spread keys across shards and keep them after reopening
+#[test]
+fn get_stored_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = ShardedKvStore::open_with_shards(temp_dir.path(), 4)?;
+    let users = store.open_namespace("users")?;
+
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    users.set("key1".to_owned(), "value".to_owned())?;
+    store.remove("key0".to_owned())?;
+    assert!(matches!(
+        store.remove("key0".to_owned()),
+        Err(Error::KeyNotFound)
+    ));
+
+    drop((store, users));
+    // every shard should have some keys
+    for n in 0..4 {
+        let shard = KvStore::check(temp_dir.path().join(format!("shard.{}", n)))?;
+        assert!(shard.live > 0);
+    }
+
+    // Open from disk again and check persistent data
+    assert!(ShardedKvStore::open_with_shards(temp_dir.path(), 2).is_err());
+    let store = ShardedKvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key0".to_owned())?, None);
+    for i in 1..100 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+
+    let mut count = 0;
+    store.for_each(|_, _| {
+        count += 1;
+        Ok(())
+    })?;
+    assert_eq!(count, 99);
+    assert_eq!(store.namespaces()?, vec!["".to_owned(), "users".to_owned()]);
+
+    Ok(())
+}
+
+// Should set and load keys from many threads at once
+#[test]
+fn concurrent_set_and_load() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = ShardedKvStore::open_with_shards(temp_dir.path(), 4)?;
+    let barrier = Arc::new(Barrier::new(8));
+
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let store = store.clone();
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                if i % 2 == 0 {
+                    for j in 0..500 {
+                        store
+                            .set(format!("key{}-{}", i, j), format!("value{}", j))
+                            .unwrap();
+                    }
+                } else {
+                    let pairs =
+                        (0..5000).map(|j| Ok((format!("key{}-{}", i, j), format!("value{}", j))));
+                    assert_eq!(store.load(pairs).unwrap(), 5000);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    for i in 0..8 {
+        let n = if i % 2 == 0 { 500 } else { 5000 };
+        for j in 0..n {
+            assert_eq!(
+                store.get(format!("key{}-{}", i, j))?,
+                Some(format!("value{}", j))
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Should watch the keys of all shards, and end the watch when the store is dropped
+#[test]
+fn watch_all_shards() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = ShardedKvStore::open_with_shards(temp_dir.path(), 4)?;
+    let watcher = store.watch("key")?;
+
+    for i in 0..20 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.set("other".to_owned(), "value".to_owned())?;
+    store.remove("key0".to_owned())?;
+    drop(store);
+
+    let events: Vec<Event> = watcher.collect();
+    assert_eq!(events.len(), 21);
+    let mut keys: Vec<_> = events.iter().map(|event| event.key().to_owned()).collect();
+    keys.sort();
+    let mut expected: Vec<_> = (0..20).map(|i| format!("key{}", i)).collect();
+    expected.push("key0".to_owned());
+    expected.sort();
+    assert_eq!(keys, expected);
+    // the events of one key are in order
+    let key0: Vec<_> = events
+        .iter()
+        .filter(|event| event.key() == "key0")
+        .collect();
+    assert!(matches!(key0[..], [Event::Set { .. }, Event::Rm { .. }]));
+
+    Ok(())
+}
+
+// Should end the watch of all shards once the watcher falls behind in any shard
+#[test]
+fn watch_falling_behind() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = ShardedKvStore::open_with_shards(temp_dir.path(), 2)?;
+    let watcher = store.watch("key")?;
+    for i in 0..3000 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let events: Vec<Event> = watcher.collect();
+    assert!(events.len() <= 2048);
+    store.set("key".to_owned(), "value".to_owned())?;
+
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use kvs::{
+    kvs_engine::Event, thread_pool::*, tls, Command, Error, KvsClient, KvsServer, MemKvsEngine,
+    Response, Result,
+};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair,
+};
+use sloggers::{null::NullLoggerBuilder, Build};
+use std::{fs, path::Path, thread};
+use tempfile::TempDir;
+
+// Should encrypt connections with TLS, including pipelines, watches and values larger than records
+#[test]
+fn tls() -> Result<()> {
+    let temp_dir = TempDir::new().unwrap();
+    let (ca, ca_key) = generate_ca(temp_dir.path(), "ca")?;
+    generate_cert(temp_dir.path(), "server", &ca, &ca_key)?;
+
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4025".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(3)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    server.set_tls(tls::server_config(
+        temp_dir.path().join("server.pem"),
+        temp_dir.path().join("server.key"),
+        None,
+    )?);
+    let handle = thread::spawn(move || server.run(Some(3)));
+
+    let config = tls::client_config(Some(&temp_dir.path().join("ca.pem")), None)?;
+    let events =
+        KvsClient::connect_tls(addr, "127.0.0.1", config.clone())?.watch("key".to_owned(), true)?;
+    let mut client = KvsClient::connect_tls(addr, "localhost", config)?;
+    let value = "v".repeat(100 * 1024);
+    let response = client.send(Command::Set {
+        key: "key1".to_owned(),
+        value: value.clone(),
+    })?;
+    assert_eq!(response, Response::SuccessSet());
+    let response = client.send(Command::Get {
+        key: "key1".to_owned(),
+    })?;
+    assert_eq!(response, Response::SuccessGet(Some(value.clone())));
+
+    let mut pipeline = client.pipeline();
+    for i in 0..1000 {
+        pipeline = pipeline.command(Command::Get {
+            key: format!("key{}", i % 2),
+        });
+    }
+    let responses = pipeline.send()?;
+    assert_eq!(responses.len(), 1000);
+    assert!(responses
+        .iter()
+        .step_by(2)
+        .all(|r| *r == Response::SuccessGet(None)));
+    assert!(responses
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .all(|r| *r == Response::SuccessGet(Some(value.clone()))));
+
+    let events: Vec<Event> = events.take(1).collect::<Result<_>>()?;
+    assert_eq!(
+        events,
+        vec![Event::Set {
+            key: "key1".to_owned(),
+            value,
+        }]
+    );
+
+    // plain clients cannot speak to the server
+    assert!(KvsClient::connect(addr).is_err());
+
+    client.close()?;
+    handle.join().unwrap()
+}
+
+// Should accept the clients presenting certificates signed by the client CA, and reject the others
+#[test]
+fn mutual_tls() -> Result<()> {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    let (ca, ca_key) = generate_ca(dir, "ca")?;
+    generate_cert(dir, "server", &ca, &ca_key)?;
+    let (client_ca, client_ca_key) = generate_ca(dir, "client-ca")?;
+    generate_cert(dir, "client", &client_ca, &client_ca_key)?;
+    // signed by the CA of servers instead of the one of clients
+    generate_cert(dir, "stranger", &ca, &ca_key)?;
+
+    let logger = NullLoggerBuilder.build()?;
+    let addr = "127.0.0.1:4026".parse().unwrap();
+    let thread_pool = SharedQueueThreadPool::new(1)?;
+    let mut server = KvsServer::new(logger, addr, MemKvsEngine::new(), thread_pool)?;
+    server.set_tls(tls::server_config(
+        dir.join("server.pem"),
+        dir.join("server.key"),
+        Some(&dir.join("client-ca.pem")),
+    )?);
+    let handle = thread::spawn(move || server.run(Some(3)));
+
+    let ca = dir.join("ca.pem");
+    let config = tls::client_config(Some(&ca), None)?;
+    assert!(matches!(
+        KvsClient::connect_tls(addr, "127.0.0.1", config),
+        Err(Error::IOError(_))
+    ));
+    let stranger = (dir.join("stranger.pem"), dir.join("stranger.key"));
+    let config = tls::client_config(Some(&ca), Some((&stranger.0, &stranger.1)))?;
+    assert!(matches!(
+        KvsClient::connect_tls(addr, "127.0.0.1", config),
+        Err(Error::IOError(_))
+    ));
+
+    let client = (dir.join("client.pem"), dir.join("client.key"));
+    let config = tls::client_config(Some(&ca), Some((&client.0, &client.1)))?;
+    let mut client = KvsClient::connect_tls(addr, "127.0.0.1", config)?;
+    let response = client.send(Command::Set {
+        key: "key1".to_owned(),
+        value: "value1".to_owned(),
+    })?;
+    assert_eq!(response, Response::SuccessSet());
+    client.close()?;
+
+    handle.join().unwrap()
+}
+
+// generates a self-signed CA, and writes its certificate to `{name}.pem`
+fn generate_ca(dir: &Path, name: &str) -> Result<(Certificate, KeyPair)> {
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::default();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name.push(DnType::CommonName, name);
+    let cert = params.self_signed(&key).unwrap();
+    fs::write(dir.join(format!("{}.pem", name)), cert.pem())?;
+    Ok((cert, key))
+}
+
+// generates a certificate for both servers and clients on the local host signed by the CA,
+// and writes it to `{name}.pem` and its private key to `{name}.key`
+fn generate_cert(dir: &Path, name: &str, ca: &Certificate, ca_key: &KeyPair) -> Result<()> {
+    let key = KeyPair::generate().unwrap();
+    let mut params =
+        CertificateParams::new(vec!["localhost".to_owned(), "127.0.0.1".to_owned()]).unwrap();
+    params.distinguished_name.push(DnType::CommonName, name);
+    params.extended_key_usages = vec![
+        ExtendedKeyUsagePurpose::ServerAuth,
+        ExtendedKeyUsagePurpose::ClientAuth,
+    ];
+    let cert = params.signed_by(&key, ca, ca_key).unwrap();
+    fs::write(dir.join(format!("{}.pem", name)), cert.pem())?;
+    fs::write(dir.join(format!("{}.key", name)), key.serialize_pem())?;
+    Ok(())
+}